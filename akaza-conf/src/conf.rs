@@ -9,14 +9,22 @@ use gtk4 as gtk;
 use gtk4::gio::ApplicationFlags;
 use gtk4::Grid;
 use gtk4::{MessageDialog, MessageType};
-use log::{error, info};
+use log::{error, info, warn};
 
-use libakaza::config::{Config, EngineConfig};
+use libakaza::config::{Config, EngineConfig, CONFIG_VERSION};
 
 use crate::pane::{about_pane, core_pane, dict_pane};
 
 pub fn open_configuration_window() -> Result<()> {
-    let config = Arc::new(Mutex::new(Config::load()?));
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            // 設定ファイルが壊れていても画面は開けるように、デフォルト設定で続行する。
+            warn!("Cannot load config, using default configuration: {:#}", err);
+            Config::default_config()
+        }
+    };
+    let config = Arc::new(Mutex::new(config));
     let app = Application::new(Some("com.github.akaza.config"), ApplicationFlags::empty());
 
     app.connect_activate(move |app| {
@@ -61,9 +69,17 @@ fn connect_activate(app: &Application, config: Arc<Mutex<Config>>) -> Result<()>
         // TODO: 保存処理
         let config = config.lock().unwrap();
         let config = Config {
+            version: CONFIG_VERSION,
+            data_dirs: config.data_dirs.clone(),
             keymap: config.keymap.to_string(),
             romkan: config.romkan.to_string(),
+            romkan_mapping: config.romkan_mapping.clone(),
             live_conversion: config.live_conversion,
+            input_style: config.input_style,
+            user_data: config.user_data.clone(),
+            punctuation: config.punctuation,
+            preedit_on_focus_out: config.preedit_on_focus_out,
+            applications: config.applications.clone(),
             engine: EngineConfig {
                 model: config.engine.model.to_string(),
                 dicts: config.engine.dicts.clone(),
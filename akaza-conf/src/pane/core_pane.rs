@@ -1,7 +1,8 @@
 use gtk4::prelude::{CheckButtonExt, ComboBoxExt, GridExt};
 use gtk4::{CheckButton, ComboBoxText, Grid, Label};
 use libakaza::config::Config;
-use log::info;
+use libakaza::resource;
+use log::{info, warn};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
@@ -123,9 +124,10 @@ pub(crate) fn get_list<P>(path: &str, filter: P) -> Vec<PathConfItem>
 where
     P: FnMut(&&PathBuf) -> bool,
 {
-    let p = xdg::BaseDirectories::with_prefix("akaza")
-        .unwrap()
-        .list_data_files(path);
+    let p = resource::list_resource_files(path).unwrap_or_else(|err| {
+        warn!("Cannot list resource files: {}", err);
+        Vec::new()
+    });
 
     p.iter()
         .filter(filter)
@@ -1,4 +1,5 @@
 use std::io::Write;
+use std::path::PathBuf;
 
 use clap::{Parser, Subcommand, ValueEnum};
 
@@ -8,10 +9,13 @@ enum OutputFormat {
     Json,
 }
 
+use libakaza::config::CONFIG_ENV;
 use libakaza::graph::reranking::ReRankingWeights;
+use libakaza::user_side_data::user_data::UserData;
 
 use crate::subcmd::bench::{bench, BenchOptions};
 use crate::subcmd::check::{check, CheckOptions};
+use crate::subcmd::config::check_config;
 use crate::subcmd::convert_skip_bigram_model::convert_skip_bigram_model;
 use crate::subcmd::dump_bigram_dict::dump_bigram_dict;
 use crate::subcmd::dump_unigram_dict::dump_unigram_dict;
@@ -23,6 +27,10 @@ use crate::subcmd::make_stats_system_skip_bigram_lm::make_stats_system_skip_bigr
 use crate::subcmd::make_stats_system_unigram_lm::make_stats_system_unigram_lm;
 use crate::subcmd::tokenize::tokenize;
 use crate::subcmd::tokenize_line::tokenize_line;
+use crate::subcmd::user_data::{
+    delete_user_word, export_user_data, import_user_data, list_user_data, merge_user_data,
+    prune_user_data, search_user_data, StatsKind,
+};
 use crate::subcmd::vocab::vocab;
 use crate::subcmd::wfreq::wfreq;
 
@@ -44,6 +52,10 @@ struct Args {
     #[clap(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
 
+    /// 設定ファイルのパス。環境変数 AKAZA_CONFIG でも指定できる。
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
     #[clap(subcommand)]
     command: Commands,
 }
@@ -80,6 +92,12 @@ enum Commands {
     /// wordcnt skip-bigram trie → skip_bigram.model に変換
     #[clap(arg_required_else_help = true)]
     ConvertSkipBigramModel(ConvertSkipBigramModelArgs),
+
+    #[clap(arg_required_else_help = true)]
+    UserData(UserDataArgs),
+
+    #[clap(arg_required_else_help = true)]
+    Config(ConfigArgs),
 }
 
 /// コーパスを形態素解析機でトーカナイズする
@@ -313,6 +331,119 @@ struct ConvertSkipBigramModelArgs {
     dst: String,
 }
 
+/// ユーザーの学習データを参照・整理する。
+///
+/// ibus-akaza の起動中に変更すると、ibus-akaza 側の保存で上書きされることがある。
+#[derive(Debug, clap::Args)]
+struct UserDataArgs {
+    /// 学習データの格納ディレクトリ（省略時は ~/.local/share/akaza/）
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: UserDataCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum UserDataCommands {
+    List(UserDataListArgs),
+    #[clap(arg_required_else_help = true)]
+    Search(UserDataSearchArgs),
+    #[clap(arg_required_else_help = true)]
+    Delete(UserDataDeleteArgs),
+    #[clap(arg_required_else_help = true)]
+    Prune(UserDataPruneArgs),
+    #[clap(arg_required_else_help = true)]
+    Merge(UserDataMergeArgs),
+    Export(UserDataExportArgs),
+    #[clap(arg_required_else_help = true)]
+    Import(UserDataImportArgs),
+}
+
+/// 出現回数の多い順に学習データを表示する
+#[derive(Debug, clap::Args)]
+struct UserDataListArgs {
+    /// 表示する統計データの種類
+    #[arg(short, long, value_enum, default_value_t = StatsKind::Unigram)]
+    kind: StatsKind,
+    /// 表示する件数
+    #[arg(short, default_value_t = 20)]
+    n: usize,
+}
+
+/// 読みか表記で学習データを検索する
+#[derive(Debug, clap::Args)]
+struct UserDataSearchArgs {
+    query: String,
+}
+
+/// 単語の学習データを削除する（その単語を含む bigram なども削除される）
+#[derive(Debug, clap::Args)]
+struct UserDataDeleteArgs {
+    surface: String,
+    yomi: String,
+}
+
+/// 出現回数の少ない学習データを削除する
+#[derive(Debug, clap::Args)]
+struct UserDataPruneArgs {
+    /// この回数未満のエントリーを削除する
+    #[arg(long)]
+    min_count: u32,
+}
+
+/// 別のディレクトリの学習データを取り込む（出現回数は足し合わせる）
+#[derive(Debug, clap::Args)]
+struct UserDataMergeArgs {
+    /// 取り込む学習データの格納ディレクトリ
+    src_dir: PathBuf,
+}
+
+/// 学習データを JSON で書き出す
+#[derive(Debug, clap::Args)]
+struct UserDataExportArgs {
+    /// 出力先ファイル（省略時は標準出力）
+    dst_file: Option<String>,
+}
+
+/// export した JSON で学習データを置き換える
+#[derive(Debug, clap::Args)]
+struct UserDataImportArgs {
+    src_file: String,
+}
+
+/// 設定ファイルを操作する
+#[derive(Debug, clap::Args)]
+struct ConfigArgs {
+    #[clap(subcommand)]
+    command: ConfigCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommands {
+    Check(ConfigCheckArgs),
+}
+
+/// 設定ファイルと、そこから参照しているキーマップ、ローマ字テーブル、モデル、辞書に問題がないか調べる
+#[derive(Debug, clap::Args)]
+struct ConfigCheckArgs {}
+
+fn user_data(opt: UserDataArgs) -> anyhow::Result<()> {
+    let data_dir = match opt.data_dir {
+        Some(data_dir) => data_dir,
+        None => UserData::default_data_dir()?,
+    };
+    match opt.command {
+        UserDataCommands::List(opt) => list_user_data(&data_dir, opt.kind, opt.n),
+        UserDataCommands::Search(opt) => search_user_data(&data_dir, &opt.query),
+        UserDataCommands::Delete(opt) => delete_user_word(&data_dir, &opt.surface, &opt.yomi),
+        UserDataCommands::Prune(opt) => prune_user_data(&data_dir, opt.min_count),
+        UserDataCommands::Merge(opt) => merge_user_data(&data_dir, &opt.src_dir),
+        UserDataCommands::Export(opt) => export_user_data(&data_dir, opt.dst_file.as_deref()),
+        UserDataCommands::Import(opt) => import_user_data(&data_dir, &opt.src_file),
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
@@ -332,6 +463,10 @@ fn main() -> anyhow::Result<()> {
         })
         .init();
 
+    if let Some(config) = &args.config {
+        std::env::set_var(CONFIG_ENV, config);
+    }
+
     match args.command {
         Commands::Tokenize(opt) => tokenize(
             opt.reader,
@@ -433,5 +568,9 @@ fn main() -> anyhow::Result<()> {
             opt.dst_unigram_model.as_str(),
             opt.dst.as_str(),
         ),
+        Commands::UserData(opt) => user_data(opt),
+        Commands::Config(opt) => match opt.command {
+            ConfigCommands::Check(_) => check_config(),
+        },
     }
 }
@@ -91,7 +91,8 @@ pub fn check(opts: CheckOptions) -> anyhow::Result<()> {
     if opts.use_user_data {
         info!("Enabled user data");
         match UserData::load_from_default_path() {
-            Ok(ud) => {
+            Ok(mut ud) => {
+                ud.set_limits(config.user_data.clone());
                 builder.user_data(Arc::new(Mutex::new(ud)));
             }
             Err(err) => {
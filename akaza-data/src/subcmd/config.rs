@@ -0,0 +1,342 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::bail;
+use encoding_rs::{EUC_JP, UTF_8};
+use serde_yaml::Value;
+
+use libakaza::config::{migrate, Config, DictConfig, DictEncoding, DictUsage, CONFIG_VERSION};
+use libakaza::dict::skk::read::parse_skkdict;
+use libakaza::engine::bigram_word_viterbi_engine::REQUIRED_MODEL_FILES;
+use libakaza::keymap::{Keymap, COMMANDS, INPUT_MODES};
+use libakaza::resource;
+use libakaza::romkan::RomKanConverter;
+
+/// 見つかった問題を表示しながら数える。
+#[derive(Default)]
+struct Diagnostics {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Diagnostics {
+    fn ok(&self, message: String) {
+        println!("[OK]    {message}");
+    }
+
+    fn warn(&mut self, message: String) {
+        self.warnings += 1;
+        println!("[WARN]  {message}");
+    }
+
+    fn error(&mut self, message: String) {
+        self.errors += 1;
+        println!("[ERROR] {message}");
+    }
+}
+
+/// 設定ファイルと、そこから参照しているファイルをすべて読み込んで、問題がないか調べる。
+pub fn check_config() -> anyhow::Result<()> {
+    let mut diag = Diagnostics::default();
+
+    let mut files = Config::system_file_names()?;
+    files.push(Config::file_name()?);
+    for file in files.iter().filter(|file| file.exists()) {
+        check_config_file(file, &mut diag);
+    }
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            diag.error(format!("Cannot load configuration: {err:#}"));
+            bail!("Found {} error(s)", diag.errors);
+        }
+    };
+
+    for dir in &config.data_dirs {
+        if !Path::new(dir).is_dir() {
+            diag.warn(format!("data_dirs: {dir} is not a directory"));
+        }
+    }
+    match resource::data_dirs() {
+        Ok(dirs) => {
+            for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+                diag.ok(format!("data dir: {}", dir.display()));
+            }
+        }
+        Err(err) => diag.error(format!("data dirs: {err:#}")),
+    }
+
+    match RomKanConverter::with_overlay(&config.romkan, &config.romkan_mapping) {
+        Ok(_) => diag.ok(format!("romkan: {}", config.romkan)),
+        Err(err) => diag.error(format!("romkan: {}: {err:#}", config.romkan)),
+    }
+
+    check_keymap(&config.keymap, &mut diag);
+    for app in &config.applications {
+        if let Some(err) = app.client.error() {
+            diag.error(format!(
+                "applications: invalid client pattern {:?}: {err}",
+                app.client.as_str()
+            ));
+        }
+        if let Some(keymap) = &app.keymap {
+            check_keymap(keymap, &mut diag);
+        }
+    }
+
+    check_model(&config.engine.model, &mut diag);
+
+    for dict in &config.engine.dicts {
+        check_dict(dict, &mut diag);
+    }
+
+    println!("{} error(s), {} warning(s)", diag.errors, diag.warnings);
+    if diag.errors > 0 {
+        bail!("Found {} error(s)", diag.errors);
+    }
+    Ok(())
+}
+
+/// 一つの設定ファイルを読み込んで、書式の誤りや知らないキーがないか調べる。
+fn check_config_file(path: &Path, diag: &mut Diagnostics) {
+    let mut value: Value = match File::open(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| Ok(serde_yaml::from_reader(BufReader::new(file))?))
+    {
+        Ok(value) => value,
+        Err(err) => {
+            diag.error(format!("{}: {err:#}", path.display()));
+            return;
+        }
+    };
+    if value.is_null() {
+        diag.ok(format!("{} (empty)", path.display()));
+        return;
+    }
+
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if let Err(err) = migrate(&mut value) {
+        diag.error(format!("{}: {err:#}", path.display()));
+        return;
+    }
+    if version < CONFIG_VERSION {
+        diag.warn(format!(
+            "{}: old config format (version {version}). It is migrated to version {CONFIG_VERSION} on load, and saved in the new format by akaza-conf.",
+            path.display()
+        ));
+    } else if version > CONFIG_VERSION {
+        diag.warn(format!(
+            "{}: config version {version} is newer than this akaza (version {CONFIG_VERSION})",
+            path.display()
+        ));
+    }
+
+    // 読み込んだ設定を書き出してみて、書き出されなかったキーは知らないキーとみなす。
+    let known = match serde_yaml::from_value::<Config>(value.clone())
+        .map_err(anyhow::Error::from)
+        .and_then(|config| Ok(serde_yaml::to_value(config)?))
+    {
+        Ok(known) => known,
+        Err(err) => {
+            diag.error(format!("{}: {err:#}", path.display()));
+            return;
+        }
+    };
+    let mut unknown = Vec::new();
+    find_unknown_keys(&value, &known, "", &mut unknown);
+    if unknown.is_empty() {
+        diag.ok(path.display().to_string());
+    }
+    for key in unknown {
+        diag.warn(format!("{}: unknown key '{key}'", path.display()));
+    }
+}
+
+fn find_unknown_keys(value: &Value, known: &Value, path: &str, unknown: &mut Vec<String>) {
+    match (value, known) {
+        (Value::Mapping(value), Value::Mapping(known)) => {
+            for (key, child) in value {
+                let name = match key {
+                    Value::String(key) => key.clone(),
+                    _ => format!("{key:?}"),
+                };
+                let child_path = if path.is_empty() {
+                    name
+                } else {
+                    format!("{path}.{name}")
+                };
+                match known.get(key) {
+                    Some(known_child) => {
+                        find_unknown_keys(child, known_child, &child_path, unknown)
+                    }
+                    // null や空のリストは書き出されないことがあるので、知らないキーとはみなさない。
+                    None if is_empty_value(child) => {}
+                    None => unknown.push(child_path),
+                }
+            }
+        }
+        (Value::Sequence(value), Value::Sequence(known)) => {
+            for (idx, (child, known_child)) in value.iter().zip(known).enumerate() {
+                find_unknown_keys(child, known_child, &format!("{path}[{idx}]"), unknown);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_empty_value(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Sequence(seq) => seq.is_empty(),
+        _ => false,
+    }
+}
+
+/// キーマップを読み込んで、知らないコマンドや不正なキーがないか調べる。
+fn check_keymap(path: &str, diag: &mut Diagnostics) {
+    let keymap = match Keymap::load(path) {
+        Ok(keymap) => keymap,
+        Err(err) => {
+            diag.error(format!("keymap: {path}: {err:#}"));
+            return;
+        }
+    };
+
+    let mut errors = Vec::new();
+    for (pattern, commands) in &keymap {
+        for command in commands {
+            if !COMMANDS.contains(&command.name.as_str()) {
+                errors.push(format!(
+                    "keymap: {path}: unknown command '{}' for key '{pattern}'",
+                    command.name
+                ));
+            }
+        }
+        if !pattern
+            .strokes
+            .iter()
+            .all(|stroke| Keymap::is_valid_key_name(&stroke.key))
+        {
+            errors.push(format!("keymap: {path}: invalid key '{pattern}'"));
+        }
+        for mode in &pattern.modes {
+            if !INPUT_MODES.contains(&mode.as_str()) {
+                errors.push(format!(
+                    "keymap: {path}: unknown input mode '{mode}' for key '{pattern}'"
+                ));
+            }
+        }
+    }
+    if errors.is_empty() {
+        diag.ok(format!("keymap: {path} ({} bindings)", keymap.len()));
+    }
+    // HashMap の順序は不定なので、並べてから表示する。
+    errors.sort();
+    for error in errors {
+        diag.error(error);
+    }
+}
+
+/// モデルのディレクトリに必要なファイルが揃っているか調べる。
+fn check_model(model_dir: &str, diag: &mut Diagnostics) {
+    if !Path::new(model_dir).is_dir() {
+        diag.error(format!(
+            "model: {model_dir} is not a directory. akaza converts with dictionaries only."
+        ));
+        return;
+    }
+
+    let mut missing = false;
+    for name in REQUIRED_MODEL_FILES {
+        let path = Path::new(model_dir).join(name);
+        if !path.is_file() {
+            diag.error(format!(
+                "model: missing {}. akaza converts with dictionaries only.",
+                path.display()
+            ));
+            missing = true;
+        }
+    }
+    if !Path::new(model_dir).join("skip_bigram.model").is_file() {
+        diag.warn(format!(
+            "model: {model_dir}/skip_bigram.model is missing (optional)"
+        ));
+    }
+    if !missing {
+        diag.ok(format!("model: {model_dir}"));
+    }
+}
+
+/// 辞書ファイルが読めて、指定された文字コードでデコードできるか調べる。
+fn check_dict(dict: &DictConfig, diag: &mut Diagnostics) {
+    if dict.usage == DictUsage::Disabled {
+        diag.ok(format!("dict: {} (disabled)", dict.path));
+        return;
+    }
+
+    let buf = match std::fs::read(&dict.path) {
+        Ok(buf) => buf,
+        Err(err) => {
+            diag.error(format!("dict: {}: {err}", dict.path));
+            return;
+        }
+    };
+    let encoding = match dict.encoding {
+        DictEncoding::EucJp => EUC_JP,
+        DictEncoding::Utf8 => UTF_8,
+    };
+    let (decoded, _, had_errors) = encoding.decode(&buf);
+    if had_errors {
+        diag.error(format!(
+            "dict: {}: cannot decode as {}. Is the encoding correct?",
+            dict.path, dict.encoding
+        ));
+        return;
+    }
+    match parse_skkdict(&decoded) {
+        Ok(entries) => diag.ok(format!(
+            "dict: {} ({}, {} entries)",
+            dict.path,
+            dict.encoding,
+            entries.len()
+        )),
+        Err(err) => diag.error(format!("dict: {}: {err:#}", dict.path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_unknown_keys() -> anyhow::Result<()> {
+        let value: Value = serde_yaml::from_str(
+            r#"
+live_convertion: true
+punctuation: Western
+romkan: /tmp/romkan.yml
+keymap: /tmp/keymap.yml
+engine:
+  model: /tmp/model
+  dicts:
+    - path: /tmp/SKK-JISYO.L
+      encodng: euc-jp
+applications: []
+"#,
+        )?;
+        let known = serde_yaml::to_value(serde_yaml::from_value::<Config>(value.clone())?)?;
+
+        let mut unknown = Vec::new();
+        find_unknown_keys(&value, &known, "", &mut unknown);
+        assert_eq!(
+            unknown,
+            vec![
+                "live_convertion".to_string(),
+                "engine.dicts[0].encodng".to_string()
+            ]
+        );
+        Ok(())
+    }
+}
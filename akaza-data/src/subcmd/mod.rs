@@ -1,5 +1,6 @@
 pub mod bench;
 pub mod check;
+pub mod config;
 pub mod convert_skip_bigram_model;
 pub mod dump_bigram_dict;
 pub mod dump_unigram_dict;
@@ -11,5 +12,6 @@ pub mod make_stats_system_skip_bigram_lm;
 pub mod make_stats_system_unigram_lm;
 pub mod tokenize;
 pub mod tokenize_line;
+pub mod user_data;
 pub mod vocab;
 pub mod wfreq;
@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Context;
+use clap::ValueEnum;
+use log::info;
+
+use libakaza::config::Config;
+use libakaza::user_side_data::user_data::{UserData, UserDataSnapshot};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum StatsKind {
+    Unigram,
+    Bigram,
+    SkipBigram,
+}
+
+impl StatsKind {
+    fn table(self, snapshot: &UserDataSnapshot) -> &BTreeMap<String, u32> {
+        match self {
+            StatsKind::Unigram => &snapshot.unigram,
+            StatsKind::Bigram => &snapshot.bigram,
+            StatsKind::SkipBigram => &snapshot.skip_bigram,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            StatsKind::Unigram => "unigram",
+            StatsKind::Bigram => "bigram",
+            StatsKind::SkipBigram => "skip_bigram",
+        }
+    }
+}
+
+/// 書き戻すときに設定ファイルの上限が適用されるように、設定を反映して読み込む。
+fn load_user_data(data_dir: &Path) -> anyhow::Result<UserData> {
+    let mut user_data = UserData::load_from_dir(data_dir);
+    user_data.set_limits(Config::load()?.user_data);
+    Ok(user_data)
+}
+
+/// 出現回数の多い順に n 件表示する。
+pub fn list_user_data(data_dir: &Path, kind: StatsKind, n: usize) -> anyhow::Result<()> {
+    let snapshot = UserData::load_from_dir(data_dir).snapshot();
+    let mut entries = kind.table(&snapshot).iter().collect::<Vec<_>>();
+    entries.sort_by(|(k1, c1), (k2, c2)| c2.cmp(c1).then(k1.cmp(k2)));
+    for (key, count) in entries.into_iter().take(n) {
+        println!("{count}\t{key}");
+    }
+    Ok(())
+}
+
+/// 読みか表記に query を含むエントリーを表示する。
+pub fn search_user_data(data_dir: &Path, query: &str) -> anyhow::Result<()> {
+    let snapshot = UserData::load_from_dir(data_dir).snapshot();
+    for kind in [StatsKind::Unigram, StatsKind::Bigram, StatsKind::SkipBigram] {
+        for (key, count) in kind.table(&snapshot) {
+            // キーは "表記/読み" を tab で繋いだもの。
+            let matched = key
+                .split('\t')
+                .filter_map(|word| word.split_once('/'))
+                .any(|(surface, yomi)| surface.contains(query) || yomi.contains(query));
+            if matched {
+                println!("{}\t{}\t{}", kind.name(), count, key);
+            }
+        }
+    }
+    for (yomi, surfaces) in &snapshot.dict {
+        for surface in surfaces {
+            if surface.contains(query) || yomi.contains(query) {
+                println!("dict\t{yomi}\t{surface}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 単語の学習結果を削除する。
+pub fn delete_user_word(data_dir: &Path, surface: &str, yomi: &str) -> anyhow::Result<()> {
+    let mut user_data = load_user_data(data_dir)?;
+    let removed = user_data.remove_word(surface, yomi);
+    user_data.compact()?;
+    println!("Removed {removed} entries");
+    Ok(())
+}
+
+/// 別のディレクトリの学習データを取り込む。
+pub fn merge_user_data(data_dir: &Path, src_dir: &Path) -> anyhow::Result<()> {
+    anyhow::ensure!(src_dir.is_dir(), "{} is not a directory", src_dir.display());
+
+    let mut user_data = load_user_data(data_dir)?;
+    user_data.merge(&UserData::load_from_dir(src_dir));
+    user_data.compact()?;
+    info!("Merged user data from {}", src_dir.display());
+    Ok(())
+}
+
+/// 出現回数が min_count 未満のエントリーを削除する。
+pub fn prune_user_data(data_dir: &Path, min_count: u32) -> anyhow::Result<()> {
+    let mut user_data = load_user_data(data_dir)?;
+    let removed = user_data.prune(min_count);
+    user_data.compact()?;
+    println!("Removed {removed} entries");
+    Ok(())
+}
+
+/// 学習データをまとめて JSON で書き出す。ファイルを指定しなければ標準出力に書く。
+pub fn export_user_data(data_dir: &Path, dst_file: Option<&str>) -> anyhow::Result<()> {
+    let snapshot = UserData::load_from_dir(data_dir).snapshot();
+    let mut writer: Box<dyn Write> = match dst_file {
+        Some(dst_file) => Box::new(BufWriter::new(
+            File::create(dst_file).with_context(|| format!("Cannot create {dst_file}"))?,
+        )),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    serde_json::to_writer_pretty(&mut writer, &snapshot)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// export した JSON で学習データを置き換える。
+pub fn import_user_data(data_dir: &Path, src_file: &str) -> anyhow::Result<()> {
+    let file = File::open(src_file).with_context(|| format!("Cannot open {src_file}"))?;
+    let snapshot: UserDataSnapshot = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Cannot parse {src_file}"))?;
+
+    let mut user_data = load_user_data(data_dir)?;
+    user_data.restore(snapshot);
+    user_data.compact()?;
+    info!("Imported user data from {src_file}");
+    Ok(())
+}
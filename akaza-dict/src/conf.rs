@@ -14,14 +14,22 @@ use gtk4::MessageDialog;
 use gtk4::{Application, ApplicationWindow, Button, ListStore};
 
 use gtk4::{CellRendererText, Grid, MessageType, TreeView, TreeViewColumn};
-use log::{info, trace};
+use log::{info, trace, warn};
 
 use libakaza::config::Config;
 use libakaza::dict::skk::read::read_skkdict;
 use libakaza::dict::skk::write::write_skk_dict;
 
 pub fn open_userdict_window(user_dict_path: &str) -> Result<()> {
-    let config = Arc::new(Mutex::new(Config::load()?));
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            // 設定ファイルが壊れていても画面は開けるように、デフォルト設定で続行する。
+            warn!("Cannot load config, using default configuration: {:#}", err);
+            Config::default_config()
+        }
+    };
+    let config = Arc::new(Mutex::new(config));
     let app = Application::new(Some("com.github.akaza.dict"), ApplicationFlags::empty());
 
     let user_dict_path = user_dict_path.to_string();
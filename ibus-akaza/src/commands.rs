@@ -6,14 +6,14 @@ use log::error;
 
 use crate::context::AkazaContext;
 use crate::input_mode::{
-    INPUT_MODE_ALNUM, INPUT_MODE_FULLWIDTH_ALNUM, INPUT_MODE_HALFWIDTH_KATAKANA,
-    INPUT_MODE_HIRAGANA, INPUT_MODE_KATAKANA,
+    get_input_mode_from_name, INPUT_MODE_ALNUM, INPUT_MODE_FULLWIDTH_ALNUM,
+    INPUT_MODE_HALFWIDTH_KATAKANA, INPUT_MODE_HIRAGANA, INPUT_MODE_KATAKANA,
 };
 
 /**
  * shortcut key を設定可能な機能。
  */
-pub type IbusAkazaCommand = fn(&mut AkazaContext, *mut IBusEngine) -> bool;
+pub type IbusAkazaCommand = fn(&mut AkazaContext, *mut IBusEngine, &[String]) -> bool;
 
 pub fn ibus_akaza_commands_map() -> HashMap<&'static str, IbusAkazaCommand> {
     let mut function_map: HashMap<&'static str, IbusAkazaCommand> = HashMap::new();
@@ -21,112 +21,118 @@ pub fn ibus_akaza_commands_map() -> HashMap<&'static str, IbusAkazaCommand> {
     // shorthand
     let mut register = |name: &'static str, cmd: IbusAkazaCommand| function_map.insert(name, cmd);
 
-    register("commit_candidate", |context, engine| {
+    register("commit_candidate", |context, engine, _| {
         context.commit_candidate(engine);
         true
     });
+    // 選択中の文節までを確定し、残りは変換し直します
+    register("partial_commit", |context, engine, _| {
+        context.partial_commit(engine)
+    });
     // 無変換状態では、ひらがなに変換してコミットします
-    register("commit_preedit", |context, engine| {
+    register("commit_preedit", |context, engine, _| {
         context.commit_preedit(engine);
         true
     });
-    register("escape", |context, engine| {
+    register("escape", |context, engine, _| {
         context.escape(engine);
         true
     });
-    register("page_up", |context, engine| {
+    register("page_up", |context, engine, _| {
         context.page_up(engine);
         true
     });
-    register("page_down", |context, engine| {
+    register("page_down", |context, engine, _| {
         context.page_down(engine);
         true
     });
 
-    register("set_input_mode_hiragana", |context, engine| {
+    register("set_input_mode_hiragana", |context, engine, _| {
         context.set_input_mode(engine, &INPUT_MODE_HIRAGANA);
         true
     });
-    register("set_input_mode_alnum", |context, engine| {
+    register("set_input_mode_alnum", |context, engine, _| {
         context.set_input_mode(engine, &INPUT_MODE_ALNUM);
         true
     });
-    register("set_input_mode_fullwidth_alnum", |context, engine| {
+    register("set_input_mode_fullwidth_alnum", |context, engine, _| {
         context.set_input_mode(engine, &INPUT_MODE_FULLWIDTH_ALNUM);
         true
     });
-    register("set_input_mode_katakana", |context, engine| {
+    register("set_input_mode_katakana", |context, engine, _| {
         context.set_input_mode(engine, &INPUT_MODE_KATAKANA);
         true
     });
-    register("set_input_mode_halfwidth_katakana", |context, engine| {
+    register("set_input_mode_halfwidth_katakana", |context, engine, _| {
         context.set_input_mode(engine, &INPUT_MODE_HALFWIDTH_KATAKANA);
         true
     });
 
-    register("update_candidates", |context, engine| {
+    register("update_candidates", |context, engine, _| {
         context.update_candidates(engine)
     });
-    register("erase_character_before_cursor", |context, engine| {
+    register("erase_character_before_cursor", |context, engine, _| {
         context.erase_character_before_cursor(engine);
         true
     });
-    register("cursor_up", |context, engine| context.cursor_up(engine));
-    register("cursor_down", |context, engine| context.cursor_down(engine));
-    register("cursor_right", |context, engine| {
+    register("cursor_up", |context, engine, _| context.cursor_up(engine));
+    register("cursor_down", |context, engine, _| {
+        context.cursor_down(engine)
+    });
+    register("cursor_right", |context, engine, _| {
         context.cursor_right(engine);
         true
     });
-    register("cursor_left", |context, engine| {
+    register("cursor_left", |context, engine, _| {
         context.cursor_left(engine);
         true
     });
-    register("extend_clause_right", |context, engine| {
+    register("extend_clause_right", |context, engine, _| {
         if let Err(e) = context.extend_clause_right(engine) {
             error!("extend_clause_right failed: {}", e);
             return false;
         }
         true
     });
-    register("extend_clause_left", |context, engine| {
+    register("extend_clause_left", |context, engine, _| {
         if let Err(e) = context.extend_clause_left(engine) {
             error!("extend_clause_left failed: {}", e);
             return false;
         }
         true
     });
-    register("cycle_segmentation", |context, engine| {
+    register("cycle_segmentation", |context, engine, _| {
         context.cycle_segmentation(engine)
     });
-    register("convert_to_full_hiragana", |context, engine| {
+    register("convert_to_full_hiragana", |context, engine, _| {
         if let Err(e) = context.convert_to_full_hiragana(engine) {
             error!("convert_to_full_hiragana failed: {}", e);
             return false;
         }
         true
     });
-    register("convert_to_full_katakana", |context, engine| {
+    register("convert_to_full_katakana", |context, engine, _| {
         if let Err(e) = context.convert_to_full_katakana(engine) {
             error!("convert_to_full_katakana failed: {}", e);
             return false;
         }
         true
     });
-    register("convert_to_half_katakana", |context, engine| {
+    register("convert_to_half_katakana", |context, engine, _| {
         if let Err(e) = context.convert_to_half_katakana(engine) {
             error!("convert_to_half_katakana failed: {}", e);
             return false;
         }
         true
     });
-    register("convert_to_full_romaji", |context, engine| {
+    register("convert_to_full_romaji", |context, engine, _| {
         if let Err(e) = context.convert_to_full_romaji(engine) {
             error!("convert_to_full_romaji failed: {}", e);
             return false;
         }
         true
     });
-    register("convert_to_half_romaji", |context, engine| {
+    register("convert_to_half_romaji", |context, engine, _| {
         if let Err(e) = context.convert_to_half_romaji(engine) {
             error!("convert_to_half_romaji failed: {}", e);
             return false;
@@ -134,39 +140,40 @@ pub fn ibus_akaza_commands_map() -> HashMap<&'static str, IbusAkazaCommand> {
         true
     });
 
-    {
-        // TODO コピペがすごい。マクロかうまいなにかでまとめて登録できるようにしたい。
-        register("press_number_1", |context, engine| {
-            context.process_num_key(1, engine)
-        });
-        register("press_number_2", |context, engine| {
-            context.process_num_key(2, engine)
-        });
-        register("press_number_3", |context, engine| {
-            context.process_num_key(3, engine)
-        });
-        register("press_number_4", |context, engine| {
-            context.process_num_key(4, engine)
-        });
-        register("press_number_5", |context, engine| {
-            context.process_num_key(5, engine)
-        });
-        register("press_number_6", |context, engine| {
-            context.process_num_key(6, engine)
-        });
-        register("press_number_7", |context, engine| {
-            context.process_num_key(7, engine)
-        });
-        register("press_number_8", |context, engine| {
-            context.process_num_key(8, engine)
-        });
-        register("press_number_9", |context, engine| {
-            context.process_num_key(9, engine)
-        });
-        register("press_number_0", |context, engine| {
-            context.process_num_key(0, engine)
-        });
-    }
+    // ページ内の番号で候補を選ぶ。args: [1] から [9] と、10 番目の [0]。
+    register("select_candidate", |context, engine, args| {
+        let Some(idx) = candidate_index_in_page(args) else {
+            error!("select_candidate requires a number argument: {:?}", args);
+            return false;
+        };
+        context.process_num_key(idx, engine)
+    });
+    // args: [Katakana] のように入力モードの名前を渡す。
+    register("set_input_mode", |context, engine, args| {
+        let [name] = args else {
+            error!("set_input_mode requires an input mode argument: {:?}", args);
+            return false;
+        };
+        match get_input_mode_from_name(name) {
+            Ok(input_mode) => {
+                context.set_input_mode(engine, &input_mode);
+                true
+            }
+            Err(e) => {
+                error!("set_input_mode failed: {}", e);
+                false
+            }
+        }
+    });
 
     function_map
 }
+
+/// select_candidate の引数から、ページ内の何番目(0 始まり)の候補かを返す。[0] は 10 番目。
+pub fn candidate_index_in_page(args: &[String]) -> Option<i32> {
+    let [arg] = args else {
+        return None;
+    };
+    let nn = arg.parse::<i32>().ok().filter(|nn| (0..=9).contains(nn))?;
+    Some(if nn == 0 { 9 } else { nn - 1 })
+}
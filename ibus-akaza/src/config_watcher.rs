@@ -0,0 +1,380 @@
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::Result;
+use inotify::{Events, Inotify, WatchDescriptor, WatchMask};
+use log::{info, warn};
+
+use libakaza::config::{Config, DictUsage};
+use libakaza::engine::bigram_word_viterbi_engine::REQUIRED_MODEL_FILES;
+
+/// 設定ファイルなどが変更された時に、作り直すべきもの。
+#[derive(Debug, PartialEq)]
+pub struct ReloadRequest {
+    pub config: Config,
+    pub keymap: bool,
+    pub romkan: bool,
+    pub engine: bool,
+}
+
+impl ReloadRequest {
+    /// 変更前後の設定と変更されたファイルから、作り直すべきものを判定する。
+    /// 何も変わっていなければ None を返す。
+    fn new(old: &Config, new: Config, changed: &HashSet<PathBuf>) -> Option<ReloadRequest> {
+        let keymap = old.keymap != new.keymap
+            || changed.contains(Path::new(&new.keymap))
+            || new
+                .applications
+                .iter()
+                .filter_map(|app| app.keymap.as_ref())
+                .any(|keymap| changed.contains(Path::new(keymap)));
+        let romkan = old.romkan != new.romkan
+            || old.romkan_mapping != new.romkan_mapping
+            || changed.contains(Path::new(&new.romkan));
+        let engine = old.engine != new.engine
+            || new
+                .engine
+                .dicts
+                .iter()
+                .any(|dict| changed.contains(Path::new(&dict.path)))
+            || model_files(&new).any(|path| changed.contains(&path));
+        if !keymap && !romkan && !engine && *old == new {
+            return None;
+        }
+        Some(ReloadRequest {
+            config: new,
+            keymap,
+            romkan,
+            engine,
+        })
+    }
+
+    /// まだ反映していないリクエストに、新しいリクエストをまとめる。
+    pub fn merge(self, newer: ReloadRequest) -> ReloadRequest {
+        ReloadRequest {
+            config: newer.config,
+            keymap: self.keymap || newer.keymap,
+            romkan: self.romkan || newer.romkan,
+            engine: self.engine || newer.engine,
+        }
+    }
+}
+
+/// 設定ファイル、キーマップ、ローマ字かな変換テーブル、辞書ファイルを監視するスレッドを起動する。
+///
+/// エンジンなどはスレッドをまたげないので、ここでは変更を検知するだけで、
+/// 実際の作り直しは受け取った側(メインスレッド)で行う。
+pub fn spawn_config_watcher(
+    config: Config,
+    debounce: Duration,
+) -> Result<(Receiver<ReloadRequest>, JoinHandle<()>)> {
+    // 後ろのファイルほど優先される。
+    let mut config_paths = Config::system_file_names()?;
+    config_paths.push(Config::file_name()?);
+    let (tx, rx) = channel();
+    let handle = thread::Builder::new()
+        .name("config-watch-thread".to_string())
+        .spawn(move || {
+            if let Err(err) = watch_config(&config_paths, config, debounce, &tx) {
+                warn!("Stopped watching config files: {}", err);
+            }
+        })?;
+    Ok((rx, handle))
+}
+
+fn watch_config(
+    config_paths: &[PathBuf],
+    mut config: Config,
+    debounce: Duration,
+    tx: &Sender<ReloadRequest>,
+) -> Result<()> {
+    // 監視は作り直さずに使い続ける。作り直すと、その間の変更を取りこぼしてしまう。
+    let mut watcher = FileWatcher::new()?;
+    loop {
+        watcher.set_targets(watch_targets(config_paths, &config));
+        let changed = watcher.wait_for_changes(debounce)?;
+        info!("Detected changes: {:?}", changed);
+
+        let new_config = if config_paths.iter().any(|it| changed.contains(it)) {
+            match Config::load_from_path(config_paths.last().unwrap()) {
+                Ok(new_config) => new_config,
+                Err(err) => {
+                    // 書きかけのファイルなどを読んでしまった場合は、今の設定のままにしておく。
+                    warn!("Cannot reload config files: {:#}", err);
+                    continue;
+                }
+            }
+        } else {
+            config.clone()
+        };
+
+        if let Some(request) = ReloadRequest::new(&config, new_config.clone(), &changed) {
+            info!(
+                "Request reloading: keymap={}, romkan={}, engine={}",
+                request.keymap, request.romkan, request.engine
+            );
+            if tx.send(request).is_err() {
+                // 受け取る側がいなくなった。
+                return Ok(());
+            }
+        }
+        config = new_config;
+    }
+}
+
+/// 監視するファイルの一覧。
+fn watch_targets(config_paths: &[PathBuf], config: &Config) -> Vec<PathBuf> {
+    let mut targets = config_paths.to_vec();
+    targets.push(PathBuf::from(&config.keymap));
+    targets.push(PathBuf::from(&config.romkan));
+    targets.extend(
+        config
+            .applications
+            .iter()
+            .filter_map(|app| app.keymap.as_ref())
+            .map(PathBuf::from),
+    );
+    targets.extend(
+        config
+            .engine
+            .dicts
+            .iter()
+            .filter(|dict| dict.usage != DictUsage::Disabled && !dict.path.is_empty())
+            .map(|dict| PathBuf::from(&dict.path)),
+    );
+    targets.extend(model_files(config));
+    targets
+}
+
+/// モデルのファイル。モデルが作り直されたりインストールされた時に読み込み直す。
+fn model_files(config: &Config) -> impl Iterator<Item = PathBuf> + '_ {
+    REQUIRED_MODEL_FILES
+        .iter()
+        .map(|name| Path::new(&config.engine.model).join(name))
+}
+
+/// ファイルの変更を監視する。
+///
+/// エディタなどは別のファイルに書いてから rename することが多く、その場合ファイル自体の監視は外れてしまう。
+/// なので、ファイルの置かれているディレクトリを監視する。
+struct FileWatcher {
+    inotify: Inotify,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    targets: Vec<PathBuf>,
+}
+
+impl FileWatcher {
+    fn new() -> Result<FileWatcher> {
+        Ok(FileWatcher {
+            inotify: Inotify::init()?,
+            dirs: HashMap::new(),
+            targets: Vec::new(),
+        })
+    }
+
+    /// 監視するファイルを変える。ディレクトリの監視は、増えたものと減ったものだけ付け外しする。
+    fn set_targets(&mut self, targets: Vec<PathBuf>) {
+        if self.targets == targets {
+            return;
+        }
+        let new_dirs = targets
+            .iter()
+            .filter_map(|target| target.parent())
+            .map(Path::to_path_buf)
+            .collect::<HashSet<_>>();
+
+        let removed = self
+            .dirs
+            .iter()
+            .filter(|(_, dir)| !new_dirs.contains(*dir))
+            .map(|(wd, _)| wd.clone())
+            .collect::<Vec<_>>();
+        for wd in removed {
+            if let Some(dir) = self.dirs.remove(&wd) {
+                if let Err(err) = self.inotify.watches().remove(wd) {
+                    // ディレクトリが消された場合などは、すでに外れている。
+                    info!("Cannot unwatch {}: {}", dir.display(), err);
+                }
+            }
+        }
+
+        for dir in new_dirs {
+            if self.dirs.values().any(|it| *it == dir) {
+                continue;
+            }
+            match self.inotify.watches().add(
+                &dir,
+                WatchMask::CLOSE_WRITE
+                    | WatchMask::MOVED_TO
+                    | WatchMask::MOVED_FROM
+                    | WatchMask::CREATE
+                    | WatchMask::DELETE,
+            ) {
+                Ok(wd) => {
+                    self.dirs.insert(wd, dir);
+                }
+                Err(err) => warn!("Cannot watch {}: {}", dir.display(), err),
+            }
+        }
+        self.targets = targets;
+    }
+
+    /// targets のいずれかが変更されるまで待ち、変更されたファイルを返す。
+    fn wait_for_changes(&mut self, debounce: Duration) -> Result<HashSet<PathBuf>> {
+        let mut buffer = [0; 4096];
+        let mut changed: HashSet<PathBuf> = HashSet::new();
+        while changed.is_empty() {
+            let events = self.inotify.read_events_blocking(&mut buffer)?;
+            collect_changes(events, &self.dirs, &self.targets, &mut changed);
+        }
+
+        // 保存処理では書き込みと rename が立て続けに起きるので、落ち着くまで待ってからまとめて返す。
+        thread::sleep(debounce);
+        loop {
+            match self.inotify.read_events(&mut buffer) {
+                Ok(events) => collect_changes(events, &self.dirs, &self.targets, &mut changed),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(changed)
+    }
+}
+
+fn collect_changes(
+    events: Events,
+    dirs: &HashMap<WatchDescriptor, PathBuf>,
+    targets: &[PathBuf],
+    changed: &mut HashSet<PathBuf>,
+) {
+    for event in events {
+        let (Some(dir), Some(name)) = (dirs.get(&event.wd), event.name) else {
+            continue;
+        };
+        let path = dir.join(name);
+        if targets.contains(&path) {
+            changed.insert(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use libakaza::config::{DictConfig, EngineConfig};
+
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            keymap: "/tmp/keymap.yml".to_string(),
+            romkan: "/tmp/romkan.yml".to_string(),
+            engine: EngineConfig {
+                dicts: vec![DictConfig {
+                    path: "/tmp/SKK-JISYO.user".to_string(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_reload_request_for_changed_files() {
+        let changed = HashSet::from([PathBuf::from("/tmp/romkan.yml")]);
+        assert_eq!(
+            ReloadRequest::new(&config(), config(), &changed),
+            Some(ReloadRequest {
+                config: config(),
+                keymap: false,
+                romkan: true,
+                engine: false,
+            })
+        );
+
+        let changed = HashSet::from([PathBuf::from("/tmp/SKK-JISYO.user")]);
+        let request = ReloadRequest::new(&config(), config(), &changed).unwrap();
+        assert!(request.engine);
+
+        let mut with_model = config();
+        with_model.engine.model = "/tmp/model".to_string();
+        let changed = HashSet::from([PathBuf::from("/tmp/model/unigram.model")]);
+        let request = ReloadRequest::new(&with_model, with_model.clone(), &changed).unwrap();
+        assert!(request.engine);
+
+        assert_eq!(
+            ReloadRequest::new(&config(), config(), &HashSet::new()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_reload_request_for_changed_config() {
+        let mut new_config = config();
+        new_config.live_conversion = true;
+        let request = ReloadRequest::new(&config(), new_config, &HashSet::new()).unwrap();
+        assert!(request.config.live_conversion);
+        assert!(!request.keymap && !request.romkan && !request.engine);
+
+        let mut new_config = config();
+        new_config.keymap = "/tmp/atok.yml".to_string();
+        let request = ReloadRequest::new(&config(), new_config, &HashSet::new()).unwrap();
+        assert!(request.keymap);
+    }
+
+    #[test]
+    fn test_wait_for_changes() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let target = tmpdir.path().join("config.yml");
+        let other = tmpdir.path().join("other.yml");
+
+        let mut watcher = FileWatcher::new()?;
+        watcher.set_targets(vec![target.clone()]);
+        let handle = thread::spawn(move || watcher.wait_for_changes(Duration::from_millis(100)));
+
+        // 監視が始まるのを待つ
+        thread::sleep(Duration::from_millis(200));
+        fs::write(&other, "")?;
+        // 別名で書いてから rename する
+        fs::write(
+            tmpdir.path().join("config.yml.tmp"),
+            "live_conversion: true",
+        )?;
+        fs::rename(tmpdir.path().join("config.yml.tmp"), &target)?;
+
+        assert_eq!(handle.join().unwrap()?, HashSet::from([target]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_changes_between_waits() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let first = tmpdir.path().join("config.yml");
+        let second = tmpdir.path().join("keymap.yml");
+
+        let mut watcher = FileWatcher::new()?;
+        watcher.set_targets(vec![first.clone(), second.clone()]);
+        fs::write(&first, "")?;
+        assert_eq!(
+            watcher.wait_for_changes(Duration::from_millis(10))?,
+            HashSet::from([first.clone()])
+        );
+
+        // 次に待ち始める前の変更も取りこぼさない
+        fs::write(&second, "")?;
+        watcher.set_targets(vec![first, second.clone()]);
+        assert_eq!(
+            watcher.wait_for_changes(Duration::from_millis(10))?,
+            HashSet::from([second])
+        );
+        Ok(())
+    }
+}
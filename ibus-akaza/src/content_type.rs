@@ -0,0 +1,117 @@
+use ibus_sys::engine::{
+    IBusInputHints_IBUS_INPUT_HINT_NO_SPELLCHECK, IBusInputHints_IBUS_INPUT_HINT_PRIVATE,
+    IBusInputHints_IBUS_INPUT_HINT_WORD_COMPLETION, IBusInputPurpose_IBUS_INPUT_PURPOSE_DIGITS,
+    IBusInputPurpose_IBUS_INPUT_PURPOSE_EMAIL, IBusInputPurpose_IBUS_INPUT_PURPOSE_NUMBER,
+    IBusInputPurpose_IBUS_INPUT_PURPOSE_PASSWORD, IBusInputPurpose_IBUS_INPUT_PURPOSE_PHONE,
+    IBusInputPurpose_IBUS_INPUT_PURPOSE_PIN, IBusInputPurpose_IBUS_INPUT_PURPOSE_URL,
+};
+use ibus_sys::glib::guint;
+
+/// 入力欄の種類(IBus の input purpose と hints)に応じた振る舞い。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContentType {
+    /// かな漢字変換をせず、キー入力をそのままアプリケーションに渡す。
+    pub passthrough: bool,
+    /// 英数モードで入力を始める。
+    pub alphanumeric: bool,
+    /// 確定した内容を学習する。
+    pub learning: bool,
+    /// 入力中に変換候補を表示する(サジェスト)。
+    pub prediction: bool,
+}
+
+impl Default for ContentType {
+    fn default() -> Self {
+        ContentType {
+            passthrough: false,
+            alphanumeric: false,
+            learning: true,
+            prediction: true,
+        }
+    }
+}
+
+impl ContentType {
+    pub fn new(purpose: guint, hints: guint) -> ContentType {
+        // パスワードや PIN を変換したり学習したりしてはいけない。
+        let secret = matches!(
+            purpose,
+            IBusInputPurpose_IBUS_INPUT_PURPOSE_PASSWORD | IBusInputPurpose_IBUS_INPUT_PURPOSE_PIN
+        );
+        let alphanumeric = matches!(
+            purpose,
+            IBusInputPurpose_IBUS_INPUT_PURPOSE_DIGITS
+                | IBusInputPurpose_IBUS_INPUT_PURPOSE_NUMBER
+                | IBusInputPurpose_IBUS_INPUT_PURPOSE_PHONE
+                | IBusInputPurpose_IBUS_INPUT_PURPOSE_URL
+                | IBusInputPurpose_IBUS_INPUT_PURPOSE_EMAIL
+        );
+        // IBus には予測入力を止めるヒントがないので、スペルチェックも補完も要らない欄では止める。
+        let no_prediction = hints & IBusInputHints_IBUS_INPUT_HINT_NO_SPELLCHECK != 0
+            && hints & IBusInputHints_IBUS_INPUT_HINT_WORD_COMPLETION == 0;
+
+        ContentType {
+            passthrough: secret,
+            alphanumeric,
+            learning: !secret && hints & IBusInputHints_IBUS_INPUT_HINT_PRIVATE == 0,
+            prediction: !secret && !no_prediction,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ibus_sys::engine::{
+        IBusInputHints_IBUS_INPUT_HINT_NONE, IBusInputPurpose_IBUS_INPUT_PURPOSE_FREE_FORM,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_free_form() {
+        assert_eq!(
+            ContentType::new(
+                IBusInputPurpose_IBUS_INPUT_PURPOSE_FREE_FORM,
+                IBusInputHints_IBUS_INPUT_HINT_NONE
+            ),
+            ContentType::default()
+        );
+    }
+
+    #[test]
+    fn test_password() {
+        let content_type = ContentType::new(
+            IBusInputPurpose_IBUS_INPUT_PURPOSE_PASSWORD,
+            IBusInputHints_IBUS_INPUT_HINT_NONE,
+        );
+        assert!(content_type.passthrough);
+        assert!(!content_type.learning);
+    }
+
+    #[test]
+    fn test_email() {
+        let content_type = ContentType::new(
+            IBusInputPurpose_IBUS_INPUT_PURPOSE_EMAIL,
+            IBusInputHints_IBUS_INPUT_HINT_NONE,
+        );
+        assert!(content_type.alphanumeric);
+        assert!(!content_type.passthrough);
+    }
+
+    #[test]
+    fn test_hints() {
+        let content_type = ContentType::new(
+            IBusInputPurpose_IBUS_INPUT_PURPOSE_FREE_FORM,
+            IBusInputHints_IBUS_INPUT_HINT_PRIVATE | IBusInputHints_IBUS_INPUT_HINT_NO_SPELLCHECK,
+        );
+        assert!(!content_type.learning);
+        assert!(!content_type.prediction);
+
+        let content_type = ContentType::new(
+            IBusInputPurpose_IBUS_INPUT_PURPOSE_FREE_FORM,
+            IBusInputHints_IBUS_INPUT_HINT_NO_SPELLCHECK
+                | IBusInputHints_IBUS_INPUT_HINT_WORD_COMPLETION,
+        );
+        assert!(content_type.prediction);
+    }
+}
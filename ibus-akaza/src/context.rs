@@ -1,44 +1,55 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 use std::process::Command;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use kelp::{h2z, hira2kata, z2h, ConvOption};
 use log::{error, info, trace, warn};
 
 use ibus_sys::core::{
-    IBusModifierType_IBUS_CONTROL_MASK, IBusModifierType_IBUS_HYPER_MASK,
-    IBusModifierType_IBUS_META_MASK, IBusModifierType_IBUS_MOD1_MASK,
-    IBusModifierType_IBUS_MOD2_MASK, IBusModifierType_IBUS_MOD3_MASK,
-    IBusModifierType_IBUS_MOD4_MASK, IBusModifierType_IBUS_MOD5_MASK,
+    IBusModifierType_IBUS_CONTROL_MASK, IBusModifierType_IBUS_MOD1_MASK,
     IBusModifierType_IBUS_RELEASE_MASK, IBusModifierType_IBUS_SHIFT_MASK,
 };
-use ibus_sys::engine::ibus_engine_commit_text;
 use ibus_sys::engine::IBusEngine;
-use ibus_sys::glib::guint;
+use ibus_sys::engine::{ibus_engine_commit_text, ibus_engine_forward_key_event};
+use ibus_sys::glib::{g_source_remove, g_timeout_add, gboolean, gpointer, guint};
 use ibus_sys::property::IBusPropState_PROP_STATE_CHECKED;
 use ibus_sys::text::StringExt;
-use libakaza::config::Config;
+use libakaza::config::{Config, InputStyle, PreeditPolicy};
 use libakaza::engine::base::HenkanEngine;
-use libakaza::engine::bigram_word_viterbi_engine::BigramWordViterbiEngine;
+use libakaza::engine::bigram_word_viterbi_engine::{
+    BigramWordViterbiEngine, BigramWordViterbiEngineBuilder, REQUIRED_MODEL_FILES,
+};
 use libakaza::graph::candidate::Candidate;
 use libakaza::kana_kanji::marisa_kana_kanji_dict::MarisaKanaKanjiDict;
-use libakaza::keymap::Keymap;
+use libakaza::keymap::{KeyCommand, KeyState, Keymap};
 use libakaza::lm::system_bigram::MarisaSystemBigramLM;
 use libakaza::lm::system_unigram_lm::MarisaSystemUnigramLM;
-use libakaza::romkan::RomKanConverter;
+use libakaza::romkan::{RomKanConverter, RomKanRule};
 
 use crate::commands::{ibus_akaza_commands_map, IbusAkazaCommand};
+use crate::config_watcher::ReloadRequest;
+use crate::content_type::ContentType;
 use crate::current_state::CurrentState;
-use crate::input_mode::get_input_mode_from_prop_name;
 use crate::input_mode::InputMode;
-use crate::input_mode::INPUT_MODE_HIRAGANA;
-use crate::keymap::IBusKeyMap;
+use crate::input_mode::{get_input_mode_from_name, get_input_mode_from_prop_name};
+use crate::input_mode::{INPUT_MODE_ALNUM, INPUT_MODE_HIRAGANA};
+use crate::keymap::{IBusKeyMap, KeySequence, PendingKey, SEQUENCE_TIMEOUT};
+use crate::thumb_shift::{ThumbShift, ThumbShiftKey, ThumbShiftOutput};
 use crate::ui::prop_controller::PropController;
 
+/// 辞書だけで変換している時に、モデルがインストールされたかを確認する間隔。
+const MODEL_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
 #[repr(C)]
 pub struct AkazaContext {
     // ==== 設定 ====
+    config: Config,
     keymap: IBusKeyMap,
+    /// 今読み込んでいるキーマップのパス
+    keymap_path: String,
     command_map: HashMap<&'static str, IbusAkazaCommand>,
 
     // ==== 現在の入力状態を保持 ====
@@ -46,6 +57,44 @@ pub struct AkazaContext {
 
     // ==== UI 関連 ====
     prop_controller: PropController,
+
+    // ==== 設定の再読み込み ====
+    reload_receiver: Option<Receiver<ReloadRequest>>,
+    /// 入力中だったので、まだ反映していない再読み込み
+    pending_reload: Option<ReloadRequest>,
+    /// 最後にモデルがインストールされたかを確認した時刻
+    last_model_retry: Option<Instant>,
+
+    // ==== アプリケーションごとの状態 ====
+    /// フォーカスしているアプリケーションの IBus クライアント名
+    current_client: Option<String>,
+    /// アプリケーションごとに最後に使っていた入力モード
+    input_mode_memory: HashMap<String, InputMode>,
+
+    // ==== 入力欄の種類 ====
+    content_type: ContentType,
+    /// 入力欄の種類に合わせて英数モードにする前の入力モード
+    input_mode_before_content_type: Option<InputMode>,
+
+    // ==== 親指シフト ====
+    thumb_shift: ThumbShift,
+
+    // ==== キーの組み合わせ ====
+    /// 組み合わせの途中まで押されたキーを、時間が経ったら入力する GLib のタイマー
+    sequence_timeout: Option<SequenceTimeout>,
+}
+
+struct SequenceTimeout {
+    source_id: guint,
+    engine: *mut IBusEngine,
+}
+
+/// 組み合わせの途中で次のキーが押されないまま時間が経ったら、押されたキーを通常の入力にする。
+unsafe extern "C" fn on_sequence_timeout(context: gpointer) -> gboolean {
+    let context_ref = &mut *(context as *mut AkazaContext);
+    context_ref.flush_pending_sequence();
+    // 一度だけ呼ばれればいいので、タイマーを止める。
+    0
 }
 
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
@@ -60,15 +109,231 @@ impl AkazaContext {
         config: Config,
     ) -> Result<Self> {
         let input_mode = INPUT_MODE_HIRAGANA;
-        let romkan = RomKanConverter::new(config.romkan.as_str())?;
+        let romkan = load_romkan(&config.romkan, &config.romkan_mapping)?;
         let keymap = Keymap::load(config.keymap.as_str())?;
 
-        Ok(AkazaContext {
+        let mut context = AkazaContext {
             current_state: CurrentState::new(input_mode, config.live_conversion, romkan, engine),
             command_map: ibus_akaza_commands_map(),
             keymap: IBusKeyMap::new(keymap)?,
-            prop_controller: PropController::new(input_mode, config)?,
-        })
+            keymap_path: config.keymap.clone(),
+            prop_controller: PropController::new(input_mode, config.clone())?,
+            config,
+            reload_receiver: None,
+            pending_reload: None,
+            last_model_retry: None,
+            current_client: None,
+            input_mode_memory: HashMap::new(),
+            content_type: ContentType::default(),
+            input_mode_before_content_type: None,
+            thumb_shift: ThumbShift::default(),
+            sequence_timeout: None,
+        };
+        context.apply_application_settings();
+        Ok(context)
+    }
+
+    /// フォーカスしたアプリケーションに合わせて、設定と入力モードを切り替える。
+    fn switch_application(&mut self, engine: *mut IBusEngine, client: Option<String>) {
+        info!(
+            "Switch application: {:?} -> {:?}",
+            self.current_client, client
+        );
+
+        // 直前のアプリケーションで使っていた入力モードを覚えておく。
+        // 入力欄の種類に合わせて切り替えたモードは覚えない。
+        if let Some(prev) = self.current_client.take() {
+            let input_mode = self
+                .input_mode_before_content_type
+                .take()
+                .unwrap_or(self.current_state.input_mode);
+            self.input_mode_memory.insert(prev, input_mode);
+        }
+        self.current_client = client;
+        self.apply_application_settings();
+
+        let Some(client) = &self.current_client else {
+            return;
+        };
+        let input_mode = match self.input_mode_memory.get(client) {
+            Some(input_mode) => Some(*input_mode),
+            None => self
+                .config
+                .application_config(client)
+                .and_then(|app| app.input_mode.as_ref())
+                .and_then(|name| match get_input_mode_from_name(name) {
+                    Ok(input_mode) => Some(input_mode),
+                    Err(err) => {
+                        warn!("Invalid input_mode for {}: {}", client, err);
+                        None
+                    }
+                }),
+        };
+        if let Some(input_mode) = input_mode {
+            if input_mode != self.current_state.input_mode {
+                self.set_input_mode(engine, &input_mode);
+            }
+        }
+    }
+
+    /// メニューから変更したライブ変換、ローマ字テーブル、キーマップを、入力中の状態に反映する。
+    /// 設定ファイルには書き込まないので、再起動すると元に戻る。
+    /// 入力途中の文字列は、切り替える前に今の設定のまま確定しておくこと。
+    fn apply_conversion_settings(&mut self, engine: *mut IBusEngine) {
+        self.apply_application_settings();
+        self.sync_props(engine);
+    }
+
+    /// メニューのチェック状態を、実際に使っている設定に合わせる。
+    fn sync_props(&self, engine: *mut IBusEngine) {
+        self.prop_controller.set_conversion_settings(
+            self.current_state.live_conversion,
+            &self.config.romkan,
+            &self.keymap_path,
+            engine,
+        );
+        self.prop_controller
+            .set_model_error(self.current_state.engine.model_error(), engine);
+    }
+
+    /// フォーカスしているアプリケーションの設定(ライブ変換、キーマップ、句読点)を反映する。
+    /// アプリケーションごとの設定で指定されていない項目は、全体の設定に従う。
+    fn apply_application_settings(&mut self) {
+        let app = self
+            .current_client
+            .as_deref()
+            .and_then(|client| self.config.application_config(client));
+
+        // SKK 風の入力では、変換範囲を自分で指定するのでライブ変換は使わない。
+        let skk_style = self.config.input_style == InputStyle::Skk;
+        self.current_state.skk_style = skk_style;
+        self.current_state.live_conversion = !skk_style
+            && app
+                .and_then(|app| app.live_conversion)
+                .unwrap_or(self.config.live_conversion);
+        self.current_state.set_punctuation(
+            app.and_then(|app| app.punctuation)
+                .unwrap_or(self.config.punctuation),
+        );
+
+        let keymap_path = app
+            .and_then(|app| app.keymap.clone())
+            .unwrap_or_else(|| self.config.keymap.clone());
+        if keymap_path != self.keymap_path {
+            match Keymap::load(keymap_path.as_str()).and_then(IBusKeyMap::new) {
+                Ok(keymap) => {
+                    self.keymap = keymap;
+                    self.keymap_path = keymap_path;
+                }
+                Err(err) => error!("Cannot load keymap {}: {}", keymap_path, err),
+            }
+        }
+    }
+
+    /// 設定ファイルなどの変更の通知を受け取る。
+    pub fn set_reload_receiver(&mut self, receiver: Receiver<ReloadRequest>) {
+        self.reload_receiver = Some(receiver);
+    }
+
+    /// 設定ファイルなどが変更されていたら、影響のある部分を作り直す。
+    /// 入力途中で変換テーブルなどが変わると混乱するので、何も入力していない時にだけ反映する。
+    fn apply_pending_reload(&mut self, engine: *mut IBusEngine) {
+        if let Some(receiver) = &self.reload_receiver {
+            while let Ok(request) = receiver.try_recv() {
+                self.pending_reload = Some(match self.pending_reload.take() {
+                    Some(pending) => pending.merge(request),
+                    None => request,
+                });
+            }
+        }
+        if !self.current_state.get_raw_input().is_empty() {
+            return;
+        }
+        if let Some(request) = self.pending_reload.take() {
+            self.reload(engine, request);
+        }
+    }
+
+    /// 辞書だけで変換している時は、モデルがインストールされたかを時々確認し、見つかれば読み込み直す。
+    fn retry_model_load(&mut self, engine: *mut IBusEngine) {
+        if self.current_state.engine.model_error().is_none()
+            || !self.current_state.get_raw_input().is_empty()
+            || self
+                .last_model_retry
+                .is_some_and(|it| it.elapsed() < MODEL_RETRY_INTERVAL)
+        {
+            return;
+        }
+        self.last_model_retry = Some(Instant::now());
+
+        // デフォルトのモデルの場所は、インストールされたパッケージによって変わるので探し直す。
+        let model = match Config::load() {
+            Ok(config) => config.engine.model,
+            Err(err) => {
+                warn!("Cannot load config: {:#}", err);
+                return;
+            }
+        };
+        if !REQUIRED_MODEL_FILES
+            .iter()
+            .all(|name| Path::new(&model).join(name).is_file())
+        {
+            return;
+        }
+
+        info!("Found model: {}. Reloading engine.", model);
+        let mut config = self.config.clone();
+        config.engine.model = model;
+        self.reload(
+            engine,
+            ReloadRequest {
+                config,
+                keymap: false,
+                romkan: false,
+                engine: true,
+            },
+        );
+    }
+
+    fn reload(&mut self, engine: *mut IBusEngine, request: ReloadRequest) {
+        let config = request.config;
+        info!("Reloading configuration: {:?}", config);
+
+        if request.romkan {
+            match load_romkan(&config.romkan, &config.romkan_mapping) {
+                Ok(romkan) => self.current_state.set_romkan(romkan),
+                Err(err) => error!("Cannot reload romkan table {}: {}", config.romkan, err),
+            }
+        }
+
+        if request.keymap {
+            // apply_application_settings で読み直させる。
+            self.keymap_path.clear();
+        }
+
+        if request.engine {
+            let user_data = self.current_state.engine.user_data.clone();
+            match BigramWordViterbiEngineBuilder::new(config.engine.clone())
+                .user_data(user_data)
+                .build()
+            {
+                Ok(henkan_engine) => self.current_state.engine = henkan_engine,
+                Err(err) => error!("Cannot reload engine: {}", err),
+            }
+
+            // ユーザー辞書のメニューを作り直す
+            match PropController::new(self.current_state.input_mode, config.clone()) {
+                Ok(prop_controller) => {
+                    self.prop_controller = prop_controller;
+                    self.prop_controller.do_focus_in(engine);
+                }
+                Err(err) => error!("Cannot rebuild properties: {}", err),
+            }
+        }
+
+        self.config = config;
+        self.apply_application_settings();
+        self.sync_props(engine);
     }
 
     /// Set props
@@ -109,6 +374,35 @@ impl AkazaContext {
             && prop_name.starts_with("InputMode.")
         {
             self.input_mode_activate(engine, prop_name, prop_state);
+        } else if prop_name == "LiveConversion" {
+            self.finish_preedit(engine, PreeditPolicy::Commit);
+            self.config.live_conversion = prop_state == IBusPropState_PROP_STATE_CHECKED;
+            info!("Live conversion: {}", self.config.live_conversion);
+            self.apply_conversion_settings(engine);
+        } else if prop_state == IBusPropState_PROP_STATE_CHECKED && prop_name.starts_with("Romkan.")
+        {
+            let Some(path) = self.prop_controller.romkan_path(&prop_name).cloned() else {
+                warn!("Unknown romkan prop_name: {}", prop_name);
+                return;
+            };
+            self.finish_preedit(engine, PreeditPolicy::Commit);
+            match load_romkan(&path, &self.config.romkan_mapping) {
+                Ok(romkan) => {
+                    self.current_state.set_romkan(romkan);
+                    self.config.romkan = path;
+                }
+                Err(err) => error!("Cannot load romkan table {}: {}", path, err),
+            }
+            self.apply_conversion_settings(engine);
+        } else if prop_state == IBusPropState_PROP_STATE_CHECKED && prop_name.starts_with("Keymap.")
+        {
+            let Some(path) = self.prop_controller.keymap_path(&prop_name).cloned() else {
+                warn!("Unknown keymap prop_name: {}", prop_name);
+                return;
+            };
+            self.finish_preedit(engine, PreeditPolicy::Commit);
+            self.config.keymap = path;
+            self.apply_conversion_settings(engine);
         } else if prop_name.starts_with("UserDict.") {
             info!("Starting UserDict. operation");
             let Some(dict_path) = self.prop_controller.user_dict_path(&prop_name) else {
@@ -147,9 +441,8 @@ impl AkazaContext {
 
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 impl AkazaContext {
-    pub(crate) fn process_num_key(&mut self, nn: i32, engine: *mut IBusEngine) -> bool {
-        let idx = if nn == 0 { 9 } else { nn - 1 };
-
+    /// ページ内の idx 番目(0 始まり)の候補を選ぶ。
+    pub(crate) fn process_num_key(&mut self, idx: i32, engine: *mut IBusEngine) -> bool {
         if self.current_state.lookup_table_visible {
             self.set_lookup_table_cursor_pos_in_current_page(engine, idx)
         } else {
@@ -196,6 +489,128 @@ impl AkazaContext {
 
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 impl AkazaContext {
+    /// 変換の途中に別の文字が入力されたら、現在の preedit 文字列は確定させる。
+    /// （サジェスト中はまだ Composition なので確定しない）
+    fn commit_before_input(&mut self, engine: *mut IBusEngine) {
+        if self.current_state.lookup_table_visible
+            && !self.current_state.suggest_active
+            && !self.current_state.suggest_candidate_selected
+        {
+            self.commit_candidate(engine);
+        }
+    }
+
+    /// キーコードで入力した文字列を preedit に追加する。
+    fn insert_keycode_input(&mut self, engine: *mut IBusEngine, keycode: guint, input: &str) {
+        trace!("Insert keycode input to preedit: {}: '{}'", keycode, input);
+        self.commit_before_input(engine);
+        for ch in input.chars() {
+            self.current_state.append_raw_input(engine, ch);
+        }
+    }
+
+    /// 親指シフト入力のテーブルなら、文字キーと親指キーの同時押しを判定して入力する。
+    /// 親指シフトで扱わないキーなら None を返す。
+    fn process_thumb_shift(
+        &mut self,
+        engine: *mut IBusEngine,
+        keyval: guint,
+        keycode: guint,
+        modifiers: guint,
+    ) -> Option<bool> {
+        if !matches!(
+            self.current_state.input_mode.prop_name,
+            "InputMode.Hiragana" | "InputMode.Katakana" | "InputMode.HalfWidthKatakana"
+        ) {
+            return None;
+        }
+        let config = self.current_state.romkan().thumb_shift()?.clone();
+        let release = modifiers & IBusModifierType_IBUS_RELEASE_MASK != 0;
+
+        let key = match config.thumb(keycode) {
+            Some(thumb) => ThumbShiftKey::Thumb {
+                thumb,
+                keyval,
+                keycode,
+            },
+            None if self
+                .current_state
+                .romkan()
+                .keycode_input(keycode, false)
+                .is_some() =>
+            {
+                ThumbShiftKey::Char(keycode)
+            }
+            None => {
+                // 親指シフトに関係のないキーが押されたら、待っている文字キーを先に入力する。
+                if !release {
+                    let outputs = self.thumb_shift.flush_char();
+                    self.apply_thumb_shift_outputs(engine, outputs);
+                }
+                return None;
+            }
+        };
+
+        if release {
+            let outputs = self.thumb_shift.release(key);
+            self.apply_thumb_shift_outputs(engine, outputs);
+            return Some(false);
+        }
+        // シフトやコントロールと一緒に押されたキーは、通常のキー入力として扱う。
+        if modifiers
+            & (IBusModifierType_IBUS_CONTROL_MASK
+                | IBusModifierType_IBUS_MOD1_MASK
+                | IBusModifierType_IBUS_SHIFT_MASK)
+            != 0
+        {
+            let outputs = self.thumb_shift.flush_char();
+            self.apply_thumb_shift_outputs(engine, outputs);
+            return None;
+        }
+        let outputs = self.thumb_shift.press(
+            key,
+            Instant::now(),
+            Duration::from_millis(config.timeout_ms),
+        );
+        self.apply_thumb_shift_outputs(engine, outputs);
+        Some(true)
+    }
+
+    fn apply_thumb_shift_outputs(
+        &mut self,
+        engine: *mut IBusEngine,
+        outputs: Vec<ThumbShiftOutput>,
+    ) {
+        for output in outputs {
+            match output {
+                ThumbShiftOutput::Char { keycode, thumb } => {
+                    let romkan = self.current_state.romkan();
+                    let input = match thumb {
+                        Some(thumb) => romkan.thumb_shift_input(keycode, thumb),
+                        None => romkan.keycode_input(keycode, false),
+                    };
+                    if let Some(input) = input.map(|input| input.to_string()) {
+                        self.insert_keycode_input(engine, keycode, &input);
+                    }
+                }
+                ThumbShiftOutput::ThumbAlone { keyval, keycode } => {
+                    // 親指キー本来の動作をする。akaza で使わないキーはアプリケーションに渡す。
+                    if !self.process_key_press(engine, keyval, keycode, 0) {
+                        unsafe {
+                            ibus_engine_forward_key_event(engine, keyval, keycode, 0);
+                            ibus_engine_forward_key_event(
+                                engine,
+                                keyval,
+                                keycode,
+                                IBusModifierType_IBUS_RELEASE_MASK,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     pub fn process_key_event(
         &mut self,
         engine: *mut IBusEngine,
@@ -210,32 +625,128 @@ impl AkazaContext {
             modifiers
         );
 
+        // パスワード欄などでは、何もせずにアプリケーションに渡す。
+        if self.content_type.passthrough {
+            return false;
+        }
+        // 親指シフトの同時押しの判定には、キーを離したイベントも使う。
+        if let Some(handled) = self.process_thumb_shift(engine, keyval, keycode, modifiers) {
+            return handled;
+        }
         // ignore key release event
         if modifiers & IBusModifierType_IBUS_RELEASE_MASK != 0 {
             return false;
         }
+        self.process_key_press(engine, keyval, keycode, modifiers)
+    }
+
+    /// キーが押された時の処理。
+    fn process_key_press(
+        &mut self,
+        engine: *mut IBusEngine,
+        keyval: guint,
+        keycode: guint,
+        modifiers: guint,
+    ) -> bool {
+        self.apply_pending_reload(engine);
         let key_state = self.current_state.get_key_state();
 
         trace!("KeyState={:?}", key_state);
-        if let Some(callback) = self
+        let (replay, sequence) = self.keymap.feed_sequence(
+            &key_state,
+            &self.current_state.input_mode,
+            PendingKey {
+                keyval,
+                keycode,
+                modifiers,
+            },
+            Instant::now(),
+        );
+        if sequence == KeySequence::Pending {
+            self.start_sequence_timeout(engine);
+        } else {
+            self.cancel_sequence_timeout();
+        }
+        self.replay_keys(engine, replay);
+        match sequence {
+            KeySequence::Command(commands) => {
+                self.run_commands(engine, &commands);
+                return true;
+            }
+            KeySequence::Pending => return true,
+            KeySequence::NotSequence => {}
+        }
+
+        self.process_single_key(engine, keyval, keycode, modifiers)
+    }
+
+    /// 組み合わせにならなかったキーは、押された順に通常のキー入力として扱う。
+    fn replay_keys(&mut self, engine: *mut IBusEngine, keys: Vec<PendingKey>) {
+        for key in keys {
+            if !self.process_single_key(engine, key.keyval, key.keycode, key.modifiers) {
+                unsafe {
+                    ibus_engine_forward_key_event(engine, key.keyval, key.keycode, key.modifiers);
+                    ibus_engine_forward_key_event(
+                        engine,
+                        key.keyval,
+                        key.keycode,
+                        key.modifiers | IBusModifierType_IBUS_RELEASE_MASK,
+                    );
+                }
+            }
+        }
+    }
+
+    /// 組み合わせの途中まで押されたキーを、時間が経ったら入力するようにする。
+    /// 次のキーが押されるたびに、時間を計りなおす。
+    fn start_sequence_timeout(&mut self, engine: *mut IBusEngine) {
+        self.cancel_sequence_timeout();
+        let source_id = unsafe {
+            g_timeout_add(
+                SEQUENCE_TIMEOUT.as_millis() as guint,
+                Some(on_sequence_timeout),
+                self as *mut Self as gpointer,
+            )
+        };
+        self.sequence_timeout = Some(SequenceTimeout { source_id, engine });
+    }
+
+    fn cancel_sequence_timeout(&mut self) {
+        if let Some(timeout) = self.sequence_timeout.take() {
+            unsafe { g_source_remove(timeout.source_id) };
+        }
+    }
+
+    /// タイマーから呼ばれる。タイマーはこの後止まるので、g_source_remove しない。
+    fn flush_pending_sequence(&mut self) {
+        let Some(timeout) = self.sequence_timeout.take() else {
+            return;
+        };
+        let keys = self.keymap.take_pending();
+        info!("Key sequence timed out: {:?}", keys);
+        self.replay_keys(timeout.engine, keys);
+    }
+
+    /// キーの組み合わせではない、一つのキーの入力を処理する。
+    fn process_single_key(
+        &mut self,
+        engine: *mut IBusEngine,
+        keyval: guint,
+        keycode: guint,
+        modifiers: guint,
+    ) -> bool {
+        let key_state = self.current_state.get_key_state();
+        if let Some(commands) = self
             .keymap
             .get(
                 &key_state,
+                &self.current_state.input_mode,
                 keyval,
-                modifiers
-                    & (IBusModifierType_IBUS_CONTROL_MASK
-                        | IBusModifierType_IBUS_SHIFT_MASK
-                        | IBusModifierType_IBUS_META_MASK
-                        | IBusModifierType_IBUS_HYPER_MASK
-                        | IBusModifierType_IBUS_MOD1_MASK
-                        | IBusModifierType_IBUS_MOD2_MASK
-                        | IBusModifierType_IBUS_MOD3_MASK
-                        | IBusModifierType_IBUS_MOD4_MASK
-                        | IBusModifierType_IBUS_MOD5_MASK),
+                modifiers,
             )
             .cloned()
         {
-            if self.run_callback_by_name(engine, callback.as_str()) {
+            if self.run_commands(engine, &commands) {
                 return true;
             }
         }
@@ -249,21 +760,38 @@ impl AkazaContext {
                     return false;
                 }
 
+                // かな入力のテーブルでは、キーの文字ではなくキーコードで入力する。
+                if self.current_state.romkan().uses_keycodes() {
+                    let shift = modifiers & IBusModifierType_IBUS_SHIFT_MASK != 0;
+                    let Some(input) = self
+                        .current_state
+                        .romkan()
+                        .keycode_input(keycode, shift)
+                        .map(|input| input.to_string())
+                    else {
+                        return false;
+                    };
+                    self.insert_keycode_input(engine, keycode, &input);
+                    return true;
+                }
+
+                if self.current_state.skk_style && ('!' as u32) <= keyval && keyval <= ('~' as u32)
+                {
+                    let Some(ch) = keyval_to_char(keyval) else {
+                        warn!("Invalid keyval: 0x{:X}", keyval);
+                        return false;
+                    };
+                    self.process_skk_char(engine, ch);
+                    return true;
+                }
+
                 if ('!' as u32) <= keyval && keyval <= ('~' as u32) {
                     trace!(
                         "Insert new character to preedit: '{}'",
                         self.current_state.get_raw_input()
                     );
 
-                    if self.current_state.lookup_table_visible
-                        && !self.current_state.suggest_active
-                        && !self.current_state.suggest_candidate_selected
-                    {
-                        // 変換の途中に別の文字が入力された。
-                        // よって、現在の preedit 文字列は確定させる。
-                        // （サジェスト中はまだ Composition なので確定しない）
-                        self.commit_candidate(engine);
-                    }
+                    self.commit_before_input(engine);
 
                     // 文字列を追加する。
                     let Some(ch) = keyval_to_char(keyval) else {
@@ -306,6 +834,34 @@ impl AkazaContext {
         false // not proceeded
     }
 
+    /// SKK 風の入力で、文字キーが押された時の処理。
+    ///
+    /// 送り仮名が入力されたら変換(▼)する。
+    fn process_skk_char(&mut self, engine: *mut IBusEngine, ch: char) {
+        trace!(
+            "process_skk_char: {:?}, {:?}",
+            ch,
+            self.current_state.skk_state
+        );
+        // 変換中に次の文字が入力されたら、変換結果を確定する。
+        if !self.current_state.clauses.is_empty() {
+            self.commit_candidate(engine);
+        }
+
+        let output = self.current_state.process_skk_char(engine, ch);
+        if !output.commit.is_empty() {
+            unsafe { ibus_engine_commit_text(engine, output.commit.to_ibus_text()) };
+        }
+        if let Some(input_mode) = output.input_mode {
+            self.set_input_mode(engine, &input_mode);
+            return;
+        }
+
+        if self.current_state.is_okurigana_complete() {
+            self.update_candidates(engine);
+        }
+    }
+
     pub(crate) fn erase_character_before_cursor(&mut self, engine: *mut IBusEngine) {
         if !self.current_state.live_conversion
             && !self.current_state.suggest_active
@@ -319,18 +875,14 @@ impl AkazaContext {
         }
 
         // サイゴの一文字をけずるが、子音が先行しているばあいは、子音もついでにとる。
-        self.current_state.set_raw_input(
-            engine,
-            self.current_state
-                .romkan
-                .remove_last_char(self.current_state.get_raw_input()),
-        )
+        self.current_state.remove_last_char(engine)
     }
 }
 
 impl Drop for AkazaContext {
     fn drop(&mut self) {
         warn!("Dropping AkazaContext");
+        self.cancel_sequence_timeout();
     }
 }
 
@@ -351,14 +903,27 @@ impl AkazaContext {
         self.current_state.set_input_mode(engine, input_mode);
     }
 
+    /// キーに割り当てたコマンドを順番に実行する。
+    /// 途中のコマンドが false を返したら、残りのコマンドは実行しない。
+    pub(crate) fn run_commands(
+        &mut self,
+        engine: *mut IBusEngine,
+        commands: &[KeyCommand],
+    ) -> bool {
+        commands
+            .iter()
+            .all(|command| self.run_callback_by_name(engine, &command.name, &command.args))
+    }
+
     pub(crate) fn run_callback_by_name(
         &mut self,
         engine: *mut IBusEngine,
         function_name: &str,
+        args: &[String],
     ) -> bool {
         if let Some(function) = self.command_map.get(function_name) {
-            info!("Calling function '{}'", function_name);
-            function(self, engine)
+            info!("Calling function '{}' {:?}", function_name, args);
+            function(self, engine, args)
         } else {
             error!("Unknown function '{}'", function_name);
             false
@@ -366,7 +931,7 @@ impl AkazaContext {
     }
 
     pub fn commit_string(&mut self, engine: *mut IBusEngine, text: &str) {
-        if !self.current_state.clauses.is_empty() {
+        if !self.current_state.clauses.is_empty() && self.content_type.learning {
             // 変換モードのときのみ学習を実施する
             self.current_state
                 .engine
@@ -387,6 +952,25 @@ impl AkazaContext {
         self.commit_string(engine, self.current_state.build_string().as_str());
     }
 
+    /// 部分確定。選択中の文節までを確定して学習し、残りの読みは変換し直して変換中のままにする。
+    pub(crate) fn partial_commit(&mut self, engine: *mut IBusEngine) -> bool {
+        if self.current_state.get_key_state() != KeyState::Conversion {
+            return false;
+        }
+        let Some((text, committed)) = self.current_state.take_clauses_to_current(engine) else {
+            // 最後の文節を選択している時は、全部確定する。
+            self.commit_candidate(engine);
+            return true;
+        };
+        if self.content_type.learning {
+            self.current_state.engine.learn(&committed);
+        }
+        unsafe {
+            ibus_engine_commit_text(engine, text.to_ibus_text());
+        }
+        self.update_candidates(engine)
+    }
+
     // space key を押して、最初に変換に入る時の処理。
     pub(crate) fn update_candidates(&mut self, engine: *mut IBusEngine) -> bool {
         if self.current_state.get_raw_input().is_empty() {
@@ -570,37 +1154,118 @@ impl AkazaContext {
         }
     }
 
-    pub fn do_focus_in(&mut self, engine: *mut IBusEngine) {
-        trace!("do_focus_in");
+    pub fn do_focus_in(&mut self, engine: *mut IBusEngine, client: Option<String>) {
+        trace!("do_focus_in: client={:?}", client);
+        self.apply_pending_reload(engine);
+        self.retry_model_load(engine);
+        if client.is_some() && client != self.current_client {
+            self.switch_application(engine, client);
+        }
+        self.sync_props(engine);
         self.prop_controller.do_focus_in(engine);
     }
 
+    pub fn do_focus_out(&mut self, engine: *mut IBusEngine) {
+        trace!("do_focus_out");
+        self.finish_preedit(engine, self.config.preedit_on_focus_out);
+    }
+
+    /// カーソルが動かされた時などに呼ばれる。入力中の文字列は捨てる。
+    pub fn do_reset(&mut self, engine: *mut IBusEngine) {
+        trace!("do_reset");
+        self.finish_preedit(engine, PreeditPolicy::Discard);
+    }
+
+    pub fn do_enable(&mut self, engine: *mut IBusEngine) {
+        trace!("do_enable");
+        self.finish_preedit(engine, PreeditPolicy::Discard);
+    }
+
+    pub fn do_disable(&mut self, engine: *mut IBusEngine) {
+        trace!("do_disable");
+        self.finish_preedit(engine, self.config.preedit_on_focus_out);
+    }
+
+    /// 入力中の文字列を policy に従って確定するか捨てて、入力状態を空にする。
+    fn finish_preedit(&mut self, engine: *mut IBusEngine, policy: PreeditPolicy) {
+        self.thumb_shift = ThumbShift::default();
+        self.cancel_sequence_timeout();
+        self.keymap.reset_sequence();
+        if !self.current_state.get_raw_input().is_empty() {
+            info!("Finish preedit: {:?}", policy);
+            match policy {
+                PreeditPolicy::Commit => {
+                    // サジェスト中で候補を選んでいなければ、ひらがなが表示されている。
+                    if self.current_state.clauses.is_empty()
+                        || (self.current_state.suggest_active
+                            && !self.current_state.suggest_candidate_selected)
+                    {
+                        self.current_state.clear_clauses(engine);
+                        self.commit_preedit(engine);
+                    } else {
+                        self.commit_candidate(engine);
+                    }
+                }
+                PreeditPolicy::CommitHiragana => {
+                    let hira = self.current_state.get_hiragana();
+                    self.current_state.clear_clauses(engine);
+                    self.commit_string(engine, hira.as_str());
+                }
+                PreeditPolicy::Discard => {}
+            }
+        }
+        self.current_state.clear_raw_input(engine);
+        self.current_state.clear_clauses(engine);
+        self.current_state.update_lookup_table(engine, false);
+        self.current_state.set_auxiliary_text(engine, "");
+    }
+
+    /// 入力欄の種類(パスワード、メールアドレスなど)に合わせて振る舞いを変える。
+    pub fn do_set_content_type(&mut self, engine: *mut IBusEngine, purpose: guint, hints: guint) {
+        let content_type = ContentType::new(purpose, hints);
+        info!(
+            "do_set_content_type: purpose={}, hints={}, {:?}",
+            purpose, hints, content_type
+        );
+        if content_type.passthrough && !self.content_type.passthrough {
+            // 入力途中の文字列がパスワード欄に残らないように、捨てておく。
+            self.current_state.clear_raw_input(engine);
+            self.current_state.clear_clauses(engine);
+        }
+        self.current_state.suggest_enabled = content_type.prediction;
+
+        if content_type.alphanumeric && self.input_mode_before_content_type.is_none() {
+            self.input_mode_before_content_type = Some(self.current_state.input_mode);
+            if self.current_state.input_mode != INPUT_MODE_ALNUM {
+                self.set_input_mode(engine, &INPUT_MODE_ALNUM);
+            }
+        } else if !content_type.alphanumeric {
+            if let Some(input_mode) = self.input_mode_before_content_type.take() {
+                if input_mode != self.current_state.input_mode {
+                    self.set_input_mode(engine, &input_mode);
+                }
+            }
+        }
+        self.content_type = content_type;
+    }
+
     /// convert selected word/characters to full-width hiragana (standard hiragana): ホワイト → ほわいと
     pub fn convert_to_full_hiragana(&mut self, engine: *mut IBusEngine) -> Result<()> {
         info!("Convert to full hiragana");
-        let hira = self
-            .current_state
-            .romkan
-            .to_hiragana(self.current_state.get_raw_input());
+        let hira = self.current_state.get_hiragana();
         self.convert_to_single(engine, hira.as_str(), hira.as_str())
     }
 
     /// convert to full-width katakana (standard katakana): ほわいと → ホワイト
     pub fn convert_to_full_katakana(&mut self, engine: *mut IBusEngine) -> Result<()> {
-        let hira = self
-            .current_state
-            .romkan
-            .to_hiragana(self.current_state.get_raw_input());
+        let hira = self.current_state.get_hiragana();
         let kata = hira2kata(hira.as_str(), ConvOption::default());
         self.convert_to_single(engine, hira.as_str(), kata.as_str())
     }
 
     /// convert to half-width katakana (standard katakana): ほわいと → ﾎﾜｲﾄ
     pub fn convert_to_half_katakana(&mut self, engine: *mut IBusEngine) -> Result<()> {
-        let hira = self
-            .current_state
-            .romkan
-            .to_hiragana(self.current_state.get_raw_input());
+        let hira = self.current_state.get_hiragana();
         let kata = z2h(
             hira2kata(hira.as_str(), ConvOption::default()).as_str(),
             ConvOption::default(),
@@ -611,10 +1276,7 @@ impl AkazaContext {
     /// convert to full-width romaji, all-capitals, proper noun capitalization (latin script inside
     /// Japanese text): ホワイト → ｈｏｗａｉｔｏ → ＨＯＷＡＩＴＯ → Ｈｏｗａｉｔｏ
     pub fn convert_to_full_romaji(&mut self, engine: *mut IBusEngine) -> Result<()> {
-        let hira = self
-            .current_state
-            .romkan
-            .to_hiragana(self.current_state.get_raw_input());
+        let hira = self.current_state.get_hiragana();
         let romaji = h2z(
             self.current_state.get_raw_input(),
             ConvOption {
@@ -630,10 +1292,7 @@ impl AkazaContext {
     /// convert to half-width romaji, all-capitals, proper noun capitalization (latin script like
     /// standard English): ホワイト → howaito → HOWAITO → Howaito
     pub fn convert_to_half_romaji(&mut self, engine: *mut IBusEngine) -> Result<()> {
-        let hira = self
-            .current_state
-            .romkan
-            .to_hiragana(self.current_state.get_raw_input());
+        let hira = self.current_state.get_hiragana();
         let romaji = z2h(
             self.current_state.get_raw_input(),
             ConvOption {
@@ -690,6 +1349,25 @@ impl AkazaContext {
     }
 }
 
+/// ローマ字かなテーブルに、設定ファイルの romkan_mapping を重ねて読み込む。
+///
+/// romkan_mapping が壊れていても入力はできるように、その時は重ねずにテーブルだけを使う。
+fn load_romkan(
+    mapping_name: &str,
+    overlay: &BTreeMap<String, Option<RomKanRule>>,
+) -> Result<RomKanConverter> {
+    match RomKanConverter::with_overlay(mapping_name, overlay) {
+        Ok(romkan) => Ok(romkan),
+        Err(err) => {
+            error!(
+                "Cannot apply romkan_mapping, using {} without it: {:#}",
+                mapping_name, err
+            );
+            RomKanConverter::new(mapping_name)
+        }
+    }
+}
+
 /// keyval を char に安全に変換する
 ///
 /// 無効な Unicode コードポイントの場合は None を返す
@@ -699,6 +1377,11 @@ fn keyval_to_char(keyval: guint) -> Option<char> {
 
 #[cfg(test)]
 mod tests {
+    use std::ptr::null_mut;
+
+    use libakaza::config::EngineConfig;
+    use libakaza::test_utils::{env_lock, EnvVarGuard};
+
     use super::*;
 
     #[test]
@@ -766,18 +1449,13 @@ mod tests {
     }
 
     #[test]
-    fn test_commands_map_contains_number_commands() {
-        // 数字キーコマンドが全て登録されていることを確認
+    fn test_commands_map_contains_parameterized_commands() {
+        // 引数を取るコマンドが登録されていることを確認
         let commands = ibus_akaza_commands_map();
 
-        for i in 0..=9 {
-            let cmd_name = format!("press_number_{}", i);
-            assert!(
-                commands.contains_key(cmd_name.as_str()),
-                "Command map should contain '{}' command",
-                cmd_name
-            );
-        }
+        assert!(commands.contains_key("select_candidate"));
+        assert!(commands.contains_key("set_input_mode"));
+        assert!(!commands.contains_key("press_number_1"));
     }
 
     #[test]
@@ -810,4 +1488,78 @@ mod tests {
         assert!(commands.contains_key("extend_clause_right"));
         assert!(commands.contains_key("extend_clause_left"));
     }
+    #[test]
+    fn test_commands_map_matches_keymap_commands() {
+        // akaza-data config check が使うコマンド一覧と一致していることを確認
+        let mut commands = ibus_akaza_commands_map()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        commands.sort();
+        let mut expected = libakaza::keymap::COMMANDS.to_vec();
+        expected.sort();
+        assert_eq!(commands, expected);
+    }
+
+    #[test]
+    fn test_load_romkan_with_conflicting_overlay() -> Result<()> {
+        // "z" は "za" などの前置になるので、重ねずにテーブルだけを使う
+        let overlay = BTreeMap::from([("z".to_string(), Some(RomKanRule::Kana("ず".to_string())))]);
+        let romkan = load_romkan("../romkan/default.yml", &overlay)?;
+        assert_eq!(romkan.to_hiragana("za"), "ざ");
+
+        // テーブルがなければエラーにする
+        assert!(load_romkan("../romkan/none.yml", &BTreeMap::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_commit() -> Result<()> {
+        // ユーザー辞書のディレクトリを作るので、一時ディレクトリを使う
+        let _lock = env_lock().lock().unwrap();
+        let tmpdir = tempfile::tempdir()?;
+        let _data_home = EnvVarGuard::set("XDG_DATA_HOME", tmpdir.path());
+        // ローマ字テーブルとキーマップはリポジトリのものを使う
+        let _data_dirs = EnvVarGuard::set("AKAZA_DATA_DIRS", Path::new(".."));
+
+        let engine = BigramWordViterbiEngineBuilder::new(EngineConfig {
+            dict_cache: false,
+            model: "/nonexistent/akaza-model".to_string(),
+            ..Default::default()
+        })
+        .build()?;
+        let mut context = AkazaContext::new(engine, Config::default_config())?;
+        context.content_type.learning = false;
+        context.current_state.suggest_enabled = false;
+
+        // 変換中でなければ何もしない
+        assert!(!context.partial_commit(null_mut()));
+
+        context
+            .current_state
+            .set_raw_input(null_mut(), "watasinonamae".to_string());
+        context.current_state.clauses = vec![
+            vec![Candidate::new("わたしの", "私の", 0_f32)],
+            vec![Candidate::new("なまえ", "名前", 0_f32)],
+        ];
+        assert!(context.partial_commit(null_mut()));
+        // 残りの読みは変換し直して、変換中のままにする
+        assert_eq!(context.current_state.get_raw_input(), "namae");
+        assert_eq!(context.current_state.get_key_state(), KeyState::Conversion);
+        assert_eq!(
+            context
+                .current_state
+                .clauses
+                .iter()
+                .map(|clause| clause[0].yomi.as_str())
+                .collect::<String>(),
+            "なまえ"
+        );
+
+        // 最後の文節を選択している時は、全部確定する
+        context.current_state.current_clause = context.current_state.clauses.len() - 1;
+        assert!(context.partial_commit(null_mut()));
+        assert_eq!(context.current_state.get_raw_input(), "");
+        Ok(())
+    }
 }
@@ -19,8 +19,7 @@ use ibus_sys::engine::{
 use ibus_sys::glib::guint;
 use ibus_sys::lookup_table::IBusLookupTable;
 use ibus_sys::text::{ibus_text_set_attributes, StringExt};
-use libakaza::consonant::ConsonantSuffixExtractor;
-use libakaza::engine::base::HenkanEngine;
+use libakaza::config::PunctuationStyle;
 use libakaza::engine::bigram_word_viterbi_engine::BigramWordViterbiEngine;
 use libakaza::extend_clause::{extend_left, extend_right};
 // 文節伸縮・選択の仕様は docs/clause-extension-behavior.md を参照。
@@ -30,9 +29,33 @@ use libakaza::kana_kanji::marisa_kana_kanji_dict::MarisaKanaKanjiDict;
 use libakaza::keymap::KeyState;
 use libakaza::lm::system_bigram::MarisaSystemBigramLM;
 use libakaza::lm::system_unigram_lm::MarisaSystemUnigramLM;
-use libakaza::romkan::RomKanConverter;
+use libakaza::romkan::{remove_last_char_len, RomKanConverter, RomKanState};
 
-use crate::input_mode::{InputMode, INPUT_MODE_HALFWIDTH_KATAKANA, INPUT_MODE_KATAKANA};
+use crate::input_mode::{
+    InputMode, INPUT_MODE_ALNUM, INPUT_MODE_HALFWIDTH_KATAKANA, INPUT_MODE_HIRAGANA,
+    INPUT_MODE_KATAKANA,
+};
+
+/// SKK 風の入力での、変換範囲の状態。
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SkkState {
+    /// ■ 変換範囲の外。入力したかなはすぐに確定する。
+    #[default]
+    Direct,
+    /// ▽ 変換範囲の読みを入力している。
+    Marked,
+    /// ▽ 送り仮名を入力している。okuri_start は raw_input での送り仮名の始まり。
+    Okurigana { okuri_start: usize },
+}
+
+/// SKK 風の入力で、文字キーを処理した結果。
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SkkOutput {
+    /// すぐに確定する文字列
+    pub(crate) commit: String,
+    /// 切り替える入力モード
+    pub(crate) input_mode: Option<InputMode>,
+}
 
 #[derive(Debug)]
 pub struct CurrentState {
@@ -54,16 +77,25 @@ pub struct CurrentState {
     /// サジェスト中に Tab/Up/Down で候補を選択したかどうか
     /// true の場合、preedit に変換結果を表示し、Enter で確定する
     pub(crate) suggest_candidate_selected: bool,
+    /// 入力欄の種類によってはサジェストしない
+    pub(crate) suggest_enabled: bool,
     pub(crate) lookup_table_visible: bool,
     pub lookup_table: IBusLookupTable,
-    pub romkan: RomKanConverter,
+    romkan: RomKanConverter,
+    /// raw_input を一文字ずつ入力した時のローマ字かな変換の状態。先頭は入力前の空の状態。
+    /// キーを押すたびに raw_input 全体を変換しなおさなくて済むように、残しておく。
+    romkan_states: Vec<RomKanState>,
     pub(crate) engine:
         BigramWordViterbiEngine<MarisaSystemUnigramLM, MarisaSystemBigramLM, MarisaKanaKanjiDict>,
-    consonant_suffix_extractor: ConsonantSuffixExtractor,
     /// k-best の分節パターン候補
     segmentation_alternatives: Vec<KBestPath>,
     /// 現在選択中の分節パターン (0 = 1-best)
     current_segmentation: usize,
+    /// SKK のように、大文字で変換範囲を指定して入力する
+    pub(crate) skk_style: bool,
+    pub(crate) skk_state: SkkState,
+    /// 部分確定で最後に確定した単語。残りの読みを変換する時の文脈にする。
+    left_context: Option<Candidate>,
 }
 
 fn next_clause_index(current: usize, len: usize, dir: i32) -> usize {
@@ -107,20 +139,24 @@ impl CurrentState {
             live_conversion,
             suggest_active: false,
             suggest_candidate_selected: false,
+            suggest_enabled: true,
             lookup_table_visible: false,
             lookup_table: IBusLookupTable::new(10, 0, 1, 1),
             romkan,
+            romkan_states: vec![RomKanState::default()],
             engine,
-            consonant_suffix_extractor: ConsonantSuffixExtractor::default(),
             segmentation_alternatives: Vec::new(),
             current_segmentation: 0,
+            skk_style: false,
+            skk_state: SkkState::Direct,
+            left_context: None,
         }
     }
 
     /// サジェスト表示すべきかどうかを判定する。
     /// ひらがな2文字以上入力されている場合に true を返す。
     fn should_suggest(&self) -> bool {
-        self.romkan.to_hiragana(&self.raw_input).chars().count() >= 2
+        self.suggest_enabled && !self.skk_style && self.get_hiragana().chars().count() >= 2
     }
 
     pub(crate) fn set_input_mode(&mut self, engine: *mut IBusEngine, input_mode: &InputMode) {
@@ -137,10 +173,13 @@ impl CurrentState {
     }
 
     pub fn clear_raw_input(&mut self, engine: *mut IBusEngine) {
+        self.skk_state = SkkState::Direct;
+        self.left_context = None;
         self.suggest_active = false;
         self.suggest_candidate_selected = false;
         if !self.raw_input.is_empty() {
             self.raw_input.clear();
+            self.romkan_states.truncate(1);
             self.on_raw_input_change(engine);
         }
     }
@@ -149,6 +188,38 @@ impl CurrentState {
         &self.raw_input
     }
 
+    pub fn romkan(&self) -> &RomKanConverter {
+        &self.romkan
+    }
+
+    /// ローマ字かなテーブルを切り替える。入力中の文字列は新しいテーブルで変換しなおす。
+    pub fn set_romkan(&mut self, romkan: RomKanConverter) {
+        self.romkan = romkan;
+        self.romkan_states = self.romkan.states(&self.raw_input);
+    }
+
+    pub fn set_punctuation(&mut self, style: PunctuationStyle) {
+        self.romkan.set_punctuation(style);
+        self.romkan_states = self.romkan.states(&self.raw_input);
+    }
+
+    /// raw_input の先頭から byte_idx までを変換した時の状態。
+    fn romkan_state_at(&self, byte_idx: usize) -> &RomKanState {
+        &self.romkan_states[self.raw_input[..byte_idx].chars().count()]
+    }
+
+    /// raw_input 全体の、未確定の入力も確定させたひらがな。
+    pub fn get_hiragana(&self) -> String {
+        self.romkan
+            .flushed_kana(self.romkan_state_at(self.raw_input.len()))
+    }
+
+    /// raw_input 全体を変換して、(確定したかな, 未確定の入力) を返す。
+    fn get_hiragana_with_pending(&self) -> (String, String) {
+        let state = self.romkan_state_at(self.raw_input.len());
+        (state.kana().to_string(), state.pending().to_string())
+    }
+
     pub fn clear_force_selected_clause(&mut self, engine: *mut IBusEngine) {
         if !self.force_selected_clause.is_empty() {
             self.force_selected_clause.clear();
@@ -164,19 +235,51 @@ impl CurrentState {
     }
 
     pub(crate) fn append_raw_input(&mut self, engine: *mut IBusEngine, ch: char) {
+        self.push_romkan_state(ch);
         self.raw_input.push(ch);
         self.on_raw_input_change(engine);
     }
 
-    /// バックスペースで一文字削除した場合などに呼ばれる。
+    fn push_romkan_state(&mut self, ch: char) {
+        let mut state = self.romkan_states.last().unwrap().clone();
+        self.romkan.push(&mut state, ch);
+        self.romkan_states.push(state);
+    }
+
     pub(crate) fn set_raw_input(&mut self, engine: *mut IBusEngine, raw_input: String) {
         if self.raw_input != raw_input {
             info!("set_raw_input: {:?}", raw_input);
+            // 前の入力と共通する先頭部分の状態は、そのまま使う。
+            let common = self
+                .raw_input
+                .chars()
+                .zip(raw_input.chars())
+                .take_while(|(a, b)| a == b)
+                .count();
+            self.romkan_states.truncate(common + 1);
+            raw_input
+                .chars()
+                .skip(common)
+                .for_each(|ch| self.push_romkan_state(ch));
             self.raw_input = raw_input;
             self.on_raw_input_change(engine);
         }
     }
 
+    /// バックスペースで、最後の一文字分の入力を消す。
+    /// 子音が先行しているばあいは、子音もついでにとる。
+    pub(crate) fn remove_last_char(&mut self, engine: *mut IBusEngine) {
+        let len = remove_last_char_len(&self.romkan_states);
+        self.romkan_states.truncate(len + 1);
+        let byte_len = self
+            .raw_input
+            .char_indices()
+            .nth(len)
+            .map_or(self.raw_input.len(), |(idx, _)| idx);
+        self.raw_input.truncate(byte_len);
+        self.on_raw_input_change(engine);
+    }
+
     pub(crate) fn henkan(&mut self, engine: *mut IBusEngine) -> anyhow::Result<()> {
         if self.get_raw_input().is_empty() {
             self.segmentation_alternatives.clear();
@@ -201,11 +304,42 @@ impl CurrentState {
                 )])];
                 self.set_clauses(engine, clauses);
             } else {
-                let paths = self.engine.convert_k_best(
-                    self.romkan.to_hiragana(&yomi).as_str(),
-                    Some(&self.force_selected_clause),
+                // SKK 風の入力では、送り仮名の前までを変換し、送り仮名は入力したままにする。
+                let (hiragana, okurigana) = match self.skk_state {
+                    SkkState::Okurigana { okuri_start } => (
+                        self.romkan.flushed_kana(self.romkan_state_at(okuri_start)),
+                        self.romkan.to_hiragana(&self.raw_input[okuri_start..]),
+                    ),
+                    _ => (self.get_hiragana(), String::new()),
+                };
+                // SKK 風の入力では、指定された変換範囲を一つの文節として変換する。
+                let whole = 0..hiragana.len();
+                let force_ranges = if self.skk_style && self.force_selected_clause.is_empty() {
+                    vec![whole]
+                } else {
+                    // 文節を伸ばして送り仮名まで含めた時は、送り仮名の分を除く。
+                    self.force_selected_clause
+                        .iter()
+                        .filter(|range| range.start < hiragana.len())
+                        .map(|range| range.start..range.end.min(hiragana.len()))
+                        .collect()
+                };
+                let mut paths = self.engine.convert_k_best_with_context(
+                    hiragana.as_str(),
+                    Some(&force_ranges),
                     5,
+                    self.left_context.as_ref(),
                 )?;
+                if !okurigana.is_empty() {
+                    for candidate in paths
+                        .iter_mut()
+                        .filter_map(|path| path.segments.last_mut())
+                        .flatten()
+                    {
+                        candidate.yomi += &okurigana;
+                        candidate.surface += &okurigana;
+                    }
+                }
                 self.segmentation_alternatives = paths;
                 self.current_segmentation = 0;
                 let clauses = self
@@ -352,6 +486,66 @@ impl CurrentState {
         build_string_from_clauses(&self.clauses, &self.node_selected)
     }
 
+    /// current_clause までの文節を取り出して、確定する文字列と学習する候補を返す。
+    /// 残りの文節の入力は raw_input に残し、取り出した最後の単語を文脈にして変換し直す。
+    pub(crate) fn take_clauses_to_current(
+        &mut self,
+        engine: *mut IBusEngine,
+    ) -> Option<(String, Vec<Candidate>)> {
+        let end = self.current_clause + 1;
+        if end >= self.clauses.len() {
+            return None;
+        }
+        let text = build_string_from_clauses(&self.clauses[..end], &self.node_selected);
+        let committed = collect_first_candidates(&self.clauses[..end], &self.node_selected);
+        let committed_yomi = self.clauses[..end]
+            .iter()
+            .filter_map(|clause| clause.first())
+            .map(|candidate| candidate.yomi.as_str())
+            .collect::<String>();
+        let rest = self.raw_input_after(&committed_yomi);
+
+        self.left_context = committed.last().cloned();
+        self.skk_state = match (self.skk_state, &rest) {
+            // 送り仮名の位置は、確定した分だけ前にずらす。
+            (SkkState::Okurigana { okuri_start }, Some(rest)) => {
+                match okuri_start.checked_sub(self.raw_input.len() - rest.len()) {
+                    Some(okuri_start) if okuri_start > 0 => SkkState::Okurigana { okuri_start },
+                    _ => SkkState::Marked,
+                }
+            }
+            (SkkState::Okurigana { .. }, None) => SkkState::Marked,
+            (state, _) => state,
+        };
+        // ローマ字の区切りと文節の区切りが合わない時は、残りの読みをかなのまま使う。
+        let rest = rest.unwrap_or_else(|| {
+            self.clauses[end..]
+                .iter()
+                .filter_map(|clause| clause.first())
+                .map(|candidate| candidate.yomi.as_str())
+                .collect()
+        });
+        self.clear_clauses(engine);
+        self.set_raw_input(engine, rest);
+        Some((text, committed))
+    }
+
+    /// raw_input のうち、読みが yomi になる先頭部分より後ろを返す。
+    /// "kanjiwo" で yomi が "かんじ" なら "wo" になる。
+    fn raw_input_after(&self, yomi: &str) -> Option<String> {
+        let (len, state) = self
+            .romkan_states
+            .iter()
+            .enumerate()
+            .find(|(_, state)| state.kana().len() >= yomi.len())?;
+        if state.kana() != yomi {
+            return None;
+        }
+        // 未確定の入力は、まだ読みになっていないので残す。
+        let start = len - state.pending().chars().count();
+        Some(self.raw_input.chars().skip(start).collect())
+    }
+
     pub fn extend_right(&mut self, engine: *mut IBusEngine) {
         self.current_segmentation = 0;
         self.force_selected_clause = extend_right(&self.clauses, self.current_clause);
@@ -438,6 +632,15 @@ impl CurrentState {
     }
 
     pub fn on_raw_input_change(&mut self, engine: *mut IBusEngine) {
+        // バックスペースで送り仮名や読みを消した時は、変換範囲の状態も戻す。
+        self.skk_state = match self.skk_state {
+            _ if self.raw_input.is_empty() => SkkState::Direct,
+            SkkState::Okurigana { okuri_start } if okuri_start >= self.raw_input.len() => {
+                SkkState::Marked
+            }
+            state => state,
+        };
+
         // unicode character の境界じゃないところに force_selected が入った状態で hanken
         // すると落ちる。
         // なので、先にクリアする必要がある。
@@ -488,7 +691,7 @@ impl CurrentState {
         // -- auxiliary text(ポップアップしてるやつのほう)
         if self.suggest_active {
             // サジェスト中は入力全体のひらがなを表示
-            let yomi = self.romkan.to_hiragana(&self.raw_input);
+            let yomi = self.get_hiragana();
             self.set_auxiliary_text(engine, &yomi);
         } else if let Some(clause) = self.clauses.get(self.current_clause) {
             if let Some(first) = clause.first() {
@@ -524,8 +727,10 @@ impl CurrentState {
             self.render_preedit(engine);
         } else if self.clauses.is_empty() {
             // live conversion じゃなくて、変換中じゃないとき。
-            let (_yomi, surface) = self.make_preedit_word_for_precomposition();
-            self.preedit = surface;
+            self.preedit = self.make_skk_preedit().unwrap_or_else(|| {
+                let (_yomi, surface) = self.make_preedit_word_for_precomposition();
+                surface
+            });
             self.render_preedit(engine);
         } else {
             // live conversion じゃなくて、変換中のとき。
@@ -625,7 +830,13 @@ impl CurrentState {
                 if self.auxiliary_text.is_empty() {
                     ibus_engine_hide_auxiliary_text(engine);
                 } else {
-                    let auxiliary_text = self.auxiliary_text.to_ibus_text();
+                    // 言語モデルが読み込めていない時は、変換精度が落ちていることを知らせる。
+                    let auxiliary_text = if self.engine.model_error().is_some() {
+                        format!("{} (辞書のみで変換中)", self.auxiliary_text)
+                    } else {
+                        self.auxiliary_text.clone()
+                    };
+                    let auxiliary_text = auxiliary_text.to_ibus_text();
                     ibus_text_set_attributes(auxiliary_text, ibus_attr_list_new());
                     ibus_engine_update_auxiliary_text(
                         engine,
@@ -669,31 +880,167 @@ impl CurrentState {
         // hogena となったら "ほげな"
         // hogenn となったら "ほげん" と表示する必要があるため。
         // 「ん」と一旦表示された後に「な」に変化したりすると気持ち悪く感じる。
-        let (preedit, suffix) = if self.romkan.mapping_name == "default" {
-            self.consonant_suffix_extractor.extract(preedit.as_str())
-        } else {
-            (preedit, "".to_string())
-        };
+        let (yomi, suffix) = self.get_hiragana_with_pending();
+        // かな入力では未確定の部分もかななので、まとめてカタカナにする。
+        let yomi = yomi + suffix.as_str();
+        let surface = self.to_input_mode_surface(&yomi);
+        (yomi, surface)
+    }
 
-        let yomi = self.romkan.to_hiragana(preedit.as_str());
-        let surface = yomi.clone();
+    /// ひらがなを、入力モードに合わせてカタカナや半角カタカナにする。
+    pub(crate) fn to_input_mode_surface(&self, yomi: &str) -> String {
         if self.input_mode == INPUT_MODE_KATAKANA {
-            (
-                yomi.to_string() + suffix.as_str(),
-                hira2kata(yomi.as_str(), ConvOption::default()) + suffix.as_str(),
-            )
+            hira2kata(yomi, ConvOption::default())
         } else if self.input_mode == INPUT_MODE_HALFWIDTH_KATAKANA {
-            (
-                yomi.to_string() + suffix.as_str(),
-                z2h(
-                    hira2kata(yomi.as_str(), ConvOption::default()).as_str(),
-                    ConvOption::default(),
-                ) + suffix.as_str(),
+            z2h(
+                hira2kata(yomi, ConvOption::default()).as_str(),
+                ConvOption::default(),
             )
         } else {
-            (yomi + suffix.as_str(), surface + suffix.as_str())
+            yomi.to_string()
         }
     }
+
+    /// SKK 風の入力で変換範囲を入力している時の preedit。"▽よ*ん" のように表示する。
+    fn make_skk_preedit(&self) -> Option<String> {
+        if !self.skk_style {
+            return None;
+        }
+        let surface = |raw: &str| {
+            let (yomi, suffix) = self.romkan.to_hiragana_with_pending(raw);
+            self.to_input_mode_surface(&(yomi + suffix.as_str()))
+        };
+        match self.skk_state {
+            SkkState::Direct => None,
+            SkkState::Marked => Some(format!(
+                "▽{}",
+                self.to_input_mode_surface(&self.romkan_state_at(self.raw_input.len()).display())
+            )),
+            SkkState::Okurigana { okuri_start } => Some(format!(
+                "▽{}*{}",
+                self.to_input_mode_surface(
+                    &self.romkan.flushed_kana(self.romkan_state_at(okuri_start))
+                ),
+                surface(&self.raw_input[okuri_start..])
+            )),
+        }
+    }
+
+    /// SKK 風の入力で、文字キーが押された時の処理。
+    ///
+    /// 大文字で変換範囲(▽)を始め、変換範囲の中の大文字で送り仮名の始まりを指定する。
+    /// 変換範囲の外で入力したかなは、すぐに確定する。
+    pub(crate) fn process_skk_char(&mut self, engine: *mut IBusEngine, ch: char) -> SkkOutput {
+        match self.skk_state {
+            SkkState::Direct => {
+                if self.raw_input.is_empty() {
+                    match ch {
+                        // ひらがなとカタカナを切り替える
+                        'q' => {
+                            let input_mode = if self.input_mode == INPUT_MODE_HIRAGANA {
+                                INPUT_MODE_KATAKANA
+                            } else {
+                                INPUT_MODE_HIRAGANA
+                            };
+                            return SkkOutput {
+                                input_mode: Some(input_mode),
+                                ..Default::default()
+                            };
+                        }
+                        'l' => {
+                            return SkkOutput {
+                                input_mode: Some(INPUT_MODE_ALNUM),
+                                ..Default::default()
+                            };
+                        }
+                        _ => {}
+                    }
+                }
+
+                if ch.is_ascii_uppercase() {
+                    // 入力途中のローマ字を確定してから、変換範囲を始める。
+                    let commit = self.take_direct_kana(engine, true);
+                    self.skk_state = SkkState::Marked;
+                    self.append_raw_input(engine, ch.to_ascii_lowercase());
+                    SkkOutput {
+                        commit,
+                        ..Default::default()
+                    }
+                } else {
+                    // 未確定のローマ字は preedit に残したまま確定する。
+                    self.append_raw_input(engine, ch);
+                    SkkOutput {
+                        commit: self.take_direct_kana(engine, false),
+                        ..Default::default()
+                    }
+                }
+            }
+            SkkState::Marked if ch == 'q' => {
+                // 変換範囲の読みを、カタカナ(カタカナモードならひらがな)にして確定する。
+                let yomi = self.get_hiragana();
+                let commit = if self.input_mode == INPUT_MODE_KATAKANA
+                    || self.input_mode == INPUT_MODE_HALFWIDTH_KATAKANA
+                {
+                    yomi
+                } else {
+                    hira2kata(&yomi, ConvOption::default())
+                };
+                self.clear_raw_input(engine);
+                SkkOutput {
+                    commit,
+                    ..Default::default()
+                }
+            }
+            SkkState::Marked if ch == 'l' => {
+                // 変換範囲の読みをそのまま確定して、英数入力にする。
+                let commit = self.to_input_mode_surface(&self.get_hiragana());
+                self.clear_raw_input(engine);
+                SkkOutput {
+                    commit,
+                    input_mode: Some(INPUT_MODE_ALNUM),
+                }
+            }
+            SkkState::Marked => {
+                if ch.is_ascii_uppercase() {
+                    self.skk_state = SkkState::Okurigana {
+                        okuri_start: self.raw_input.len(),
+                    };
+                }
+                self.append_raw_input(engine, ch.to_ascii_lowercase());
+                SkkOutput::default()
+            }
+            SkkState::Okurigana { .. } => {
+                self.append_raw_input(engine, ch.to_ascii_lowercase());
+                SkkOutput::default()
+            }
+        }
+    }
+
+    /// SKK 風の入力の変換範囲の外で、かなが確定した部分を取り出す。
+    /// 未確定のローマ字は raw_input に残す。flush なら未確定の部分も確定させる。
+    pub(crate) fn take_direct_kana(&mut self, engine: *mut IBusEngine, flush: bool) -> String {
+        let (yomi, pending) = if flush {
+            (self.get_hiragana(), String::new())
+        } else {
+            self.get_hiragana_with_pending()
+        };
+        if yomi.is_empty() {
+            return yomi;
+        }
+        self.set_raw_input(engine, pending);
+        self.to_input_mode_surface(&yomi)
+    }
+
+    /// SKK 風の入力で、送り仮名が一文字以上確定したか。確定したら変換を始める。
+    pub(crate) fn is_okurigana_complete(&self) -> bool {
+        let SkkState::Okurigana { okuri_start } = self.skk_state else {
+            return false;
+        };
+        let (kana, pending) = self
+            .romkan
+            .to_hiragana_with_pending(&self.raw_input[okuri_start..]);
+        !kana.is_empty() && pending.is_empty()
+    }
 }
 
 /// clauses と node_selected から、各文節の選択された候補を集める。
@@ -758,12 +1105,218 @@ fn build_string_from_clauses(
 
 #[cfg(test)]
 mod tests {
+    use std::ptr::null_mut;
+
+    use libakaza::config::{DictConfig, DictEncoding, DictType, DictUsage, EngineConfig};
+    use libakaza::engine::bigram_word_viterbi_engine::BigramWordViterbiEngineBuilder;
+
     use super::*;
 
     fn candidate(yomi: &str, surface: &str) -> Candidate {
         Candidate::new(yomi, surface, 0_f32)
     }
 
+    /// CurrentState を作る。モデルはなく、dicts の辞書だけで変換する。
+    fn new_state(dicts: Vec<DictConfig>) -> anyhow::Result<CurrentState> {
+        let engine = BigramWordViterbiEngineBuilder::new(EngineConfig {
+            dicts,
+            dict_cache: false,
+            model: "/nonexistent/akaza-model".to_string(),
+            ..Default::default()
+        })
+        .build()?;
+        let romkan = RomKanConverter::new("../romkan/default.yml")?;
+        Ok(CurrentState::new(
+            INPUT_MODE_HIRAGANA,
+            false,
+            romkan,
+            engine,
+        ))
+    }
+
+    /// SKK 風の入力の CurrentState を作る。
+    fn skk_state(dicts: Vec<DictConfig>) -> anyhow::Result<CurrentState> {
+        let mut state = new_state(dicts)?;
+        state.skk_style = true;
+        Ok(state)
+    }
+
+    fn type_skk(state: &mut CurrentState, src: &str) -> Vec<SkkOutput> {
+        src.chars()
+            .map(|ch| state.process_skk_char(null_mut(), ch))
+            .collect()
+    }
+
+    // --- 部分確定 ---
+
+    #[test]
+    fn test_take_clauses_to_current() -> anyhow::Result<()> {
+        let mut state = new_state(vec![])?;
+        state.suggest_enabled = false;
+        state.set_raw_input(null_mut(), "kanjiwokaku".to_string());
+        state.clauses = vec![
+            vec![candidate("かんじ", "漢字"), candidate("かんじ", "感じ")],
+            vec![candidate("を", "を")],
+            vec![candidate("かく", "書く")],
+        ];
+        state.node_selected.insert(0, 1);
+        state.current_clause = 1;
+
+        let (text, committed) = state.take_clauses_to_current(null_mut()).unwrap();
+        assert_eq!(text, "感じを");
+        assert_eq!(
+            committed,
+            vec![candidate("かんじ", "感じ"), candidate("を", "を")]
+        );
+        // 残りはローマ字のまま残るので、ローマ字に変換しなおせる
+        assert_eq!(state.get_raw_input(), "kaku");
+        assert_eq!(state.get_hiragana(), "かく");
+        assert_eq!(state.left_context, Some(candidate("を", "を")));
+        assert!(state.clauses.is_empty());
+
+        // 最後の文節を選択している時は、取り出さない
+        state.clauses = vec![vec![candidate("かく", "書く")]];
+        state.current_clause = 0;
+        assert!(state.take_clauses_to_current(null_mut()).is_none());
+        assert_eq!(state.get_raw_input(), "kaku");
+        Ok(())
+    }
+
+    #[test]
+    fn test_take_clauses_to_current_with_pending() -> anyhow::Result<()> {
+        let mut state = new_state(vec![])?;
+        state.suggest_enabled = false;
+        // "n" はまだ「ん」になっていないが、変換する時は「ん」として読む
+        state.set_raw_input(null_mut(), "kanjin".to_string());
+        state.clauses = vec![
+            vec![candidate("かんじ", "漢字")],
+            vec![candidate("ん", "ん")],
+        ];
+        state.take_clauses_to_current(null_mut()).unwrap();
+        assert_eq!(state.get_raw_input(), "n");
+
+        // "kya" の途中で文節が分かれている時は、残りの読みをかなで残す
+        state.set_raw_input(null_mut(), "kya".to_string());
+        state.clauses = vec![vec![candidate("き", "木")], vec![candidate("ゃ", "ゃ")]];
+        state.take_clauses_to_current(null_mut()).unwrap();
+        assert_eq!(state.get_raw_input(), "ゃ");
+        Ok(())
+    }
+
+    // --- SKK 風の入力 ---
+
+    #[test]
+    fn test_skk_direct_kana_is_committed() -> anyhow::Result<()> {
+        let mut state = skk_state(vec![])?;
+        let output = type_skk(&mut state, "ka");
+        // 子音だけの間は preedit に残し、かなになったらすぐに確定する
+        assert_eq!(output[0], SkkOutput::default());
+        assert_eq!(output[1].commit, "か");
+        assert_eq!(state.get_raw_input(), "");
+        assert_eq!(state.skk_state, SkkState::Direct);
+
+        // "n" は次の入力で変わるので、まだ確定しない
+        let output = type_skk(&mut state, "n");
+        assert_eq!(output[0].commit, "");
+        assert_eq!(state.get_raw_input(), "n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_skk_marked_and_okurigana() -> anyhow::Result<()> {
+        let mut state = skk_state(vec![])?;
+        // 入力途中のローマ字は、変換範囲を始める前に確定する
+        let output = type_skk(&mut state, "nKa");
+        assert_eq!(output[1].commit, "ん");
+        assert_eq!(state.skk_state, SkkState::Marked);
+        assert_eq!(state.make_skk_preedit(), Some("▽か".to_string()));
+
+        type_skk(&mut state, "K");
+        assert_eq!(state.skk_state, SkkState::Okurigana { okuri_start: 2 });
+        assert_eq!(state.make_skk_preedit(), Some("▽か*k".to_string()));
+        assert!(!state.is_okurigana_complete());
+
+        type_skk(&mut state, "u");
+        assert_eq!(state.make_skk_preedit(), Some("▽か*く".to_string()));
+        assert!(state.is_okurigana_complete());
+        Ok(())
+    }
+
+    #[test]
+    fn test_skk_q_and_l() -> anyhow::Result<()> {
+        let mut state = skk_state(vec![])?;
+        // 変換範囲の外では、入力モードを切り替える
+        let output = type_skk(&mut state, "q");
+        assert_eq!(output[0].input_mode, Some(INPUT_MODE_KATAKANA));
+        let output = type_skk(&mut state, "l");
+        assert_eq!(output[0].input_mode, Some(INPUT_MODE_ALNUM));
+
+        // 変換範囲の中の q は、読みをカタカナにして確定する
+        let output = type_skk(&mut state, "Kanaq");
+        assert_eq!(output[4].commit, "カナ");
+        assert_eq!(output[4].input_mode, None);
+        assert_eq!(state.get_raw_input(), "");
+        assert_eq!(state.skk_state, SkkState::Direct);
+
+        // 変換範囲の中の l は、読みを確定して英数入力にする
+        let output = type_skk(&mut state, "Kanal");
+        assert_eq!(
+            output[4],
+            SkkOutput {
+                commit: "かな".to_string(),
+                input_mode: Some(INPUT_MODE_ALNUM),
+            }
+        );
+        assert_eq!(state.get_raw_input(), "");
+        assert_eq!(state.skk_state, SkkState::Direct);
+        Ok(())
+    }
+
+    #[test]
+    fn test_skk_backspace_restores_state() -> anyhow::Result<()> {
+        let mut state = skk_state(vec![])?;
+        type_skk(&mut state, "KaKu");
+        assert_eq!(state.skk_state, SkkState::Okurigana { okuri_start: 2 });
+
+        // 送り仮名を消すと ▽ に戻る
+        state.remove_last_char(null_mut());
+        assert_eq!(state.get_raw_input(), "ka");
+        assert_eq!(state.skk_state, SkkState::Marked);
+        assert_eq!(state.make_skk_preedit(), Some("▽か".to_string()));
+
+        // 読みを全部消すと、変換範囲の外に戻る
+        state.remove_last_char(null_mut());
+        assert_eq!(state.get_raw_input(), "");
+        assert_eq!(state.skk_state, SkkState::Direct);
+        Ok(())
+    }
+
+    #[test]
+    fn test_skk_okurigana_is_not_converted() -> anyhow::Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let dict = tmpdir.path().join("SKK-JISYO.test");
+        std::fs::write(&dict, ";; okuri-nasi entries.\nか /書/\n")?;
+        let mut state = skk_state(vec![DictConfig {
+            path: dict.to_string_lossy().to_string(),
+            encoding: DictEncoding::Utf8,
+            dict_type: DictType::SKK,
+            usage: DictUsage::Normal,
+        }])?;
+
+        type_skk(&mut state, "KaKu");
+        state.henkan(null_mut())?;
+        // 送り仮名の前までを一つの文節として変換し、送り仮名は入力したまま後ろにつける
+        assert_eq!(state.clauses.len(), 1);
+        assert_eq!(state.clauses[0][0].yomi, "かく");
+        assert!(state.clauses[0]
+            .iter()
+            .all(|candidate| candidate.surface.ends_with('く')));
+        assert!(state.clauses[0]
+            .iter()
+            .any(|candidate| candidate.surface == "書く"));
+        Ok(())
+    }
+
     // --- collect_first_candidates tests ---
 
     #[test]
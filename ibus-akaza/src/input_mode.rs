@@ -66,6 +66,11 @@ pub fn get_input_mode_from_prop_name(prop_code: &str) -> anyhow::Result<InputMod
     bail!("Unknown prop_code: {}", prop_code)
 }
 
+/// 設定ファイルで指定された "Hiragana" などの名前から InputMode を得る。
+pub fn get_input_mode_from_name(name: &str) -> anyhow::Result<InputMode> {
+    get_input_mode_from_prop_name(format!("InputMode.{name}").as_str())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,6 +99,23 @@ mod tests {
         assert_eq!(modes.len(), 5, "Should return 5 input modes");
     }
 
+    #[test]
+    fn test_input_mode_names_match_keymap_modes() {
+        // キーマップの modes で使える名前と一致していることを確認
+        let names = get_all_input_modes()
+            .iter()
+            .map(|mode| mode.prop_name.trim_start_matches("InputMode."))
+            .collect::<Vec<_>>();
+        assert_eq!(names, libakaza::keymap::INPUT_MODES.to_vec());
+    }
+
+    #[test]
+    fn test_get_input_mode_from_name() {
+        let mode = get_input_mode_from_name("Alphanumeric").unwrap();
+        assert_eq!(mode, INPUT_MODE_ALNUM);
+        assert!(get_input_mode_from_name("InputMode.Alphanumeric").is_err());
+    }
+
     #[test]
     fn test_get_input_mode_from_prop_name_valid() {
         // 有効なprop_nameで正しいモードが取得できることを確認
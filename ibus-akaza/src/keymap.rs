@@ -1,17 +1,33 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CString;
+use std::time::{Duration, Instant};
 
 use log::{error, trace};
 
-use ibus_sys::core::{IBusModifierType_IBUS_CONTROL_MASK, IBusModifierType_IBUS_SHIFT_MASK};
+use ibus_sys::core::{
+    IBusModifierType_IBUS_CONTROL_MASK, IBusModifierType_IBUS_HYPER_MASK,
+    IBusModifierType_IBUS_META_MASK, IBusModifierType_IBUS_MOD1_MASK,
+    IBusModifierType_IBUS_MOD3_MASK, IBusModifierType_IBUS_MOD4_MASK,
+    IBusModifierType_IBUS_SHIFT_MASK, IBusModifierType_IBUS_SUPER_MASK,
+};
 use ibus_sys::glib::guint;
-use ibus_sys::ibus_key::IBUS_KEY_VoidSymbol;
+use ibus_sys::ibus_key::{
+    IBUS_KEY_Hyper_R, IBUS_KEY_ISO_Level3_Shift, IBUS_KEY_Mode_switch, IBUS_KEY_Shift_L,
+    IBUS_KEY_VoidSymbol,
+};
 use ibus_sys::keys::ibus_keyval_from_name;
-use libakaza::keymap::{KeyPattern, KeyState};
+use libakaza::keymap::{KeyCommand, KeyPattern, KeyState, KeyStroke};
+
+use crate::input_mode::{get_input_mode_from_name, InputMode};
+
+/// "C-x C-k" のようなキーの組み合わせで、次のキーを待つ時間。
+pub(crate) const SEQUENCE_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Hash, PartialEq, Debug)]
 struct IBusKeyPattern {
     key_state: KeyState,
+    /// 入力モードの mode_code。None なら全ての入力モード。
+    mode: Option<i32>,
     keyval: u32,
     modifier: u32,
 }
@@ -19,17 +35,65 @@ struct IBusKeyPattern {
 impl Eq for IBusKeyPattern {}
 
 impl IBusKeyPattern {
-    fn new(key_state: KeyState, keyval: u32, modifier: u32) -> Self {
+    fn new(key_state: KeyState, mode: Option<i32>, keyval: u32, modifier: u32) -> Self {
         IBusKeyPattern {
             key_state,
+            mode,
             keyval,
             modifier,
         }
     }
 }
 
+/// 二つ以上のキーを順番に押す組み合わせ、またはその途中まで。
+#[derive(Hash, PartialEq, Eq, Debug)]
+struct IBusKeySequence {
+    key_state: KeyState,
+    mode: Option<i32>,
+    strokes: Vec<IBusKeyStroke>,
+}
+
+/// キーの組み合わせのうちの一つのキー。modifier は normalize_modifier したもの。
+#[derive(Hash, PartialEq, Eq, Debug, Clone, Copy)]
+struct IBusKeyStroke {
+    keyval: u32,
+    modifier: u32,
+}
+
+/// 組み合わせの途中まで押されたキー。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PendingKey {
+    pub keyval: u32,
+    pub keycode: u32,
+    pub modifiers: u32,
+}
+
+#[derive(Debug)]
+struct PendingSequence {
+    key_state: KeyState,
+    mode: i32,
+    keys: Vec<PendingKey>,
+    at: Instant,
+}
+
+/// キーの組み合わせの判定結果。
+#[derive(Debug, PartialEq)]
+pub enum KeySequence {
+    /// 組み合わせが完成した。
+    Command(Vec<KeyCommand>),
+    /// 組み合わせの続きのキーを待っている。
+    Pending,
+    /// 組み合わせではないので、通常のキー入力として扱う。
+    NotSequence,
+}
+
 pub struct IBusKeyMap {
-    keymap: HashMap<IBusKeyPattern, String>,
+    keymap: HashMap<IBusKeyPattern, Vec<KeyCommand>>,
+    /// 二つ以上のキーを順番に押す組み合わせ
+    sequences: HashMap<IBusKeySequence, Vec<KeyCommand>>,
+    /// sequences の途中までのキー
+    prefixes: HashSet<IBusKeySequence>,
+    pending: Option<PendingSequence>,
 }
 
 impl IBusKeyMap {
@@ -41,52 +105,264 @@ impl IBusKeyMap {
         unsafe { ibus_keyval_from_name(cs.as_ptr()) }
     }
 
-    pub(crate) fn new(keymap: HashMap<KeyPattern, String>) -> anyhow::Result<Self> {
-        let mut mapping: HashMap<IBusKeyPattern, String> = HashMap::new();
+    fn to_ibus_stroke(stroke: &KeyStroke) -> Option<IBusKeyStroke> {
+        let mut modifier = 0_u32;
+        if stroke.ctrl {
+            modifier |= IBusModifierType_IBUS_CONTROL_MASK;
+        }
+        if stroke.shift {
+            modifier |= IBusModifierType_IBUS_SHIFT_MASK;
+        }
+        if stroke.alt {
+            modifier |= IBusModifierType_IBUS_MOD1_MASK;
+        }
+        if stroke.super_ {
+            modifier |= IBusModifierType_IBUS_SUPER_MASK;
+        }
+        if stroke.hyper {
+            modifier |= IBusModifierType_IBUS_HYPER_MASK;
+        }
+        let keyval = Self::to_ibus_key(stroke.key.as_str());
+        if keyval == IBUS_KEY_VoidSymbol {
+            return None;
+        }
+        Some(IBusKeyStroke { keyval, modifier })
+    }
+
+    pub(crate) fn new(keymap: HashMap<KeyPattern, Vec<KeyCommand>>) -> anyhow::Result<Self> {
+        let mut mapping: HashMap<IBusKeyPattern, Vec<KeyCommand>> = HashMap::new();
+        let mut sequences = HashMap::new();
+        let mut prefixes = HashSet::new();
 
         for (key_pattern, command) in keymap {
-            let key = &key_pattern.key;
-            let mut modifier = 0_u32;
-            if key_pattern.ctrl {
-                modifier |= IBusModifierType_IBUS_CONTROL_MASK;
+            let Some(strokes) = key_pattern
+                .strokes
+                .iter()
+                .map(Self::to_ibus_stroke)
+                .collect::<Option<Vec<_>>>()
+            else {
+                error!("Unknown key symbol: {} {:?}", key_pattern, key_pattern);
+                continue;
+            };
+            let Ok(modes) = key_pattern
+                .modes
+                .iter()
+                .map(|name| get_input_mode_from_name(name).map(|mode| Some(mode.mode_code)))
+                .collect::<anyhow::Result<Vec<_>>>()
+            else {
+                error!("Unknown input mode: {} {:?}", key_pattern, key_pattern);
+                continue;
+            };
+            // modes を書いていなければ、全ての入力モードで使う。
+            let modes = if modes.is_empty() { vec![None] } else { modes };
+            trace!("Insert: {:?} {} {:?}", strokes, key_pattern, key_pattern);
+            for state in &key_pattern.states {
+                for mode in &modes {
+                    if let [stroke] = strokes.as_slice() {
+                        mapping.insert(
+                            IBusKeyPattern::new(*state, *mode, stroke.keyval, stroke.modifier),
+                            command.clone(),
+                        );
+                    } else {
+                        for len in 1..strokes.len() {
+                            prefixes.insert(IBusKeySequence {
+                                key_state: *state,
+                                mode: *mode,
+                                strokes: strokes[..len].to_vec(),
+                            });
+                        }
+                        sequences.insert(
+                            IBusKeySequence {
+                                key_state: *state,
+                                mode: *mode,
+                                strokes: strokes.clone(),
+                            },
+                            command.clone(),
+                        );
+                    }
+                }
             }
-            if key_pattern.shift {
-                modifier |= IBusModifierType_IBUS_SHIFT_MASK;
+        }
+
+        Ok(IBusKeyMap {
+            keymap: mapping,
+            sequences,
+            prefixes,
+            pending: None,
+        })
+    }
+
+    /// キーの組み合わせを一つずつ判定する。キーが押されるたびに、get より先に呼ぶ。
+    ///
+    /// 組み合わせの途中まで押されていたが、組み合わせにならなかったキーも返すので、
+    /// 呼び出し側は、それらを今のキーより先に通常のキー入力として扱う。
+    pub fn feed_sequence(
+        &mut self,
+        key_state: &KeyState,
+        input_mode: &InputMode,
+        key: PendingKey,
+        now: Instant,
+    ) -> (Vec<PendingKey>, KeySequence) {
+        // Control などの修飾キーだけを押した時は、組み合わせの途中でもそのまま待つ。
+        if (IBUS_KEY_Shift_L..=IBUS_KEY_Hyper_R).contains(&key.keyval)
+            || key.keyval == IBUS_KEY_ISO_Level3_Shift
+            || key.keyval == IBUS_KEY_Mode_switch
+        {
+            return (Vec::new(), KeySequence::NotSequence);
+        }
+
+        let stroke = IBusKeyStroke {
+            keyval: key.keyval,
+            modifier: Self::normalize_modifier(key.modifiers),
+        };
+        let mut replay = Vec::new();
+        let pending = match self.pending.take() {
+            Some(pending) if now - pending.at > SEQUENCE_TIMEOUT => {
+                // 時間が経ったので、途中まで押されていたキーは組み合わせにしない。
+                replay = pending.keys;
+                None
             }
-            let keyval = Self::to_ibus_key(key.as_str());
-            if keyval == IBUS_KEY_VoidSymbol {
-                error!("Unknown key symbol: {} {:?}", key, key_pattern);
-                continue;
+            pending => pending,
+        };
+
+        match pending {
+            Some(mut pending) => {
+                let mut strokes = pending
+                    .keys
+                    .iter()
+                    .map(|key| IBusKeyStroke {
+                        keyval: key.keyval,
+                        modifier: Self::normalize_modifier(key.modifiers),
+                    })
+                    .collect::<Vec<_>>();
+                strokes.push(stroke);
+                if let Some(command) = self.find_sequence(pending.key_state, pending.mode, &strokes)
+                {
+                    return (Vec::new(), KeySequence::Command(command.clone()));
+                }
+                if self.is_prefix(pending.key_state, pending.mode, &strokes) {
+                    pending.keys.push(key);
+                    pending.at = now;
+                    self.pending = Some(pending);
+                    return (Vec::new(), KeySequence::Pending);
+                }
+                (pending.keys, KeySequence::NotSequence)
             }
-            trace!("Insert: {} {} {} {:?}", modifier, keyval, key, key_pattern);
-            for state in &key_pattern.states {
-                mapping.insert(
-                    IBusKeyPattern::new(*state, keyval, modifier),
-                    command.clone(),
-                );
+            None => {
+                if self.is_prefix(*key_state, input_mode.mode_code, &[stroke]) {
+                    self.pending = Some(PendingSequence {
+                        key_state: *key_state,
+                        mode: input_mode.mode_code,
+                        keys: vec![key],
+                        at: now,
+                    });
+                    return (replay, KeySequence::Pending);
+                }
+                (replay, KeySequence::NotSequence)
             }
         }
+    }
+
+    /// 入力モードを指定した組み合わせを、指定していないものより優先する。
+    fn find_sequence(
+        &self,
+        key_state: KeyState,
+        mode: i32,
+        strokes: &[IBusKeyStroke],
+    ) -> Option<&Vec<KeyCommand>> {
+        [Some(mode), None].into_iter().find_map(|mode| {
+            self.sequences.get(&IBusKeySequence {
+                key_state,
+                mode,
+                strokes: strokes.to_vec(),
+            })
+        })
+    }
+
+    fn is_prefix(&self, key_state: KeyState, mode: i32, strokes: &[IBusKeyStroke]) -> bool {
+        [Some(mode), None].into_iter().any(|mode| {
+            self.prefixes.contains(&IBusKeySequence {
+                key_state,
+                mode,
+                strokes: strokes.to_vec(),
+            })
+        })
+    }
+
+    /// 組み合わせの途中まで押されたキーを捨てる。
+    pub fn reset_sequence(&mut self) {
+        self.pending = None;
+    }
+
+    /// 組み合わせの途中まで押されたキーを取り出す。
+    /// 次のキーが押されないまま時間が経った時に、通常のキー入力として扱うために使う。
+    pub fn take_pending(&mut self) -> Vec<PendingKey> {
+        self.pending
+            .take()
+            .map(|pending| pending.keys)
+            .unwrap_or_default()
+    }
 
-        Ok(IBusKeyMap { keymap: mapping })
+    /// キーイベントの修飾キーを、キーマップで使う Control, Shift, Alt, Super, Hyper だけにする。
+    ///
+    /// X11 では Alt は Mod1、Super は Mod4 として送られてくることがあるので、
+    /// それぞれ同じ修飾キーとして扱う。NumLock(Mod2) などは無視する。
+    fn normalize_modifier(modifier: u32) -> u32 {
+        let mut normalized =
+            modifier & (IBusModifierType_IBUS_CONTROL_MASK | IBusModifierType_IBUS_SHIFT_MASK);
+        if modifier & (IBusModifierType_IBUS_MOD1_MASK | IBusModifierType_IBUS_META_MASK) != 0 {
+            normalized |= IBusModifierType_IBUS_MOD1_MASK;
+        }
+        if modifier & (IBusModifierType_IBUS_MOD4_MASK | IBusModifierType_IBUS_SUPER_MASK) != 0 {
+            normalized |= IBusModifierType_IBUS_SUPER_MASK;
+        }
+        if modifier & (IBusModifierType_IBUS_MOD3_MASK | IBusModifierType_IBUS_HYPER_MASK) != 0 {
+            normalized |= IBusModifierType_IBUS_HYPER_MASK;
+        }
+        normalized
     }
 
-    pub fn get(&self, key_state: &KeyState, keyval: u32, modifier: u32) -> Option<&String> {
+    /// 入力モードを指定したキーを、指定していないキーより優先する。
+    pub fn get(
+        &self,
+        key_state: &KeyState,
+        input_mode: &InputMode,
+        keyval: u32,
+        modifier: u32,
+    ) -> Option<&Vec<KeyCommand>> {
         trace!("MODIFIER: {}", modifier);
-        self.keymap
-            .get(&IBusKeyPattern::new(*key_state, keyval, modifier))
+        let modifier = Self::normalize_modifier(modifier);
+        [Some(input_mode.mode_code), None]
+            .into_iter()
+            .find_map(|mode| {
+                self.keymap
+                    .get(&IBusKeyPattern::new(*key_state, mode, keyval, modifier))
+            })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use ibus_sys::core::IBusModifierType_IBUS_MOD2_MASK;
+    use ibus_sys::ibus_key::IBUS_KEY_Control_L;
+
+    use crate::input_mode::{INPUT_MODE_HIRAGANA, INPUT_MODE_KATAKANA};
+
     use super::*;
 
+    fn command(name: &str) -> Vec<KeyCommand> {
+        vec![KeyCommand {
+            name: name.to_string(),
+            args: vec![],
+        }]
+    }
+
     #[test]
     fn test_ibus_key_pattern_equality() {
         // IBusKeyPatternの等価性をテスト
-        let pattern1 = IBusKeyPattern::new(KeyState::PreComposition, 97, 0);
-        let pattern2 = IBusKeyPattern::new(KeyState::PreComposition, 97, 0);
-        let pattern3 = IBusKeyPattern::new(KeyState::PreComposition, 98, 0);
+        let pattern1 = IBusKeyPattern::new(KeyState::PreComposition, None, 97, 0);
+        let pattern2 = IBusKeyPattern::new(KeyState::PreComposition, None, 97, 0);
+        let pattern3 = IBusKeyPattern::new(KeyState::PreComposition, None, 98, 0);
 
         assert_eq!(pattern1, pattern2, "Identical patterns should be equal");
         assert_ne!(pattern1, pattern3, "Different keyval should not be equal");
@@ -95,8 +371,8 @@ mod tests {
     #[test]
     fn test_ibus_key_pattern_different_states() {
         // 異なるKeyStateでパターンが異なることを確認
-        let pattern1 = IBusKeyPattern::new(KeyState::PreComposition, 97, 0);
-        let pattern2 = IBusKeyPattern::new(KeyState::Composition, 97, 0);
+        let pattern1 = IBusKeyPattern::new(KeyState::PreComposition, None, 97, 0);
+        let pattern2 = IBusKeyPattern::new(KeyState::Composition, None, 97, 0);
 
         assert_ne!(
             pattern1, pattern2,
@@ -107,9 +383,10 @@ mod tests {
     #[test]
     fn test_ibus_key_pattern_different_modifiers() {
         // 異なるmodifierでパターンが異なることを確認
-        let pattern1 = IBusKeyPattern::new(KeyState::PreComposition, 97, 0);
+        let pattern1 = IBusKeyPattern::new(KeyState::PreComposition, None, 97, 0);
         let pattern2 = IBusKeyPattern::new(
             KeyState::PreComposition,
+            None,
             97,
             IBusModifierType_IBUS_CONTROL_MASK,
         );
@@ -141,10 +418,242 @@ mod tests {
         let keymap = HashMap::new();
         let ibus_keymap = IBusKeyMap::new(keymap).unwrap();
 
-        let result = ibus_keymap.get(&KeyState::PreComposition, 97, 0);
+        let result = ibus_keymap.get(&KeyState::PreComposition, &INPUT_MODE_HIRAGANA, 97, 0);
         assert!(result.is_none(), "Should return None for nonexistent key");
     }
 
+    #[test]
+    fn test_normalize_modifier() {
+        // Alt は Mod1 でも Meta でも同じ
+        assert_eq!(
+            IBusKeyMap::normalize_modifier(IBusModifierType_IBUS_META_MASK),
+            IBusModifierType_IBUS_MOD1_MASK
+        );
+        assert_eq!(
+            IBusKeyMap::normalize_modifier(
+                IBusModifierType_IBUS_MOD4_MASK | IBusModifierType_IBUS_SUPER_MASK
+            ),
+            IBusModifierType_IBUS_SUPER_MASK
+        );
+        // NumLock は無視する
+        assert_eq!(
+            IBusKeyMap::normalize_modifier(
+                IBusModifierType_IBUS_CONTROL_MASK | IBusModifierType_IBUS_MOD2_MASK
+            ),
+            IBusModifierType_IBUS_CONTROL_MASK
+        );
+    }
+
+    #[test]
+    fn test_ibus_keymap_alt_binding() {
+        let mut keymap = HashMap::new();
+        keymap.insert(
+            KeyPattern {
+                states: vec![KeyState::Composition],
+                modes: vec![],
+                strokes: vec![KeyStroke {
+                    ctrl: false,
+                    shift: false,
+                    alt: true,
+                    super_: false,
+                    hyper: false,
+                    key: "space".to_string(),
+                }],
+            },
+            command("cycle_segmentation"),
+        );
+        let ibus_keymap = IBusKeyMap::new(keymap).unwrap();
+        let space = IBusKeyMap::to_ibus_key("space");
+
+        assert_eq!(
+            ibus_keymap.get(
+                &KeyState::Composition,
+                &INPUT_MODE_HIRAGANA,
+                space,
+                IBusModifierType_IBUS_MOD1_MASK
+            ),
+            Some(&command("cycle_segmentation"))
+        );
+        // Alt なしの space とは衝突しない
+        assert_eq!(
+            ibus_keymap.get(&KeyState::Composition, &INPUT_MODE_HIRAGANA, space, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ibus_keymap_modes() {
+        let f7 = KeyStroke {
+            ctrl: false,
+            shift: false,
+            alt: false,
+            super_: false,
+            hyper: false,
+            key: "F7".to_string(),
+        };
+        let mut keymap = HashMap::new();
+        keymap.insert(
+            KeyPattern {
+                states: vec![KeyState::Composition],
+                modes: vec![],
+                strokes: vec![f7.clone()],
+            },
+            command("convert_to_full_katakana"),
+        );
+        keymap.insert(
+            KeyPattern {
+                states: vec![KeyState::Composition],
+                modes: vec!["Katakana".to_string()],
+                strokes: vec![f7.clone()],
+            },
+            command("convert_to_full_hiragana"),
+        );
+        // 存在しない入力モードのキーは無視する
+        keymap.insert(
+            KeyPattern {
+                states: vec![KeyState::Composition],
+                modes: vec!["Unknown".to_string()],
+                strokes: vec![f7],
+            },
+            command("escape"),
+        );
+        let ibus_keymap = IBusKeyMap::new(keymap).unwrap();
+        let f7 = IBusKeyMap::to_ibus_key("F7");
+
+        assert_eq!(
+            ibus_keymap.get(&KeyState::Composition, &INPUT_MODE_HIRAGANA, f7, 0),
+            Some(&command("convert_to_full_katakana"))
+        );
+        assert_eq!(
+            ibus_keymap.get(&KeyState::Composition, &INPUT_MODE_KATAKANA, f7, 0),
+            Some(&command("convert_to_full_hiragana"))
+        );
+    }
+
+    fn ctrl_stroke(key: &str) -> KeyStroke {
+        KeyStroke {
+            ctrl: true,
+            shift: false,
+            alt: false,
+            super_: false,
+            hyper: false,
+            key: key.to_string(),
+        }
+    }
+
+    fn sequence_keymap() -> IBusKeyMap {
+        let mut keymap = HashMap::new();
+        // C-x C-k
+        keymap.insert(
+            KeyPattern {
+                states: vec![KeyState::PreComposition],
+                modes: vec![],
+                strokes: vec![ctrl_stroke("x"), ctrl_stroke("k")],
+            },
+            command("set_input_mode_katakana"),
+        );
+        IBusKeyMap::new(keymap).unwrap()
+    }
+
+    fn key(name: &str, modifiers: u32) -> PendingKey {
+        PendingKey {
+            keyval: IBusKeyMap::to_ibus_key(name),
+            keycode: 0,
+            modifiers,
+        }
+    }
+
+    #[test]
+    fn test_feed_sequence() {
+        let mut keymap = sequence_keymap();
+        let now = Instant::now();
+        let state = KeyState::PreComposition;
+        let ctrl = IBusModifierType_IBUS_CONTROL_MASK;
+
+        assert_eq!(
+            keymap.feed_sequence(&state, &INPUT_MODE_HIRAGANA, key("x", ctrl), now),
+            (vec![], KeySequence::Pending)
+        );
+        // Control キーを押し直しても、組み合わせは続く
+        assert_eq!(
+            keymap.feed_sequence(
+                &state,
+                &INPUT_MODE_HIRAGANA,
+                PendingKey {
+                    keyval: IBUS_KEY_Control_L,
+                    keycode: 0,
+                    modifiers: ctrl,
+                },
+                now
+            ),
+            (vec![], KeySequence::NotSequence)
+        );
+        assert_eq!(
+            keymap.feed_sequence(&state, &INPUT_MODE_HIRAGANA, key("k", ctrl), now),
+            (
+                vec![],
+                KeySequence::Command(command("set_input_mode_katakana"))
+            )
+        );
+        // 組み合わせの外のキー
+        assert_eq!(
+            keymap.feed_sequence(&state, &INPUT_MODE_HIRAGANA, key("k", ctrl), now),
+            (vec![], KeySequence::NotSequence)
+        );
+    }
+
+    #[test]
+    fn test_feed_sequence_mismatch() {
+        let mut keymap = sequence_keymap();
+        let now = Instant::now();
+        let state = KeyState::PreComposition;
+        let ctrl = IBusModifierType_IBUS_CONTROL_MASK;
+
+        keymap.feed_sequence(&state, &INPUT_MODE_HIRAGANA, key("x", ctrl), now);
+        // 組み合わせにならなかったキーは、通常のキー入力として扱うために返す。
+        assert_eq!(
+            keymap.feed_sequence(&state, &INPUT_MODE_HIRAGANA, key("a", 0), now),
+            (vec![key("x", ctrl)], KeySequence::NotSequence)
+        );
+    }
+
+    #[test]
+    fn test_feed_sequence_timeout() {
+        let mut keymap = sequence_keymap();
+        let now = Instant::now();
+        let state = KeyState::PreComposition;
+        let ctrl = IBusModifierType_IBUS_CONTROL_MASK;
+
+        keymap.feed_sequence(&state, &INPUT_MODE_HIRAGANA, key("x", ctrl), now);
+        let later = now + SEQUENCE_TIMEOUT + Duration::from_millis(1);
+        assert_eq!(
+            keymap.feed_sequence(&state, &INPUT_MODE_HIRAGANA, key("x", ctrl), later),
+            (vec![key("x", ctrl)], KeySequence::Pending)
+        );
+        keymap.reset_sequence();
+        assert_eq!(
+            keymap.feed_sequence(&state, &INPUT_MODE_HIRAGANA, key("k", ctrl), later),
+            (vec![], KeySequence::NotSequence)
+        );
+    }
+
+    #[test]
+    fn test_take_pending() {
+        let mut keymap = sequence_keymap();
+        let now = Instant::now();
+        let state = KeyState::PreComposition;
+        let ctrl = IBusModifierType_IBUS_CONTROL_MASK;
+
+        assert_eq!(keymap.take_pending(), vec![]);
+        keymap.feed_sequence(&state, &INPUT_MODE_HIRAGANA, key("x", ctrl), now);
+        assert_eq!(keymap.take_pending(), vec![key("x", ctrl)]);
+        // 取り出した後は、組み合わせの途中ではない
+        assert_eq!(
+            keymap.feed_sequence(&state, &INPUT_MODE_HIRAGANA, key("k", ctrl), now),
+            (vec![], KeySequence::NotSequence)
+        );
+    }
+
     #[test]
     fn test_to_ibus_key_with_common_keys() {
         // よく使われるキー名がVoidSymbolではないことを確認
@@ -2,11 +2,15 @@
 
 // Expose modules for testing
 pub mod commands;
+pub mod config_watcher;
+pub mod content_type;
 pub mod context;
 pub mod current_state;
 pub mod input_mode;
 pub mod keymap;
+pub mod thumb_shift;
 pub mod ui;
+pub mod user_data_saver;
 pub mod wrapper_bindings;
 
 pub mod test_utils {
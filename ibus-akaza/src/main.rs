@@ -1,10 +1,11 @@
 #![allow(non_upper_case_globals)]
 
 use std::ffi::{c_char, c_void, CStr};
+use std::fs::OpenOptions;
 use std::io::Write;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
-use std::{fs::OpenOptions, thread, time};
+use std::time::{Duration, SystemTime};
 
 use anyhow::Result;
 use clap::Parser;
@@ -13,11 +14,13 @@ use log::{error, info, warn};
 use ibus_sys::core::ibus_main;
 use ibus_sys::engine::IBusEngine;
 use ibus_sys::glib::{gchar, guint};
-use libakaza::config::Config;
+use libakaza::config::{Config, CONFIG_ENV};
 use libakaza::engine::bigram_word_viterbi_engine::BigramWordViterbiEngineBuilder;
 use libakaza::user_side_data::user_data::UserData;
 
+use ibus_akaza_lib::config_watcher::spawn_config_watcher;
 use ibus_akaza_lib::context::AkazaContext;
+use ibus_akaza_lib::user_data_saver::{flush_user_data, spawn_user_data_saver};
 use ibus_akaza_lib::wrapper_bindings::{ibus_akaza_init, ibus_akaza_set_callback};
 
 unsafe extern "C" fn process_key_event(
@@ -42,9 +45,34 @@ unsafe extern "C" fn candidate_clicked(
     context_ref.do_candidate_clicked(engine, index, button, state);
 }
 
-unsafe extern "C" fn focus_in(context: *mut c_void, engine: *mut IBusEngine) {
+unsafe extern "C" fn focus_in(context: *mut c_void, engine: *mut IBusEngine, client: *const gchar) {
+    let client = if client.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(client).to_string_lossy().to_string())
+    };
     let context_ref = &mut *(context as *mut AkazaContext);
-    context_ref.do_focus_in(engine);
+    context_ref.do_focus_in(engine, client);
+}
+
+unsafe extern "C" fn focus_out(context: *mut c_void, engine: *mut IBusEngine) {
+    let context_ref = &mut *(context as *mut AkazaContext);
+    context_ref.do_focus_out(engine);
+}
+
+unsafe extern "C" fn reset(context: *mut c_void, engine: *mut IBusEngine) {
+    let context_ref = &mut *(context as *mut AkazaContext);
+    context_ref.do_reset(engine);
+}
+
+unsafe extern "C" fn enable(context: *mut c_void, engine: *mut IBusEngine) {
+    let context_ref = &mut *(context as *mut AkazaContext);
+    context_ref.do_enable(engine);
+}
+
+unsafe extern "C" fn disable(context: *mut c_void, engine: *mut IBusEngine) {
+    let context_ref = &mut *(context as *mut AkazaContext);
+    context_ref.do_disable(engine);
 }
 
 unsafe extern "C" fn property_activate(
@@ -64,9 +92,22 @@ unsafe extern "C" fn property_activate(
     context_ref.do_property_activate(engine, prop_name_str, prop_state);
 }
 
-fn load_user_data() -> Arc<Mutex<UserData>> {
+unsafe extern "C" fn set_content_type(
+    context: *mut c_void,
+    engine: *mut IBusEngine,
+    purpose: guint,
+    hints: guint,
+) {
+    let context_ref = &mut *(context as *mut AkazaContext);
+    context_ref.do_set_content_type(engine, purpose, hints);
+}
+
+fn load_user_data(config: &Config) -> Arc<Mutex<UserData>> {
     match UserData::load_from_default_path() {
-        Ok(user_data) => Arc::new(Mutex::new(user_data)),
+        Ok(mut user_data) => {
+            user_data.set_limits(config.user_data.clone());
+            Arc::new(Mutex::new(user_data))
+        }
         Err(err) => {
             error!("Cannot load user data: {}", err);
             Arc::new(Mutex::new(UserData::default()))
@@ -80,12 +121,20 @@ struct IBusAkazaArgs {
     #[clap(long)]
     ibus: bool,
 
+    /// 設定ファイルのパス。環境変数 AKAZA_CONFIG でも指定できる。
+    #[clap(long)]
+    config: Option<PathBuf>,
+
     #[clap(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
 }
 
 fn main() -> Result<()> {
     let arg: IBusAkazaArgs = IBusAkazaArgs::parse();
+    if let Some(config) = &arg.config {
+        // メニューから起動する akaza-conf なども、同じ設定ファイルを使うようにする。
+        std::env::set_var(CONFIG_ENV, config);
+    }
 
     let logpath = xdg::BaseDirectories::with_prefix("akaza")?
         .create_cache_directory("logs")?
@@ -117,12 +166,19 @@ fn main() -> Result<()> {
 
     unsafe {
         let sys_time = SystemTime::now();
-        let user_data = load_user_data();
-        let config = Config::load()?;
-        let akaza = BigramWordViterbiEngineBuilder::new(Config::load()?.engine)
+        let config = match Config::load() {
+            Ok(config) => config,
+            Err(err) => {
+                // 設定ファイルが壊れていても入力はできるように、デフォルト設定で起動する。
+                error!("Cannot load config, using default configuration: {:#}", err);
+                Config::default_config()
+            }
+        };
+        let user_data = load_user_data(&config);
+        let akaza = BigramWordViterbiEngineBuilder::new(config.engine.clone())
             .user_data(user_data.clone())
             .build()?;
-        let mut ac = AkazaContext::new(akaza, config);
+        let mut ac = AkazaContext::new(akaza, config.clone())?;
         let new_sys_time = SystemTime::now();
         let difference = new_sys_time.duration_since(sys_time)?;
         info!(
@@ -131,23 +187,15 @@ fn main() -> Result<()> {
         );
 
         // ユーザー辞書をバックグラウンドで保存するスレッド。
-        thread::Builder::new()
-            .name("user-data-save-thread".to_string())
-            .spawn(move || {
-                let interval = time::Duration::from_secs(3);
-
-                // スレッド内で雑に例外投げるとスレッドとまっちゃうので丁寧めに処理する。
-                loop {
-                    if let Ok(mut data) = user_data.lock() {
-                        if let Err(e) = data.write_user_files() {
-                            warn!("Cannot save user stats file: {}", e);
-                        }
-                    } else {
-                        warn!("Cannot get mutex for saving user data")
-                    };
-                    thread::sleep(interval);
-                }
-            })?;
+        // 学習が行われてから、3秒間次の学習がなければ保存する。
+        spawn_user_data_saver(user_data.clone(), Duration::from_secs(3))?;
+
+        // 設定ファイルや辞書の変更を監視するスレッド。
+        // ibus を再起動しなくても、akaza-conf などでの変更が反映されるようにする。
+        match spawn_config_watcher(config, Duration::from_millis(500)) {
+            Ok((reload_receiver, _)) => ac.set_reload_receiver(reload_receiver),
+            Err(err) => warn!("Cannot watch config files: {}", err),
+        }
 
         ibus_akaza_set_callback(
             &mut ac as *mut _ as *mut c_void,
@@ -155,6 +203,11 @@ fn main() -> Result<()> {
             candidate_clicked,
             focus_in,
             property_activate,
+            set_content_type,
+            focus_out,
+            reset,
+            enable,
+            disable,
         );
 
         ibus_akaza_init(arg.ibus);
@@ -162,9 +215,11 @@ fn main() -> Result<()> {
         info!("Enter the ibus_main()");
 
         // run main loop
+        // ibus-daemon との接続が切れた時(ibus restart など)や SIGTERM を受けた時に抜けてくる。
         ibus_main();
 
-        warn!("Should not reach here.");
+        info!("Exited from ibus_main(). Flushing user data...");
+        flush_user_data(&user_data);
     }
     Ok(())
 }
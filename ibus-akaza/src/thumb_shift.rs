@@ -0,0 +1,274 @@
+use std::time::{Duration, Instant};
+
+use libakaza::romkan::Thumb;
+
+/// 親指シフト入力で扱うキー。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThumbShiftKey {
+    /// テーブルに書かれている文字キー
+    Char(u32),
+    /// 親指キー
+    Thumb {
+        thumb: Thumb,
+        keyval: u32,
+        keycode: u32,
+    },
+}
+
+/// 同時押しを判定した結果。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThumbShiftOutput {
+    /// 文字キーの入力。thumb が Some なら、その親指キーと同時に押された。
+    Char { keycode: u32, thumb: Option<Thumb> },
+    /// 親指キーが単独で押された。親指キー本来の動作(変換やスペース)をする。
+    ThumbAlone { keyval: u32, keycode: u32 },
+}
+
+#[derive(Debug)]
+struct PressedThumb {
+    thumb: Thumb,
+    keyval: u32,
+    keycode: u32,
+    at: Instant,
+    /// 文字キーと同時に押されたか。押しっぱなしの間は続けてシフトする。
+    used: bool,
+}
+
+/// 文字キーと親指キーの押した時刻・離した時刻から、同時押しを判定する。
+///
+/// 文字キーを押しただけでは入力を決めず、timeout 以内に親指キーが押されるか、
+/// 文字キーが離されるか、次のキーが押されるまで待つ。
+#[derive(Debug, Default)]
+pub struct ThumbShift {
+    pending_char: Option<(u32, Instant)>,
+    thumb: Option<PressedThumb>,
+}
+
+impl ThumbShift {
+    pub fn press(
+        &mut self,
+        key: ThumbShiftKey,
+        now: Instant,
+        timeout: Duration,
+    ) -> Vec<ThumbShiftOutput> {
+        let mut outputs = Vec::new();
+        match key {
+            ThumbShiftKey::Char(keycode) => {
+                outputs.extend(self.flush_char());
+                match &mut self.thumb {
+                    Some(pressed) if pressed.used || now - pressed.at <= timeout => {
+                        pressed.used = true;
+                        outputs.push(ThumbShiftOutput::Char {
+                            keycode,
+                            thumb: Some(pressed.thumb),
+                        });
+                    }
+                    Some(pressed) => {
+                        // 親指キーを押してから時間が経っているので、親指キーは単独で押されたとみなす。
+                        pressed.used = true;
+                        outputs.push(ThumbShiftOutput::ThumbAlone {
+                            keyval: pressed.keyval,
+                            keycode: pressed.keycode,
+                        });
+                        self.pending_char = Some((keycode, now));
+                    }
+                    None => self.pending_char = Some((keycode, now)),
+                }
+            }
+            ThumbShiftKey::Thumb {
+                thumb,
+                keyval,
+                keycode,
+            } => {
+                outputs.extend(self.release_thumb());
+                let mut used = false;
+                if let Some((char_keycode, at)) = self.pending_char.take() {
+                    used = now - at <= timeout;
+                    outputs.push(ThumbShiftOutput::Char {
+                        keycode: char_keycode,
+                        thumb: used.then_some(thumb),
+                    });
+                }
+                self.thumb = Some(PressedThumb {
+                    thumb,
+                    keyval,
+                    keycode,
+                    at: now,
+                    used,
+                });
+            }
+        }
+        outputs
+    }
+
+    pub fn release(&mut self, key: ThumbShiftKey) -> Vec<ThumbShiftOutput> {
+        match key {
+            ThumbShiftKey::Char(keycode) => match self.pending_char {
+                Some((pending, _)) if pending == keycode => self.flush_char(),
+                _ => Vec::new(),
+            },
+            ThumbShiftKey::Thumb { keycode, .. } => match &self.thumb {
+                Some(pressed) if pressed.keycode == keycode => self.release_thumb(),
+                _ => Vec::new(),
+            },
+        }
+    }
+
+    /// 判定を待っている文字キーを、単独で押されたものとして確定する。
+    /// 親指シフトに関係のないキーが押された時に呼ぶ。
+    pub fn flush_char(&mut self) -> Vec<ThumbShiftOutput> {
+        self.pending_char
+            .take()
+            .map(|(keycode, _)| ThumbShiftOutput::Char {
+                keycode,
+                thumb: None,
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /// 判定を待っているキーがあるか。
+    pub fn is_pending(&self) -> bool {
+        self.pending_char.is_some() || self.thumb.is_some()
+    }
+
+    fn release_thumb(&mut self) -> Vec<ThumbShiftOutput> {
+        match self.thumb.take() {
+            Some(pressed) if !pressed.used => vec![ThumbShiftOutput::ThumbAlone {
+                keyval: pressed.keyval,
+                keycode: pressed.keycode,
+            }],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+    const LEFT: ThumbShiftKey = ThumbShiftKey::Thumb {
+        thumb: Thumb::Left,
+        keyval: 0xff22,
+        keycode: 94,
+    };
+
+    fn char_output(keycode: u32, thumb: Option<Thumb>) -> Vec<ThumbShiftOutput> {
+        vec![ThumbShiftOutput::Char { keycode, thumb }]
+    }
+
+    #[test]
+    fn test_char_alone() {
+        let mut state = ThumbShift::default();
+        let now = Instant::now();
+        assert_eq!(state.press(ThumbShiftKey::Char(30), now, TIMEOUT), vec![]);
+        assert_eq!(
+            state.release(ThumbShiftKey::Char(30)),
+            char_output(30, None)
+        );
+        assert!(!state.is_pending());
+    }
+
+    #[test]
+    fn test_char_then_thumb() {
+        let mut state = ThumbShift::default();
+        let now = Instant::now();
+        state.press(ThumbShiftKey::Char(30), now, TIMEOUT);
+        assert_eq!(
+            state.press(LEFT, now + Duration::from_millis(30), TIMEOUT),
+            char_output(30, Some(Thumb::Left))
+        );
+        assert_eq!(state.release(ThumbShiftKey::Char(30)), vec![]);
+        // 文字キーと同時に押したので、親指キーを離しても単独の入力にはならない。
+        assert_eq!(state.release(LEFT), vec![]);
+    }
+
+    #[test]
+    fn test_char_then_thumb_timeout() {
+        let mut state = ThumbShift::default();
+        let now = Instant::now();
+        state.press(ThumbShiftKey::Char(30), now, TIMEOUT);
+        assert_eq!(
+            state.press(LEFT, now + Duration::from_millis(300), TIMEOUT),
+            char_output(30, None)
+        );
+        assert_eq!(
+            state.release(LEFT),
+            vec![ThumbShiftOutput::ThumbAlone {
+                keyval: 0xff22,
+                keycode: 94
+            }]
+        );
+    }
+
+    #[test]
+    fn test_thumb_then_chars() {
+        let mut state = ThumbShift::default();
+        let now = Instant::now();
+        assert_eq!(state.press(LEFT, now, TIMEOUT), vec![]);
+        assert_eq!(
+            state.press(
+                ThumbShiftKey::Char(30),
+                now + Duration::from_millis(50),
+                TIMEOUT
+            ),
+            char_output(30, Some(Thumb::Left))
+        );
+        // 親指キーを押しっぱなしにしている間は、続けてシフトする。
+        assert_eq!(
+            state.press(
+                ThumbShiftKey::Char(31),
+                now + Duration::from_millis(500),
+                TIMEOUT
+            ),
+            char_output(31, Some(Thumb::Left))
+        );
+        assert_eq!(state.release(LEFT), vec![]);
+        assert!(!state.is_pending());
+    }
+
+    #[test]
+    fn test_thumb_held_then_char() {
+        let mut state = ThumbShift::default();
+        let now = Instant::now();
+        state.press(LEFT, now, TIMEOUT);
+        assert_eq!(
+            state.press(
+                ThumbShiftKey::Char(30),
+                now + Duration::from_millis(300),
+                TIMEOUT
+            ),
+            vec![ThumbShiftOutput::ThumbAlone {
+                keyval: 0xff22,
+                keycode: 94
+            }]
+        );
+        assert_eq!(state.release(LEFT), vec![]);
+        assert_eq!(
+            state.release(ThumbShiftKey::Char(30)),
+            char_output(30, None)
+        );
+    }
+
+    #[test]
+    fn test_next_char_flushes() {
+        let mut state = ThumbShift::default();
+        let now = Instant::now();
+        state.press(ThumbShiftKey::Char(30), now, TIMEOUT);
+        assert_eq!(
+            state.press(
+                ThumbShiftKey::Char(31),
+                now + Duration::from_millis(20),
+                TIMEOUT
+            ),
+            char_output(30, None)
+        );
+        // 先に押した文字キーを離しても、何も起きない。
+        assert_eq!(state.release(ThumbShiftKey::Char(30)), vec![]);
+        assert_eq!(
+            state.release(ThumbShiftKey::Char(31)),
+            char_output(31, None)
+        );
+    }
+}
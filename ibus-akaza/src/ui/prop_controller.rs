@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::path::Path;
 
 use anyhow::Result;
+use log::warn;
 
 use ibus_sys::core::to_gboolean;
 use ibus_sys::engine::{ibus_engine_register_properties, ibus_engine_update_property, IBusEngine};
@@ -9,22 +10,17 @@ use ibus_sys::glib::{g_object_ref_sink, gchar, gpointer};
 use ibus_sys::prop_list::{ibus_prop_list_append, ibus_prop_list_new, IBusPropList};
 use ibus_sys::property::{
     ibus_property_new, ibus_property_set_label, ibus_property_set_state,
-    ibus_property_set_sub_props, ibus_property_set_symbol, IBusPropState_PROP_STATE_CHECKED,
+    ibus_property_set_sub_props, ibus_property_set_symbol, ibus_property_set_tooltip,
+    ibus_property_set_visible, IBusPropState_PROP_STATE_CHECKED,
     IBusPropState_PROP_STATE_UNCHECKED, IBusPropType_PROP_TYPE_MENU, IBusPropType_PROP_TYPE_NORMAL,
-    IBusPropType_PROP_TYPE_RADIO, IBusProperty,
+    IBusPropType_PROP_TYPE_RADIO, IBusPropType_PROP_TYPE_TOGGLE, IBusProperty,
 };
 use ibus_sys::text::{IBusText, StringExt};
 use libakaza::config::{Config, DictConfig, DictEncoding, DictType, DictUsage};
+use libakaza::resource;
 
 use crate::input_mode::{get_all_input_modes, InputMode};
 
-type InitPropsResult = (
-    *mut IBusProperty,
-    *mut IBusPropList,
-    HashMap<String, *mut IBusProperty>,
-    HashMap<String, String>,
-);
-
 pub struct PropController {
     prop_list: *mut IBusPropList,
     /// input mode のメニューの親プロパティ。
@@ -33,20 +29,92 @@ pub struct PropController {
     prop_dict: HashMap<String, *mut IBusProperty>,
     /// user dict menu items (prop_name -> path).
     user_dict_map: HashMap<String, String>,
+    /// ライブ変換のトグル
+    live_conversion_prop: *mut IBusProperty,
+    /// ローマ字テーブルのメニュー
+    romkan_menu: ResourceMenu,
+    /// キーマップのメニュー
+    keymap_menu: ResourceMenu,
+    /// モデルが読み込めなかった時の警告。普段は表示しない。
+    model_warning_prop: *mut IBusProperty,
+}
+
+/// ローマ字テーブルやキーマップのように、ファイルを一つ選ぶメニュー。
+struct ResourceMenu {
+    /// prop_name -> (path, property)
+    items: HashMap<String, (String, *mut IBusProperty)>,
+}
+
+impl ResourceMenu {
+    /// prop_name に対応するファイルのパス
+    fn path(&self, prop_name: &str) -> Option<&String> {
+        self.items.get(prop_name).map(|(path, _)| path)
+    }
+
+    /// path のファイルだけをチェックし、他は外す。
+    fn select(&self, path: &str, engine: *mut IBusEngine) {
+        for (item_path, property) in self.items.values() {
+            let state = if item_path == path {
+                IBusPropState_PROP_STATE_CHECKED
+            } else {
+                IBusPropState_PROP_STATE_UNCHECKED
+            };
+            unsafe {
+                ibus_property_set_state(*property, state);
+                if !engine.is_null() {
+                    ibus_engine_update_property(engine, *property);
+                }
+            }
+        }
+    }
 }
 
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 impl PropController {
     pub fn new(initial_input_mode: InputMode, config: Config) -> Result<Self> {
-        let (input_mode_prop, prop_list, prop_dict, user_dict_map) =
-            Self::init_props(initial_input_mode, config)?;
+        unsafe {
+            let prop_list =
+                g_object_ref_sink(ibus_prop_list_new() as gpointer) as *mut IBusPropList;
+
+            let (input_mode_prop, prop_dict) =
+                Self::build_input_mode_menu(prop_list, initial_input_mode);
+            let model_warning_prop = Self::build_model_warning(prop_list);
+
+            // 変換の設定
+            let live_conversion_prop =
+                Self::build_live_conversion_toggle(prop_list, config.live_conversion);
+            let romkan_menu = Self::build_resource_menu(
+                prop_list,
+                "Romkan",
+                "ローマ字テーブル",
+                "romkan",
+                &config.romkan,
+            );
+            let keymap_menu = Self::build_resource_menu(
+                prop_list,
+                "Keymap",
+                "キーマップ",
+                "keymap",
+                &config.keymap,
+            );
+
+            // ユーザー辞書
+            let user_dict_map = Self::build_user_dict(prop_list, config)?;
+
+            // 設定ファイルを開くというやつ
+            Self::build_preference_menu(prop_list);
 
-        Ok(PropController {
-            prop_list,
-            input_mode_prop,
-            prop_dict,
-            user_dict_map,
-        })
+            Ok(PropController {
+                prop_list,
+                input_mode_prop,
+                prop_dict,
+                user_dict_map,
+                live_conversion_prop,
+                romkan_menu,
+                keymap_menu,
+                model_warning_prop,
+            })
+        }
     }
 
     /// ibus の do_focus_in のときに呼ばれる。
@@ -56,58 +124,138 @@ impl PropController {
         }
     }
 
-    /// タスクメニューからポップアップして選べるメニューを構築する。
+    /// タスクメニューからポップアップして選べる、入力モードのメニューを構築する。
     ///
     /// * `initial_input_mode`: 初期状態の input_mode
-    fn init_props(initial_input_mode: InputMode, config: Config) -> Result<InitPropsResult> {
-        unsafe {
-            let prop_list =
-                g_object_ref_sink(ibus_prop_list_new() as gpointer) as *mut IBusPropList;
+    unsafe fn build_input_mode_menu(
+        prop_list: *mut IBusPropList,
+        initial_input_mode: InputMode,
+    ) -> (*mut IBusProperty, HashMap<String, *mut IBusProperty>) {
+        let input_mode_prop = g_object_ref_sink(ibus_property_new(
+            c"InputMode".as_ptr() as *const gchar,
+            IBusPropType_PROP_TYPE_MENU,
+            format!("入力モード: {}", initial_input_mode.symbol).to_ibus_text(),
+            c"".as_ptr() as *const gchar,
+            "Switch input mode".to_ibus_text(),
+            to_gboolean(true),
+            to_gboolean(true),
+            IBusPropState_PROP_STATE_UNCHECKED,
+            std::ptr::null_mut(),
+        ) as gpointer) as *mut IBusProperty;
+        ibus_prop_list_append(prop_list, input_mode_prop);
 
-            let input_mode_prop = g_object_ref_sink(ibus_property_new(
-                c"InputMode".as_ptr() as *const gchar,
-                IBusPropType_PROP_TYPE_MENU,
-                format!("入力モード: {}", initial_input_mode.symbol).to_ibus_text(),
+        let props = g_object_ref_sink(ibus_prop_list_new() as gpointer) as *mut IBusPropList;
+        let mut prop_map: HashMap<String, *mut IBusProperty> = HashMap::new();
+        for input_mode in get_all_input_modes() {
+            let prop = g_object_ref_sink(ibus_property_new(
+                (input_mode.prop_name.to_string() + "\0").as_ptr() as *const gchar,
+                IBusPropType_PROP_TYPE_RADIO,
+                input_mode.label.to_ibus_text(),
                 c"".as_ptr() as *const gchar,
-                "Switch input mode".to_ibus_text(),
+                std::ptr::null_mut() as *mut IBusText,
                 to_gboolean(true),
                 to_gboolean(true),
-                IBusPropState_PROP_STATE_UNCHECKED,
+                if input_mode.mode_code == initial_input_mode.mode_code {
+                    IBusPropState_PROP_STATE_CHECKED
+                } else {
+                    IBusPropState_PROP_STATE_UNCHECKED
+                },
                 std::ptr::null_mut(),
             ) as gpointer) as *mut IBusProperty;
-            ibus_prop_list_append(prop_list, input_mode_prop);
-
-            let props = g_object_ref_sink(ibus_prop_list_new() as gpointer) as *mut IBusPropList;
-            let mut prop_map: HashMap<String, *mut IBusProperty> = HashMap::new();
-            for input_mode in get_all_input_modes() {
-                let prop = g_object_ref_sink(ibus_property_new(
-                    (input_mode.prop_name.to_string() + "\0").as_ptr() as *const gchar,
-                    IBusPropType_PROP_TYPE_RADIO,
-                    input_mode.label.to_ibus_text(),
-                    c"".as_ptr() as *const gchar,
-                    std::ptr::null_mut() as *mut IBusText,
-                    to_gboolean(true),
-                    to_gboolean(true),
-                    if input_mode.mode_code == initial_input_mode.mode_code {
-                        IBusPropState_PROP_STATE_CHECKED
-                    } else {
-                        IBusPropState_PROP_STATE_UNCHECKED
-                    },
-                    std::ptr::null_mut(),
-                ) as gpointer) as *mut IBusProperty;
-                prop_map.insert(input_mode.prop_name.to_string(), prop);
-                ibus_prop_list_append(props, prop);
-            }
-            ibus_property_set_sub_props(input_mode_prop, props);
+            prop_map.insert(input_mode.prop_name.to_string(), prop);
+            ibus_prop_list_append(props, prop);
+        }
+        ibus_property_set_sub_props(input_mode_prop, props);
 
-            // ユーザー辞書
-            let user_dict_map = Self::build_user_dict(prop_list, config)?;
+        (input_mode_prop, prop_map)
+    }
 
-            // 設定ファイルを開くというやつ
-            Self::build_preference_menu(prop_list);
+    unsafe fn build_model_warning(prop_list: *mut IBusPropList) -> *mut IBusProperty {
+        let prop = g_object_ref_sink(ibus_property_new(
+            c"ModelWarning".as_ptr() as *const gchar,
+            IBusPropType_PROP_TYPE_NORMAL,
+            "⚠ 言語モデルが読み込めません(辞書のみで変換中)".to_ibus_text(),
+            c"dialog-warning".as_ptr() as *const gchar,
+            std::ptr::null_mut() as *mut IBusText,
+            to_gboolean(false),
+            to_gboolean(false),
+            IBusPropState_PROP_STATE_UNCHECKED,
+            std::ptr::null_mut(),
+        ) as gpointer) as *mut IBusProperty;
+        ibus_prop_list_append(prop_list, prop);
+        prop
+    }
+
+    unsafe fn build_live_conversion_toggle(
+        prop_list: *mut IBusPropList,
+        live_conversion: bool,
+    ) -> *mut IBusProperty {
+        let prop = g_object_ref_sink(ibus_property_new(
+            c"LiveConversion".as_ptr() as *const gchar,
+            IBusPropType_PROP_TYPE_TOGGLE,
+            "ライブ変換".to_ibus_text(),
+            c"".as_ptr() as *const gchar,
+            "Live conversion".to_ibus_text(),
+            to_gboolean(true),
+            to_gboolean(true),
+            if live_conversion {
+                IBusPropState_PROP_STATE_CHECKED
+            } else {
+                IBusPropState_PROP_STATE_UNCHECKED
+            },
+            std::ptr::null_mut(),
+        ) as gpointer) as *mut IBusProperty;
+        ibus_prop_list_append(prop_list, prop);
+        prop
+    }
+
+    /// data ディレクトリの `{dir}/*.yml` から一つを選ぶメニューを構築する。
+    /// 各項目の prop_name は `{key}.{idx}` になる。
+    unsafe fn build_resource_menu(
+        prop_list: *mut IBusPropList,
+        key: &str,
+        label: &str,
+        dir: &str,
+        current: &str,
+    ) -> ResourceMenu {
+        let parent = g_object_ref_sink(ibus_property_new(
+            (key.to_string() + "\0").as_ptr() as *const gchar,
+            IBusPropType_PROP_TYPE_MENU,
+            label.to_ibus_text(),
+            c"".as_ptr() as *const gchar,
+            key.to_ibus_text(),
+            to_gboolean(true),
+            to_gboolean(true),
+            IBusPropState_PROP_STATE_UNCHECKED,
+            std::ptr::null_mut(),
+        ) as gpointer) as *mut IBusProperty;
+        ibus_prop_list_append(prop_list, parent);
 
-            Ok((input_mode_prop, prop_list, prop_map, user_dict_map))
+        let props = g_object_ref_sink(ibus_prop_list_new() as gpointer) as *mut IBusPropList;
+        let mut items = HashMap::new();
+        for (idx, (name, path)) in find_resource_files(dir).into_iter().enumerate() {
+            let prop_name = format!("{key}.{idx}");
+            let prop = g_object_ref_sink(ibus_property_new(
+                (prop_name.clone() + "\0").as_ptr() as *const gchar,
+                IBusPropType_PROP_TYPE_RADIO,
+                name.to_ibus_text(),
+                c"".as_ptr() as *const gchar,
+                std::ptr::null_mut() as *mut IBusText,
+                to_gboolean(true),
+                to_gboolean(true),
+                if path == current {
+                    IBusPropState_PROP_STATE_CHECKED
+                } else {
+                    IBusPropState_PROP_STATE_UNCHECKED
+                },
+                std::ptr::null_mut(),
+            ) as gpointer) as *mut IBusProperty;
+            ibus_prop_list_append(props, prop);
+            items.insert(prop_name, (path, prop));
         }
+        ibus_property_set_sub_props(parent, props);
+
+        ResourceMenu { items }
     }
 
     unsafe fn build_user_dict(
@@ -222,4 +370,79 @@ impl PropController {
     pub fn user_dict_path(&self, prop_name: &str) -> Option<&String> {
         self.user_dict_map.get(prop_name)
     }
+
+    pub fn romkan_path(&self, prop_name: &str) -> Option<&String> {
+        self.romkan_menu.path(prop_name)
+    }
+
+    pub fn keymap_path(&self, prop_name: &str) -> Option<&String> {
+        self.keymap_menu.path(prop_name)
+    }
+
+    /// 実際に使っている設定に、メニューのチェック状態を合わせる。
+    pub fn set_conversion_settings(
+        &self,
+        live_conversion: bool,
+        romkan: &str,
+        keymap: &str,
+        engine: *mut IBusEngine,
+    ) {
+        unsafe {
+            ibus_property_set_state(
+                self.live_conversion_prop,
+                if live_conversion {
+                    IBusPropState_PROP_STATE_CHECKED
+                } else {
+                    IBusPropState_PROP_STATE_UNCHECKED
+                },
+            );
+            if !engine.is_null() {
+                ibus_engine_update_property(engine, self.live_conversion_prop);
+            }
+        }
+        self.romkan_menu.select(romkan, engine);
+        self.keymap_menu.select(keymap, engine);
+    }
+
+    /// モデルが読み込めなかった場合は、その理由をメニューに表示する。
+    pub fn set_model_error(&self, model_error: Option<&str>, engine: *mut IBusEngine) {
+        unsafe {
+            ibus_property_set_visible(self.model_warning_prop, to_gboolean(model_error.is_some()));
+            ibus_property_set_tooltip(
+                self.model_warning_prop,
+                model_error.unwrap_or_default().to_ibus_text(),
+            );
+            if !engine.is_null() {
+                ibus_engine_update_property(engine, self.model_warning_prop);
+            }
+        }
+    }
+}
+
+/// data ディレクトリから `{dir}/*.yml` を探して、(名前, パス) の一覧を返す。
+/// 同じ名前のファイルが複数ある場合は、優先度の高いディレクトリのものを使う。
+fn find_resource_files(dir: &str) -> Vec<(String, String)> {
+    let files = match resource::list_resource_files(dir) {
+        Ok(files) => files,
+        Err(err) => {
+            warn!("Cannot list resource files: {}", err);
+            return Vec::new();
+        }
+    };
+
+    let mut result: Vec<(String, String)> = Vec::new();
+    for file in files {
+        if file.extension().is_none_or(|ext| ext != "yml") {
+            continue;
+        }
+        let Some(name) = file.file_stem().map(|it| it.to_string_lossy().to_string()) else {
+            continue;
+        };
+        if result.iter().any(|(it, _)| *it == name) {
+            continue;
+        }
+        result.push((name, file.to_string_lossy().to_string()));
+    }
+    result.sort();
+    result
 }
@@ -0,0 +1,113 @@
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use libakaza::user_side_data::user_data::UserData;
+
+/// 学習が行われるたびに通知を受け取り、入力が落ち着いたところでユーザーデータを保存するスレッドを起動する。
+///
+/// 一定間隔でのポーリングと違い、学習していない間は何もしない。
+pub fn spawn_user_data_saver(
+    user_data: Arc<Mutex<UserData>>,
+    debounce: Duration,
+) -> std::io::Result<JoinHandle<()>> {
+    let (tx, rx) = channel();
+    match user_data.lock() {
+        Ok(mut data) => data.set_save_notifier(tx),
+        Err(err) => warn!("Cannot get mutex for registering save notifier: {}", err),
+    }
+
+    thread::Builder::new()
+        .name("user-data-save-thread".to_string())
+        .spawn(move || {
+            run_debounced(&rx, debounce, || save_user_data(&user_data));
+            info!("user-data-save-thread finished");
+        })
+}
+
+/// 通知が来てから `debounce` の間、次の通知が来なければ `f` を実行する。
+/// 送信側がすべて閉じられたら、保留中の処理を実行してから戻る。
+fn run_debounced<F: FnMut()>(rx: &Receiver<()>, debounce: Duration, mut f: F) {
+    while rx.recv().is_ok() {
+        loop {
+            match rx.recv_timeout(debounce) {
+                Ok(()) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    f();
+                    return;
+                }
+            }
+        }
+        f();
+    }
+}
+
+fn save_user_data(user_data: &Arc<Mutex<UserData>>) {
+    // スレッド内で雑に例外投げるとスレッドとまっちゃうので丁寧めに処理する。
+    if let Ok(mut data) = user_data.lock() {
+        if let Err(e) = data.write_user_files() {
+            warn!("Cannot save user stats file: {}", e);
+        }
+    } else {
+        warn!("Cannot get mutex for saving user data")
+    };
+}
+
+/// 終了時に呼び出す。ジャーナルに溜まっている学習結果をスナップショットに書き出す。
+pub fn flush_user_data(user_data: &Arc<Mutex<UserData>>) {
+    match user_data.lock() {
+        Ok(mut data) => {
+            if let Err(e) = data.compact() {
+                warn!("Cannot flush user data: {}", e);
+            }
+        }
+        Err(err) => warn!("Cannot get mutex for flushing user data: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    #[test]
+    fn test_run_debounced_coalesces_notifications() {
+        let (tx, rx) = channel();
+        // 立て続けの学習はまとめて1回の保存になる
+        for _ in 0..5 {
+            tx.send(()).unwrap();
+        }
+        // 送信側を閉じると、保留中の保存を実行してから終了する
+        drop(tx);
+
+        let mut count = 0;
+        run_debounced(&rx, Duration::from_secs(60), || count += 1);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_run_debounced_saves_when_quiet() {
+        let (tx, rx) = channel();
+        let (saved_tx, saved_rx) = channel();
+        let handle = thread::spawn(move || {
+            run_debounced(&rx, Duration::ZERO, || saved_tx.send(()).unwrap());
+        });
+
+        // 次の通知が来なければ、終了を待たずに保存する
+        tx.send(()).unwrap();
+        saved_rx.recv().unwrap();
+        tx.send(()).unwrap();
+        saved_rx.recv().unwrap();
+
+        // 保留中の保存がなければ、閉じても保存しない
+        drop(tx);
+        handle.join().unwrap();
+        assert!(saved_rx.try_recv().is_err());
+    }
+}
@@ -31,7 +31,20 @@ pub(crate) type ibus_akaza_callback_property_activate = unsafe extern "C" fn(
     prop_state: guint,
 );
 
+/// client: IBus のクライアント名。 focus_in_id に対応していない IBus では NULL になる。
 pub(crate) type ibus_akaza_callback_focus_in =
+    unsafe extern "C" fn(context: *mut c_void, engine: *mut IBusEngine, client: *const gchar);
+
+/// purpose, hints: IBusInputPurpose と IBusInputHints の値。
+pub(crate) type ibus_akaza_callback_set_content_type = unsafe extern "C" fn(
+    context: *mut c_void,
+    engine: *mut IBusEngine,
+    purpose: guint,
+    hints: guint,
+);
+
+/// focus_out, reset, enable, disable で使う。
+pub(crate) type ibus_akaza_callback_engine_event =
     unsafe extern "C" fn(context: *mut c_void, engine: *mut IBusEngine);
 
 extern "C" {
@@ -44,5 +57,10 @@ extern "C" {
         candidate_cb: ibus_akaza_callback_candidate_clicked,
         focus_in_cb: ibus_akaza_callback_focus_in,
         property_activate: ibus_akaza_callback_property_activate,
+        set_content_type: ibus_akaza_callback_set_content_type,
+        focus_out: ibus_akaza_callback_engine_event,
+        reset: ibus_akaza_callback_engine_event,
+        enable: ibus_akaza_callback_engine_event,
+        disable: ibus_akaza_callback_engine_event,
     );
 }
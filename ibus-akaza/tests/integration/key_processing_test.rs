@@ -1,6 +1,9 @@
-use ibus_akaza_lib::commands::ibus_akaza_commands_map;
+use std::fs;
+
+use ibus_akaza_lib::commands::{candidate_index_in_page, ibus_akaza_commands_map};
 use ibus_akaza_lib::input_mode::{get_all_input_modes, get_input_mode_from_prop_name};
 use ibus_akaza_lib::test_utils::mock_engine;
+use libakaza::keymap::{KeyCommand, Keymap};
 
 /// キー処理の基本的な統合テスト
 /// IBusEngineの実際の機能は使わず、モジュール間の連携をテスト
@@ -52,17 +55,46 @@ fn test_command_names_are_consistent() {
         "Should have at least 5 input mode commands"
     );
 
-    // 数字コマンドが全て press_number_ で始まることを確認
-    let number_commands: Vec<_> = commands
-        .keys()
-        .filter(|&k| k.starts_with("press_number_"))
-        .collect();
+    // 数字キーで候補を選ぶコマンドは、引数で番号を受け取る一つのコマンド
+    assert!(commands.contains_key("select_candidate"));
+    assert!(!commands.keys().any(|k| k.starts_with("press_number_")));
+}
 
+#[test]
+fn test_select_candidate_args() {
+    // 引数の番号が、ページ内の何番目の候補になるかを確認
+    let args = |nn: &str| vec![nn.to_string()];
+    assert_eq!(candidate_index_in_page(&args("1")), Some(0));
+    assert_eq!(candidate_index_in_page(&args("3")), Some(2));
+    assert_eq!(candidate_index_in_page(&args("9")), Some(8));
+    assert_eq!(candidate_index_in_page(&args("0")), Some(9));
+    assert_eq!(candidate_index_in_page(&args("10")), None);
+    assert_eq!(candidate_index_in_page(&[]), None);
+}
+
+#[test]
+fn test_press_number_alias() -> anyhow::Result<()> {
+    // 以前のキーマップの press_number_N は、select_candidate の引数 [N] として読み込まれる
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("keymap.yml");
+    fs::write(
+        &path,
+        r#"
+keys:
+  - states: [Conversion]
+    key: ["3"]
+    command: press_number_3
+"#,
+    )?;
+    let keymap = Keymap::load(path.to_str().unwrap())?;
+    let commands = keymap.values().next().unwrap();
     assert_eq!(
-        number_commands.len(),
-        10,
-        "Should have exactly 10 number commands (0-9)"
+        commands,
+        &vec![KeyCommand::new("select_candidate", vec!["3".to_string()])]
     );
+    assert!(ibus_akaza_commands_map().contains_key(commands[0].name.as_str()));
+    assert_eq!(candidate_index_in_page(&commands[0].args), Some(2));
+    Ok(())
 }
 
 #[test]
@@ -1,4 +1,6 @@
 #include <ibus.h>
+#include <glib-unix.h>
+#include <signal.h>
 #include <string.h>
 #include <stdbool.h>
 #include <stdio.h>
@@ -11,6 +13,11 @@ static ibus_akaza_callback_key_event global_key_event_cb = NULL;
 static ibus_akaza_callback_candidate_clicked global_candidate_clicked_cb = NULL;
 static ibus_akaza_callback_focus_in global_focus_in_cb = NULL;
 static ibus_akaza_callback_property_activate global_property_activate_cb = NULL;
+static ibus_akaza_callback_set_content_type global_set_content_type_cb = NULL;
+static ibus_akaza_callback_engine_event global_focus_out_cb = NULL;
+static ibus_akaza_callback_engine_event global_reset_cb = NULL;
+static ibus_akaza_callback_engine_event global_enable_cb = NULL;
+static ibus_akaza_callback_engine_event global_disable_cb = NULL;
 
 #define IBUS_TYPE_AKAZA_ENGINE        \
         (ibus_akaza_engine_get_type ())
@@ -32,11 +39,20 @@ static gboolean ibus_akaza_engine_process_key_event(IBusEngine *engine,
 static void ibus_akaza_engine_focus_in(
     IBusEngine *engine
 );
+static void ibus_akaza_engine_focus_out(IBusEngine *engine);
+static void ibus_akaza_engine_reset(IBusEngine *engine);
+static void ibus_akaza_engine_enable(IBusEngine *engine);
+static void ibus_akaza_engine_disable(IBusEngine *engine);
 static void ibus_akaza_engine_property_activate(
     IBusEngine *engine,
     const gchar *prop_name,
     guint prop_state
 );
+static void ibus_akaza_engine_set_content_type(
+    IBusEngine *engine,
+    guint purpose,
+    guint hints
+);
 
 G_DEFINE_TYPE(IBusAkazaEngine, ibus_akaza_engine, IBUS_TYPE_ENGINE)
 
@@ -60,8 +76,56 @@ static gboolean ibus_akaza_engine_candidate_clicked(
 static void ibus_akaza_engine_focus_in(
     IBusEngine *engine
 ) {
-   global_focus_in_cb(global_context, engine);
+   global_focus_in_cb(global_context, engine, NULL);
+}
+
+// 入力中にウィンドウを切り替えた時などに、入力中の文字列が残らないようにする。
+static void ibus_akaza_engine_focus_out(IBusEngine *engine) {
+   global_focus_out_cb(global_context, engine);
+}
+
+static void ibus_akaza_engine_reset(IBusEngine *engine) {
+   global_reset_cb(global_context, engine);
+}
+
+static void ibus_akaza_engine_enable(IBusEngine *engine) {
+   global_enable_cb(global_context, engine);
+}
+
+static void ibus_akaza_engine_disable(IBusEngine *engine) {
+   global_disable_cb(global_context, engine);
+}
+
+#if IBUS_CHECK_VERSION(1, 5, 27)
+// client にはアプリケーション名が入る。アプリケーションごとの設定に使う。
+static void ibus_akaza_engine_focus_in_id(
+    IBusEngine *engine,
+    const gchar *object_path,
+    const gchar *client
+) {
+   global_focus_in_cb(global_context, engine, client);
+}
+
+// focus_in_id を受け取るには、has-focus-id を指定してエンジンを作る必要がある。
+static IBusEngine *ibus_akaza_create_engine(
+    IBusFactory *factory,
+    const gchar *engine_name,
+    gpointer user_data
+) {
+  static guint engine_id = 0;
+  gchar *object_path =
+      g_strdup_printf("/org/freedesktop/IBus/Engine/Akaza/%u", ++engine_id);
+  IBusEngine *engine = (IBusEngine *)g_object_new(
+      IBUS_TYPE_AKAZA_ENGINE,
+      "engine-name", engine_name,
+      "object-path", object_path,
+      "connection", ibus_service_get_connection(IBUS_SERVICE(factory)),
+      "has-focus-id", TRUE,
+      NULL);
+  g_free(object_path);
+  return engine;
 }
+#endif
 
 static void ibus_akaza_engine_property_activate(
     IBusEngine *engine,
@@ -71,6 +135,15 @@ static void ibus_akaza_engine_property_activate(
    global_property_activate_cb(global_context, engine, prop_name, prop_state);
 }
 
+// パスワード欄などでは入力のしかたを変える。
+static void ibus_akaza_engine_set_content_type(
+    IBusEngine *engine,
+    guint purpose,
+    guint hints
+) {
+   global_set_content_type_cb(global_context, engine, purpose, hints);
+}
+
 static gboolean ibus_akaza_engine_process_key_event(IBusEngine *engine,
                                                       guint keyval,
                                                       guint keycode,
@@ -82,6 +155,12 @@ static void ibus_disconnected_cb(IBusBus *bus, gpointer user_data) {
   ibus_quit();
 }
 
+// SIGTERM/SIGINT を受けたら main loop を抜けて、Rust 側で終了処理をさせる。
+static gboolean ibus_akaza_signal_cb(gpointer user_data) {
+  ibus_quit();
+  return G_SOURCE_REMOVE;
+}
+
 static void ibus_akaza_engine_class_init(IBusAkazaEngineClass *klass) {
   IBusObjectClass *ibus_object_class = IBUS_OBJECT_CLASS(klass);
   IBusEngineClass *engine_class = IBUS_ENGINE_CLASS(klass);
@@ -92,7 +171,15 @@ static void ibus_akaza_engine_class_init(IBusAkazaEngineClass *klass) {
   engine_class->process_key_event = ibus_akaza_engine_process_key_event;
   engine_class->candidate_clicked = ibus_akaza_engine_candidate_clicked;
   engine_class->focus_in = ibus_akaza_engine_focus_in;
+#if IBUS_CHECK_VERSION(1, 5, 27)
+  engine_class->focus_in_id = ibus_akaza_engine_focus_in_id;
+#endif
+  engine_class->focus_out = ibus_akaza_engine_focus_out;
+  engine_class->reset = ibus_akaza_engine_reset;
+  engine_class->enable = ibus_akaza_engine_enable;
+  engine_class->disable = ibus_akaza_engine_disable;
   engine_class->property_activate = ibus_akaza_engine_property_activate;
+  engine_class->set_content_type = ibus_akaza_engine_set_content_type;
 }
 
 
@@ -101,13 +188,23 @@ void ibus_akaza_set_callback(
     ibus_akaza_callback_key_event* key_event_cb,
     ibus_akaza_callback_candidate_clicked* candidate_cb,
     ibus_akaza_callback_focus_in* focus_in_cb,
-    ibus_akaza_callback_property_activate* property_activate_cb
+    ibus_akaza_callback_property_activate* property_activate_cb,
+    ibus_akaza_callback_set_content_type* set_content_type_cb,
+    ibus_akaza_callback_engine_event* focus_out_cb,
+    ibus_akaza_callback_engine_event* reset_cb,
+    ibus_akaza_callback_engine_event* enable_cb,
+    ibus_akaza_callback_engine_event* disable_cb
 ) {
     global_context = context;
     global_key_event_cb = key_event_cb;
     global_candidate_clicked_cb = candidate_cb;
     global_focus_in_cb = focus_in_cb;
     global_property_activate_cb = property_activate_cb;
+    global_set_content_type_cb = set_content_type_cb;
+    global_focus_out_cb = focus_out_cb;
+    global_reset_cb = reset_cb;
+    global_enable_cb = enable_cb;
+    global_disable_cb = disable_cb;
 }
 
 void ibus_akaza_init(bool ibus) {
@@ -118,10 +215,15 @@ void ibus_akaza_init(bool ibus) {
   struct IBusBus* bus = ibus_bus_new();
   g_object_ref_sink(bus);
   g_signal_connect(bus, "disconnected", G_CALLBACK(ibus_disconnected_cb), NULL);
+  g_unix_signal_add(SIGTERM, ibus_akaza_signal_cb, NULL);
+  g_unix_signal_add(SIGINT, ibus_akaza_signal_cb, NULL);
 
   IBusFactory * factory = ibus_factory_new(ibus_bus_get_connection(bus));
   g_object_ref_sink(factory);
   ibus_factory_add_engine(factory, "akaza", IBUS_TYPE_AKAZA_ENGINE);
+#if IBUS_CHECK_VERSION(1, 5, 27)
+  g_signal_connect(factory, "create-engine", G_CALLBACK(ibus_akaza_create_engine), NULL);
+#endif
 
   if (ibus) {
     ibus_bus_request_name(bus, "org.freedesktop.IBus.Akaza", 0);
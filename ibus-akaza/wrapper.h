@@ -4,10 +4,12 @@
 
 typedef gboolean (*ibus_akaza_callback_key_event)(void* ctx, IBusEngine* engine, guint keyval, guint keycode, guint modifiers);
 typedef gboolean (*ibus_akaza_callback_candidate_clicked)(void* ctx, IBusEngine* engine, guint index, guint button, guint state);
-typedef void (*ibus_akaza_callback_focus_in)(void* ctx, IBusEngine* engine);
+typedef void (*ibus_akaza_callback_focus_in)(void* ctx, IBusEngine* engine, const gchar *client);
 typedef void (*ibus_akaza_callback_property_activate)(void* ctx, IBusEngine* engine, const gchar *prop_name, guint prop_state);
+typedef void (*ibus_akaza_callback_engine_event)(void* ctx, IBusEngine* engine);
+typedef void (*ibus_akaza_callback_set_content_type)(void* ctx, IBusEngine* engine, guint purpose, guint hints);
 
-void ibus_akaza_set_callback(void* ctx, ibus_akaza_callback_key_event* cb, ibus_akaza_callback_candidate_clicked*, ibus_akaza_callback_focus_in*, ibus_akaza_callback_property_activate*);
+void ibus_akaza_set_callback(void* ctx, ibus_akaza_callback_key_event* cb, ibus_akaza_callback_candidate_clicked*, ibus_akaza_callback_focus_in*, ibus_akaza_callback_property_activate*, ibus_akaza_callback_set_content_type*, ibus_akaza_callback_engine_event* focus_out, ibus_akaza_callback_engine_event* reset, ibus_akaza_callback_engine_event* enable, ibus_akaza_callback_engine_event* disable);
 
 typedef struct {
   IBusEngine parent;
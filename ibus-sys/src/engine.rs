@@ -32,9 +32,46 @@ extern "C" {
         visible: gboolean,
     );
 
+    #[doc = " ibus_engine_forward_key_event:\n @engine: An IBusEngine.\n @keyval: KeySym.\n @keycode: keyboard scancode.\n @state: Key modifier flags.\n\n Forward the key event."]
+    pub fn ibus_engine_forward_key_event(
+        engine: *mut IBusEngine,
+        keyval: guint,
+        keycode: guint,
+        state: guint,
+    );
+
     pub fn ibus_engine_register_properties(engine: *mut IBusEngine, prop_list: *mut IBusPropList);
 
     pub fn ibus_engine_update_property(engine: *mut IBusEngine, prop: *mut IBusProperty);
 }
 
 pub type IBusEngine = [u64; 11usize];
+
+// 入力欄の種類。 ibus_engine_get_content_type や set_content_type で渡される。
+pub type IBusInputPurpose = ::std::os::raw::c_uint;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_FREE_FORM: IBusInputPurpose = 0;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_ALPHA: IBusInputPurpose = 1;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_DIGITS: IBusInputPurpose = 2;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_NUMBER: IBusInputPurpose = 3;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_PHONE: IBusInputPurpose = 4;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_URL: IBusInputPurpose = 5;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_EMAIL: IBusInputPurpose = 6;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_NAME: IBusInputPurpose = 7;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_PASSWORD: IBusInputPurpose = 8;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_PIN: IBusInputPurpose = 9;
+pub const IBusInputPurpose_IBUS_INPUT_PURPOSE_TERMINAL: IBusInputPurpose = 10;
+
+pub type IBusInputHints = ::std::os::raw::c_uint;
+pub const IBusInputHints_IBUS_INPUT_HINT_NONE: IBusInputHints = 0;
+pub const IBusInputHints_IBUS_INPUT_HINT_SPELLCHECK: IBusInputHints = 1;
+pub const IBusInputHints_IBUS_INPUT_HINT_NO_SPELLCHECK: IBusInputHints = 2;
+pub const IBusInputHints_IBUS_INPUT_HINT_WORD_COMPLETION: IBusInputHints = 4;
+pub const IBusInputHints_IBUS_INPUT_HINT_LOWERCASE: IBusInputHints = 8;
+pub const IBusInputHints_IBUS_INPUT_HINT_UPPERCASE_CHARS: IBusInputHints = 16;
+pub const IBusInputHints_IBUS_INPUT_HINT_UPPERCASE_WORDS: IBusInputHints = 32;
+pub const IBusInputHints_IBUS_INPUT_HINT_UPPERCASE_SENTENCES: IBusInputHints = 64;
+pub const IBusInputHints_IBUS_INPUT_HINT_INHIBIT_OSK: IBusInputHints = 128;
+pub const IBusInputHints_IBUS_INPUT_HINT_VERTICAL_WRITING: IBusInputHints = 256;
+pub const IBusInputHints_IBUS_INPUT_HINT_EMOJI: IBusInputHints = 512;
+pub const IBusInputHints_IBUS_INPUT_HINT_NO_EMOJI: IBusInputHints = 1024;
+pub const IBusInputHints_IBUS_INPUT_HINT_PRIVATE: IBusInputHints = 2048;
@@ -39,6 +39,10 @@ extern "C" {
     pub fn ibus_property_set_symbol(prop: *mut IBusProperty, symbol: *mut IBusText);
 
     pub fn ibus_property_set_icon(prop: *mut IBusProperty, icon: *const gchar);
+
+    pub fn ibus_property_set_tooltip(prop: *mut IBusProperty, tooltip: *mut IBusText);
+
+    pub fn ibus_property_set_visible(prop: *mut IBusProperty, visible: gboolean);
 }
 
 #[repr(C)]
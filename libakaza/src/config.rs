@@ -1,33 +1,57 @@
 /*
 ---
-dicts:
-  - path: /usr/share/skk/SKK-JISYO.okinawa
-    encoding: euc-jp
-    dict_type: skk
+version: 1
+engine:
+  dicts:
+    - path: /usr/share/skk/SKK-JISYO.okinawa
+      encoding: EucJp
+      dict_type: SKK
+      usage: Normal
  */
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::fs::File;
 use std::io::{BufReader, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
 use log::{info, warn};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
 
 use DictEncoding::Utf8;
 
 use crate::config::DictUsage::{Normal, SingleTerm};
+pub use crate::config_migration::{migrate, CONFIG_VERSION};
 use crate::graph::reranking::ReRankingWeights;
+use crate::resource;
 use crate::resource::detect_resource_path;
+use crate::romkan::RomKanRule;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Default)]
+/// 設定ファイルのパスを指定する環境変数
+pub const CONFIG_ENV: &str = "AKAZA_CONFIG";
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default, Clone)]
 pub struct Config {
+    /// 設定ファイルの形式のバージョン。読み込む時に古い形式から移行する。
+    #[serde(default)]
+    pub version: u64,
+
+    /// romkan, keymap, model を探す追加のディレクトリ。XDG の data ディレクトリより優先される。
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub data_dirs: Vec<String>,
+
     /// ローマ字かな変換テーブルの指定
     /// "default", "kana", etc.
     #[serde(default = "default_romkan")]
     pub romkan: String,
 
+    /// romkan のテーブルに追加・上書きするローマ字かな変換の規則。null を指定すると規則を消す。
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub romkan_mapping: BTreeMap<String, Option<RomKanRule>>,
+
     /// キーマップテーブルの指定
     /// "default", "atok", etc.
     #[serde(default = "default_keymap")]
@@ -39,6 +63,39 @@ pub struct Config {
     /// ライブ変換
     #[serde(default = "default_live_conversion")]
     pub live_conversion: bool,
+
+    /// 変換範囲の指定のしかた
+    #[serde(default)]
+    pub input_style: InputStyle,
+
+    /// ユーザー学習データの設定
+    #[serde(default)]
+    pub user_data: UserDataConfig,
+
+    /// 句読点のスタイル
+    #[serde(default)]
+    pub punctuation: PunctuationStyle,
+
+    /// 入力中にフォーカスが外れた時、入力中の文字列をどうするか
+    #[serde(default)]
+    pub preedit_on_focus_out: PreeditPolicy,
+
+    /// アプリケーションごとの設定。上から順に見て、最初にマッチしたものを使う。
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub applications: Vec<ApplicationConfig>,
+}
+
+/// romkan などのデフォルト値は data_dirs から探すので、デシリアライズする前に登録しておく。
+fn register_data_dirs(value: &Value) {
+    let dirs = match value.get("data_dirs") {
+        Some(Value::Sequence(dirs)) => dirs
+            .iter()
+            .filter_map(Value::as_str)
+            .map(PathBuf::from)
+            .collect(),
+        _ => Vec::new(),
+    };
+    resource::set_config_data_dirs(dirs);
 }
 
 fn default_romkan() -> String {
@@ -85,52 +142,351 @@ impl Config {
     pub fn load_from_file(path: &str) -> Result<Self> {
         let file = File::open(path)?;
         let reader = BufReader::new(file);
-        let config: Config = serde_yaml::from_reader(reader)?;
+        let mut value: Value = serde_yaml::from_reader(reader)?;
+        migrate(&mut value)?;
+        register_data_dirs(&value);
+        let config: Config = serde_yaml::from_value(value)?;
         Ok(config)
     }
 
+    /// ユーザーの設定ファイルのパス。
+    /// 環境変数 AKAZA_CONFIG が設定されていれば、そのパスを使う。
     pub fn file_name() -> Result<PathBuf> {
+        if let Some(path) = std::env::var_os(CONFIG_ENV).filter(|it| !it.is_empty()) {
+            return Ok(PathBuf::from(path));
+        }
         let basedir = xdg::BaseDirectories::with_prefix("akaza")?;
         Ok(basedir.get_config_file("config.yml"))
     }
 
+    /// 現在の形式で設定ファイルに書き込む。
+    /// システム全体の設定とデフォルト値から変えた項目だけを書く。
     pub fn save(&self) -> Result<()> {
-        let file_name = Self::file_name()?;
-        let yml = serde_yaml::to_string(self)?;
+        self.save_to_file(&Self::file_name()?, &Self::system_file_names()?)
+    }
+
+    fn save_to_file(&self, file_name: &Path, system_files: &[PathBuf]) -> Result<()> {
+        let base = Self::load_from_files(system_files).unwrap_or_else(|err| {
+            warn!(
+                "Cannot load system config, using default configuration: {:#}",
+                err
+            );
+            Self::default_config()
+        });
+        let mut value = diff_yaml(serde_yaml::to_value(self)?, &serde_yaml::to_value(&base)?)
+            .unwrap_or_else(|| Value::Mapping(Mapping::new()));
+        if let Value::Mapping(value) = &mut value {
+            value.insert("version".into(), CONFIG_VERSION.into());
+        }
+        let yml = serde_yaml::to_string(&value)?;
         if let Some(parent) = file_name.parent() {
             std::fs::create_dir_all(parent)
                 .with_context(|| format!("Cannot create config dir: {}", parent.display()))?;
         }
         info!("Write to file: {}", file_name.display());
-        let mut fp = File::create(&file_name)
+        let mut fp = File::create(file_name)
             .with_context(|| format!("Cannot open config file: {}", file_name.display()))?;
         fp.write_all(yml.as_bytes())
             .with_context(|| format!("Cannot write config file: {}", file_name.display()))?;
         Ok(())
     }
 
+    /// IBus のクライアント名に対応するアプリケーションごとの設定を探す。
+    pub fn application_config(&self, client: &str) -> Option<&ApplicationConfig> {
+        self.applications
+            .iter()
+            .find(|app| app.client.is_match(client))
+    }
+
+    /// 設定を読み込む。
+    ///
+    /// システム全体の設定ファイル(`/etc/xdg/akaza/config.yml` など)の上に、
+    /// ユーザーの設定ファイルを重ねたものを使う。
     pub fn load() -> Result<Self> {
-        let configfile = Self::file_name()?;
-        let config = match Config::load_from_file(configfile.to_str().unwrap()) {
-            Ok(config) => config,
-            Err(err) => {
-                warn!(
-                    "Cannot load configuration file: {} {}",
-                    configfile.to_string_lossy(),
-                    err
-                );
-                let config: Config = serde_yaml::from_str("").unwrap();
-                info!("Loaded default configuration: {:?}", config);
-                return Ok(config);
+        Self::load_from_path(&Self::file_name()?)
+    }
+
+    /// システム全体の設定ファイルの上に、path の設定ファイルを重ねて読み込む。
+    /// path が存在しなければ、システム全体の設定とデフォルト値だけを使う。
+    pub fn load_from_path(path: &Path) -> Result<Self> {
+        let mut files = Self::system_file_names()?;
+        files.push(path.to_path_buf());
+        Self::load_from_files(&files)
+    }
+
+    /// files を先頭から順に重ねて読み込む。後ろのファイルほど優先される。
+    /// 存在しないファイルは無視するが、読めないファイルや壊れたファイルはエラーにする。
+    fn load_from_files(files: &[PathBuf]) -> Result<Self> {
+        let mut merged = Value::Mapping(Mapping::new());
+        for file in files {
+            if !file.exists() {
+                continue;
             }
-        };
-        info!(
-            "Loaded config file: {}, {:?}",
-            configfile.to_string_lossy(),
-            config
-        );
+            let reader = BufReader::new(
+                File::open(file)
+                    .with_context(|| format!("Cannot open config file: {}", file.display()))?,
+            );
+            let mut value: Value = serde_yaml::from_reader(reader)
+                .with_context(|| format!("Cannot parse config file: {}", file.display()))?;
+            // ファイルごとに形式が違うかもしれないので、重ねる前に移行しておく。
+            migrate(&mut value)
+                .with_context(|| format!("Cannot migrate config file: {}", file.display()))?;
+            info!("Loaded config file: {}", file.display());
+            merge_yaml(&mut merged, value);
+        }
+        if let Value::Mapping(merged) = &mut merged {
+            merged.insert("version".into(), CONFIG_VERSION.into());
+        }
+        register_data_dirs(&merged);
+        let config: Config = serde_yaml::from_value(merged).with_context(|| {
+            format!(
+                "Invalid configuration: {}",
+                files
+                    .iter()
+                    .map(|it| it.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+        info!("Loaded configuration: {:?}", config);
         Ok(config)
     }
+
+    /// 設定ファイルを使わない、デフォルトの設定。
+    pub fn default_config() -> Self {
+        Config {
+            version: CONFIG_VERSION,
+            ..serde_yaml::from_str("").unwrap()
+        }
+    }
+
+    /// システム全体の設定ファイル。優先度の低いものから順に並べる。
+    pub fn system_file_names() -> Result<Vec<PathBuf>> {
+        let basedir = xdg::BaseDirectories::with_prefix("akaza")?;
+        Ok(basedir
+            .get_config_dirs()
+            .into_iter()
+            .rev()
+            .map(|dir| dir.join("config.yml"))
+            .collect())
+    }
+}
+
+/// base に overlay を重ねる。
+/// マップはキーごとに再帰的に重ね、それ以外(リストなど)は overlay の値で置き換える。
+fn merge_yaml(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        // 空のファイル
+        (_, Value::Null) => {}
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_yaml(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// value のうち base と違う部分だけを返す。違いがなければ None。
+/// マップはキーごとに再帰的に比べ、それ以外(リストなど)は丸ごと比べる。
+fn diff_yaml(value: Value, base: &Value) -> Option<Value> {
+    match (value, base) {
+        (Value::Mapping(value), Value::Mapping(base)) => {
+            let diff: Mapping = value
+                .into_iter()
+                .filter_map(|(key, value)| match base.get(&key) {
+                    Some(base_value) => diff_yaml(value, base_value).map(|it| (key, it)),
+                    None => Some((key, value)),
+                })
+                .collect();
+            (!diff.is_empty()).then_some(Value::Mapping(diff))
+        }
+        (value, base) => (&value != base).then_some(value),
+    }
+}
+
+/// 句読点のスタイル
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default, Clone, Copy)]
+pub enum PunctuationStyle {
+    /// 、。
+    #[default]
+    Japanese,
+    /// ，．
+    Western,
+    /// ，。
+    Mixed,
+}
+
+impl PunctuationStyle {
+    /// 読点
+    pub fn touten(&self) -> &'static str {
+        match self {
+            PunctuationStyle::Japanese => "、",
+            PunctuationStyle::Western | PunctuationStyle::Mixed => "，",
+        }
+    }
+
+    /// 句点
+    pub fn kuten(&self) -> &'static str {
+        match self {
+            PunctuationStyle::Japanese | PunctuationStyle::Mixed => "。",
+            PunctuationStyle::Western => "．",
+        }
+    }
+}
+
+/// 変換範囲の指定のしかた
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default, Clone, Copy)]
+pub enum InputStyle {
+    /// 入力した文字列全体を変換する
+    #[default]
+    Normal,
+    /// SKK のように、大文字で変換範囲と送り仮名の始まりを指定する。
+    /// 変換範囲の外で入力したかなは、すぐに確定する。ライブ変換とサジェストは使わない。
+    Skk,
+}
+
+/// 入力中にフォーカスが外れた時の、入力中の文字列の扱い
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default, Clone, Copy)]
+pub enum PreeditPolicy {
+    /// 表示されている内容(変換中なら変換結果)のまま確定する
+    #[default]
+    Commit,
+    /// ひらがなにして確定する
+    CommitHiragana,
+    /// 捨てる
+    Discard,
+}
+
+/// アプリケーションごとの設定。指定しなかった項目は全体の設定に従う。
+///
+/// ```yaml
+/// applications:
+///   - client: "kitty|alacritty|gnome-terminal"
+///     input_mode: Alphanumeric
+///   - client: "slack"
+///     live_conversion: true
+/// ```
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default, Clone)]
+pub struct ApplicationConfig {
+    /// IBus のクライアント名(例: "gtk3-im:firefox")にマッチする正規表現
+    pub client: ClientPattern,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub live_conversion: Option<bool>,
+
+    /// フォーカスした時の入力モード。"Hiragana", "Alphanumeric" など。
+    /// 一度入力モードを切り替えると、以降はそのアプリケーションで最後に使った入力モードになる。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_mode: Option<String>,
+
+    /// キーマップファイルのパス
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub punctuation: Option<PunctuationStyle>,
+}
+
+/// アプリケーションごとの設定の client。
+/// フォーカスが変わるたびに使うので、設定を読み込んだ時に一度だけ正規表現をコンパイルする。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct ClientPattern {
+    source: String,
+    regex: Result<Regex, regex::Error>,
+}
+
+impl ClientPattern {
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// 正規表現として正しくなければ、そのエラー。
+    pub fn error(&self) -> Option<&regex::Error> {
+        self.regex.as_ref().err()
+    }
+
+    /// 正しくない正規表現は、どのクライアントにもマッチしない。
+    pub fn is_match(&self, client: &str) -> bool {
+        self.regex
+            .as_ref()
+            .is_ok_and(|regex| regex.is_match(client))
+    }
+}
+
+impl From<String> for ClientPattern {
+    fn from(source: String) -> Self {
+        let regex = Regex::new(&source);
+        if let Err(err) = &regex {
+            warn!("Invalid client pattern {:?}: {}", source, err);
+        }
+        ClientPattern { source, regex }
+    }
+}
+
+impl From<ClientPattern> for String {
+    fn from(pattern: ClientPattern) -> Self {
+        pattern.source
+    }
+}
+
+impl Default for ClientPattern {
+    fn default() -> Self {
+        ClientPattern::from(String::new())
+    }
+}
+
+impl PartialEq for ClientPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Display for ClientPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// ユーザー学習データの設定。
+/// 統計データは保存のたびにファイル全体を書き直すので、エントリー数に上限を設ける。
+/// 上限を超えたら、出現回数が少なく長い間使われていないものから削除する。0 なら無制限。
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct UserDataConfig {
+    #[serde(default = "default_max_unigram_entries")]
+    pub max_unigram_entries: usize,
+    #[serde(default = "default_max_bigram_entries")]
+    pub max_bigram_entries: usize,
+    #[serde(default = "default_max_skip_bigram_entries")]
+    pub max_skip_bigram_entries: usize,
+}
+
+impl Default for UserDataConfig {
+    fn default() -> Self {
+        UserDataConfig {
+            max_unigram_entries: default_max_unigram_entries(),
+            max_bigram_entries: default_max_bigram_entries(),
+            max_skip_bigram_entries: default_max_skip_bigram_entries(),
+        }
+    }
+}
+
+fn default_max_unigram_entries() -> usize {
+    50_000
+}
+
+fn default_max_bigram_entries() -> usize {
+    100_000
+}
+
+fn default_max_skip_bigram_entries() -> usize {
+    100_000
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Default, Clone)]
@@ -156,7 +512,13 @@ fn default_dict_cache() -> bool {
 }
 
 fn default_model() -> String {
-    detect_resource_path("model", "default").unwrap()
+    // モデルがまだインストールされていなくても起動できるように、見つからなければユーザーの data ディレクトリを指しておく。
+    detect_resource_path("model", "default").unwrap_or_else(|err| {
+        warn!("{:#}", err);
+        resource::user_resource_path("model", "default")
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or_default()
+    })
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Default, Clone)]
@@ -276,3 +638,118 @@ impl DictUsage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_application_config() -> Result<()> {
+        let config: Config = serde_yaml::from_str(
+            r#"
+applications:
+  - client: "kitty|gnome-terminal"
+    input_mode: Alphanumeric
+  - client: "slack"
+    live_conversion: true
+    punctuation: Western
+"#,
+        )?;
+
+        let terminal = config.application_config("gtk3-im:kitty").unwrap();
+        assert_eq!(terminal.input_mode, Some("Alphanumeric".to_string()));
+        assert_eq!(terminal.live_conversion, None);
+
+        let slack = config.application_config("gtk3-im:slack").unwrap();
+        assert_eq!(slack.live_conversion, Some(true));
+        assert_eq!(slack.punctuation, Some(PunctuationStyle::Western));
+
+        assert!(config.application_config("gtk3-im:firefox").is_none());
+        assert!(config.applications[0].client.error().is_none());
+
+        // 正しくない正規表現は、読み込んだ時にエラーが分かり、どのクライアントにもマッチしない
+        let config: Config = serde_yaml::from_str("applications:\n  - client: \"kitty(\"\n")?;
+        assert!(config.applications[0].client.error().is_some());
+        assert!(config.application_config("kitty(").is_none());
+        assert_eq!(config.applications[0].client.as_str(), "kitty(");
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_from_files() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let system = tmpdir.path().join("system.yml");
+        let user = tmpdir.path().join("user.yml");
+        std::fs::write(
+            &system,
+            "live_conversion: true\npunctuation: Western\nuser_data:\n  max_unigram_entries: 10\n",
+        )?;
+        std::fs::write(
+            &user,
+            "punctuation: Mixed\nuser_data:\n  max_bigram_entries: 20\n",
+        )?;
+
+        let config = Config::load_from_files(&[system.clone(), user.clone()])?;
+        assert!(config.live_conversion);
+        assert_eq!(config.punctuation, PunctuationStyle::Mixed);
+        assert_eq!(config.user_data.max_unigram_entries, 10);
+        assert_eq!(config.user_data.max_bigram_entries, 20);
+
+        // 存在しないファイルは無視する
+        let config = Config::load_from_files(&[system.clone(), tmpdir.path().join("none.yml")])?;
+        assert_eq!(config.punctuation, PunctuationStyle::Western);
+
+        // 壊れたファイルはエラーにする
+        std::fs::write(&user, "punctuation: [")?;
+        assert!(Config::load_from_files(&[system, user]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_to_file() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let system = tmpdir.path().join("system.yml");
+        let user = tmpdir.path().join("user.yml");
+        std::fs::write(
+            &system,
+            "live_conversion: true\nuser_data:\n  max_unigram_entries: 10\n",
+        )?;
+
+        let mut config = Config::load_from_files(&[system.clone(), user.clone()])?;
+        config.punctuation = PunctuationStyle::Mixed;
+        config.user_data.max_bigram_entries = 20;
+        config.save_to_file(&user, std::slice::from_ref(&system))?;
+
+        // システム全体の設定やデフォルト値と同じ項目は書かない
+        let value: Value = serde_yaml::from_str(&std::fs::read_to_string(&user)?)?;
+        let expected: Value = serde_yaml::from_str(&format!(
+            "punctuation: Mixed\nuser_data:\n  max_bigram_entries: 20\nversion: {}\n",
+            CONFIG_VERSION
+        ))?;
+        assert_eq!(value, expected);
+
+        // 読み直すと同じ設定になる
+        assert_eq!(Config::load_from_files(&[system, user])?, config);
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_style() -> Result<()> {
+        let config: Config = serde_yaml::from_str("live_conversion: false")?;
+        assert_eq!(config.input_style, InputStyle::Normal);
+
+        let config: Config = serde_yaml::from_str("input_style: Skk")?;
+        assert_eq!(config.input_style, InputStyle::Skk);
+        Ok(())
+    }
+
+    #[test]
+    fn test_preedit_on_focus_out() -> Result<()> {
+        let config: Config = serde_yaml::from_str("live_conversion: false")?;
+        assert_eq!(config.preedit_on_focus_out, PreeditPolicy::Commit);
+
+        let config: Config = serde_yaml::from_str("preedit_on_focus_out: Discard")?;
+        assert_eq!(config.preedit_on_focus_out, PreeditPolicy::Discard);
+        Ok(())
+    }
+}
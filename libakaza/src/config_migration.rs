@@ -0,0 +1,152 @@
+use anyhow::{bail, Result};
+use log::{info, warn};
+use serde_yaml::{Mapping, Value};
+
+/// 設定ファイルの形式のバージョン。
+/// 設定項目の名前や意味を変えたら上げて、MIGRATIONS に移行処理を追加する。
+pub const CONFIG_VERSION: u64 = 1;
+
+/// MIGRATIONS[n] は、バージョン n の設定をバージョン n + 1 の形式に書き換える。
+const MIGRATIONS: [fn(&mut Mapping); CONFIG_VERSION as usize] = [migrate_v0];
+
+/// 読み込んだ設定ファイルを、現在の形式に書き換える。
+/// version が書かれていない設定ファイルは、バージョン 0 とみなす。
+pub fn migrate(value: &mut Value) -> Result<()> {
+    let Value::Mapping(mapping) = value else {
+        // 空のファイルなど
+        return Ok(());
+    };
+
+    let version = match mapping.get("version") {
+        None | Some(Value::Null) => 0,
+        Some(Value::Number(n)) if n.as_u64().is_some() => n.as_u64().unwrap(),
+        Some(other) => bail!("Invalid config version: {:?}", other),
+    };
+    if version > CONFIG_VERSION {
+        warn!(
+            "Config version {} is newer than supported version {}. Some settings may be ignored.",
+            version, CONFIG_VERSION
+        );
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!("Migrating config from version {} to {}", from, from + 1);
+        migration(mapping);
+    }
+    mapping.insert("version".into(), CONFIG_VERSION.into());
+    Ok(())
+}
+
+/// バージョン 0(version が書かれていない頃)の設定の移行。
+///
+/// - トップレベルの `dicts` を `engine.dicts` に移す。
+/// - `usage` がない辞書は無効扱いになってしまうので、通常辞書にする。
+/// - `euc-jp` や `skk` のような古い書き方を、今の名前にする。
+/// - `skip_bigram_weight` が追加される前の `reranking_weights` には、以前と同じ変換結果になるように 0 を入れる。
+fn migrate_v0(config: &mut Mapping) {
+    if let Some(dicts) = config.remove("dicts") {
+        let engine = config
+            .entry("engine".into())
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+        if let Value::Mapping(engine) = engine {
+            engine.entry("dicts".into()).or_insert(dicts);
+        }
+    }
+
+    let Some(Value::Mapping(engine)) = config.get_mut("engine") else {
+        return;
+    };
+
+    if let Some(Value::Sequence(dicts)) = engine.get_mut("dicts") {
+        for dict in dicts.iter_mut() {
+            let Value::Mapping(dict) = dict else {
+                continue;
+            };
+            dict.entry("usage".into()).or_insert("Normal".into());
+            rename_value(dict, "encoding", &[("euc-jp", "EucJp"), ("utf-8", "Utf8")]);
+            rename_value(dict, "dict_type", &[("skk", "SKK")]);
+        }
+    }
+
+    if let Some(Value::Mapping(weights)) = engine.get_mut("reranking_weights") {
+        weights
+            .entry("skip_bigram_weight".into())
+            .or_insert(0.0.into());
+    }
+}
+
+/// key の値が names のいずれか(大文字小文字は区別しない)なら、新しい名前に置き換える。
+fn rename_value(mapping: &mut Mapping, key: &str, names: &[(&str, &str)]) {
+    let Some(Value::String(value)) = mapping.get_mut(key) else {
+        return;
+    };
+    if let Some((_, new)) = names
+        .iter()
+        .find(|(old, _)| old.eq_ignore_ascii_case(value))
+    {
+        *value = new.to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_v0() -> Result<()> {
+        let mut value: Value = serde_yaml::from_str(
+            r#"
+dicts:
+  - path: /usr/share/skk/SKK-JISYO.okinawa
+    encoding: euc-jp
+    dict_type: skk
+  - path: /usr/share/skk/SKK-JISYO.emoji
+    usage: SingleTerm
+engine:
+  reranking_weights:
+    bigram_weight: 1.0
+    length_weight: 2.0
+    unknown_bigram_weight: 1.0
+"#,
+        )?;
+        migrate(&mut value)?;
+
+        let expected: Value = serde_yaml::from_str(
+            r#"
+engine:
+  reranking_weights:
+    bigram_weight: 1.0
+    length_weight: 2.0
+    unknown_bigram_weight: 1.0
+    skip_bigram_weight: 0.0
+  dicts:
+    - path: /usr/share/skk/SKK-JISYO.okinawa
+      encoding: EucJp
+      dict_type: SKK
+      usage: Normal
+    - path: /usr/share/skk/SKK-JISYO.emoji
+      usage: SingleTerm
+version: 1
+"#,
+        )?;
+        assert_eq!(value, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_current_version() -> Result<()> {
+        // 現在のバージョンの設定は書き換えない。
+        let src = r#"
+version: 1
+engine:
+  dicts:
+    - path: /usr/share/skk/SKK-JISYO.L
+"#;
+        let mut value: Value = serde_yaml::from_str(src)?;
+        migrate(&mut value)?;
+        assert_eq!(value, serde_yaml::from_str::<Value>(src)?);
+
+        let mut value: Value = serde_yaml::from_str("version: foo")?;
+        assert!(migrate(&mut value).is_err());
+        Ok(())
+    }
+}
@@ -130,44 +130,16 @@ pub fn load_dict(dict: &DictConfig) -> Result<HashMap<String, Vec<String>>> {
 mod tests {
     use std::collections::HashSet;
     use std::io::Write;
-    use std::sync::{Mutex, OnceLock};
-    use std::{env, thread, time};
+    use std::{thread, time};
 
     use crate::config::DictUsage;
+    use crate::test_utils::{env_lock, EnvVarGuard};
     use anyhow::Result;
     use log::LevelFilter;
     use tempfile::{tempdir, NamedTempFile};
 
     use super::*;
 
-    struct EnvVarGuard {
-        key: &'static str,
-        prev: Option<String>,
-    }
-
-    impl EnvVarGuard {
-        fn set(key: &'static str, value: &std::path::Path) -> Self {
-            let prev = env::var(key).ok();
-            env::set_var(key, value);
-            Self { key, prev }
-        }
-    }
-
-    impl Drop for EnvVarGuard {
-        fn drop(&mut self) {
-            if let Some(value) = &self.prev {
-                env::set_var(self.key, value);
-            } else {
-                env::remove_var(self.key);
-            }
-        }
-    }
-
-    fn env_lock() -> &'static Mutex<()> {
-        static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
-        LOCK.get_or_init(|| Mutex::new(()))
-    }
-
     #[test]
     fn test_load_dict_ex() -> Result<()> {
         let _lock = env_lock().lock().unwrap();
@@ -5,7 +5,7 @@ use std::path::Path;
 
 use anyhow::{Context, Result};
 use encoding_rs::Encoding;
-use log::info;
+use log::{info, warn};
 use regex::Regex;
 
 use crate::dict::merge_dict::merge_dict;
@@ -23,7 +23,14 @@ pub fn read_skkdict(
     let file = File::open(path).with_context(|| path.to_string_lossy().to_string())?;
     let mut buf: Vec<u8> = Vec::new();
     BufReader::new(file).read_to_end(&mut buf)?;
-    let (decoded, _, _) = encoding.decode(buf.as_slice());
+    let (decoded, _, had_errors) = encoding.decode(buf.as_slice());
+    if had_errors {
+        warn!(
+            "{} contains invalid byte sequences for {}",
+            path.to_string_lossy(),
+            encoding.name()
+        );
+    }
     let decoded = decoded.to_string();
     parse_skkdict(decoded.as_str())
 }
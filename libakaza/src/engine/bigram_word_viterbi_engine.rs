@@ -1,12 +1,14 @@
 use std::fmt::{Debug, Formatter};
 use std::ops::Range;
+use std::path::Path;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
-use anyhow::Result;
-use log::{error, info};
+use anyhow::{bail, Result};
+use log::{error, info, warn};
 
 use crate::config::{DictConfig, DictEncoding, DictType, DictUsage, EngineConfig};
+use crate::cost::calc_cost;
 use crate::dict::loader::{load_dicts, load_dicts_with_cache};
 use crate::engine::base::HenkanEngine;
 use crate::graph::candidate::Candidate;
@@ -19,11 +21,14 @@ use crate::kana_kanji::base::KanaKanjiDict;
 use crate::kana_kanji::marisa_kana_kanji_dict::MarisaKanaKanjiDict;
 use crate::kana_trie::cedarwood_kana_trie::CedarwoodKanaTrie;
 use crate::lm::base::{SystemBigramLM, SystemSkipBigramLM, SystemUnigramLM};
-use crate::lm::system_bigram::MarisaSystemBigramLM;
+use crate::lm::system_bigram::{MarisaSystemBigramLM, MarisaSystemBigramLMBuilder};
 use crate::lm::system_skip_bigram::MarisaSystemSkipBigramLM;
-use crate::lm::system_unigram_lm::MarisaSystemUnigramLM;
+use crate::lm::system_unigram_lm::{MarisaSystemUnigramLM, MarisaSystemUnigramLMBuilder};
 use crate::user_side_data::user_data::UserData;
 
+/// モデルのディレクトリに必要なファイル。skip_bigram.model は任意。
+pub const REQUIRED_MODEL_FILES: [&str; 3] = ["unigram.model", "bigram.model", "SKK-JISYO.akaza"];
+
 /// バイグラムのビタビベースかな漢字変換エンジンです。
 /// 単語バイグラムを採用しています。
 pub struct BigramWordViterbiEngine<U: SystemUnigramLM, B: SystemBigramLM, KD: KanaKanjiDict> {
@@ -33,6 +38,8 @@ pub struct BigramWordViterbiEngine<U: SystemUnigramLM, B: SystemBigramLM, KD: Ka
     pub user_data: Arc<Mutex<UserData>>,
     reranking_weights: ReRankingWeights,
     skip_bigram_lm: Option<Rc<MarisaSystemSkipBigramLM>>,
+    /// モデルが読み込めず、辞書だけで変換している場合はその理由。
+    model_error: Option<String>,
 }
 
 impl<U: SystemUnigramLM, B: SystemBigramLM, KD: KanaKanjiDict> Debug
@@ -68,15 +75,35 @@ impl<U: SystemUnigramLM, B: SystemBigramLM, KD: KanaKanjiDict> HenkanEngine
         force_ranges: Option<&[Range<usize>]>,
         k: usize,
     ) -> Result<Vec<KBestPath>> {
-        let lattice = self.to_lattice(yomi, force_ranges)?;
+        self.convert_k_best_with_context(yomi, force_ranges, k, None)
+    }
+}
+
+impl<U: SystemUnigramLM, B: SystemBigramLM, KD: KanaKanjiDict> BigramWordViterbiEngine<U, B, KD> {
+    /// モデルが読み込めなかった場合、その理由を返す。
+    /// この場合、言語モデルを使わずに辞書だけで変換している。
+    pub fn model_error(&self) -> Option<&str> {
+        self.model_error.as_deref()
+    }
+
+    /// 部分確定した後の残りの読みのように、直前に確定した単語に続けて変換する。
+    pub fn convert_k_best_with_context(
+        &self,
+        yomi: &str,
+        force_ranges: Option<&[Range<usize>]>,
+        k: usize,
+        left_context: Option<&Candidate>,
+    ) -> Result<Vec<KBestPath>> {
+        let segmentation_result = &self.segmenter.build(yomi, force_ranges);
+        let lattice =
+            self.graph_builder
+                .construct_with_context(yomi, segmentation_result, left_context);
         let mut paths = self.graph_resolver.resolve_k_best(&lattice, k)?;
         // skip-bigram コストは Viterbi DP 内で計算済み（GraphResolver 経由）
         self.reranking_weights.rerank(&mut paths);
         Ok(paths)
     }
-}
 
-impl<U: SystemUnigramLM, B: SystemBigramLM, KD: KanaKanjiDict> BigramWordViterbiEngine<U, B, KD> {
     pub fn resolve(&self, lattice: &LatticeGraph<U, B>) -> Result<Vec<Vec<Candidate>>> {
         self.graph_resolver.resolve(lattice)
     }
@@ -115,27 +142,26 @@ impl BigramWordViterbiEngineBuilder {
     ) -> Result<
         BigramWordViterbiEngine<MarisaSystemUnigramLM, MarisaSystemBigramLM, MarisaKanaKanjiDict>,
     > {
-        let model_name = self.config.model.clone();
-
-        let system_unigram_lm =
-            MarisaSystemUnigramLM::load(Self::try_load(&model_name, "unigram.model")?.as_str())?;
-        let system_bigram_lm =
-            MarisaSystemBigramLM::load(Self::try_load(&model_name, "bigram.model")?.as_str())?;
-        let skip_bigram_path = Self::try_load(&model_name, "skip_bigram.model")?;
-        let skip_bigram_lm = match MarisaSystemSkipBigramLM::load(&skip_bigram_path) {
-            Ok(lm) => {
-                info!("Loaded skip-bigram model: {}", skip_bigram_path);
-                Some(Rc::new(lm))
-            }
-            Err(_) => {
-                info!(
-                    "Skip-bigram model not found (optional): {}",
-                    skip_bigram_path
-                );
-                None
-            }
-        };
-        let system_dict = Self::try_load(&model_name, "SKK-JISYO.akaza")?;
+        // モデルが壊れていたりインストールされていなくても、辞書だけで変換できるようにする。
+        let (system_unigram_lm, system_bigram_lm, skip_bigram_lm, system_dict, model_error) =
+            match Self::load_model(&self.config.model) {
+                Ok((unigram, bigram, skip_bigram, system_dict)) => {
+                    (unigram, bigram, skip_bigram, Some(system_dict), None)
+                }
+                Err(err) => {
+                    warn!(
+                        "Cannot load model, falling back to dictionary-only mode: {:#}",
+                        err
+                    );
+                    (
+                        Self::empty_unigram_lm()?,
+                        Self::empty_bigram_lm()?,
+                        None,
+                        None,
+                        Some(format!("{err:#}")),
+                    )
+                }
+            };
 
         let user_data = if let Some(d) = &self.user_data {
             d.clone()
@@ -151,12 +177,14 @@ impl BigramWordViterbiEngineBuilder {
                 .filter(|it| it.usage == DictUsage::Normal)
                 .cloned()
                 .collect::<Vec<_>>();
-            dicts.push(DictConfig {
-                path: system_dict,
-                dict_type: DictType::SKK,
-                encoding: DictEncoding::Utf8,
-                usage: DictUsage::Normal,
-            });
+            if let Some(system_dict) = system_dict {
+                dicts.push(DictConfig {
+                    path: system_dict,
+                    dict_type: DictType::SKK,
+                    encoding: DictEncoding::Utf8,
+                    usage: DictUsage::Normal,
+                });
+            }
 
             if self.config.dict_cache {
                 load_dicts_with_cache(&dicts, "kana_kanji_cache.marisa")?
@@ -226,9 +254,71 @@ impl BigramWordViterbiEngineBuilder {
             user_data,
             reranking_weights,
             skip_bigram_lm,
+            model_error,
         })
     }
 
+    /// モデルのディレクトリから言語モデルを読み込み、システム辞書のパスと一緒に返す。
+    #[allow(clippy::type_complexity)]
+    fn load_model(
+        model_dir: &str,
+    ) -> Result<(
+        MarisaSystemUnigramLM,
+        MarisaSystemBigramLM,
+        Option<Rc<MarisaSystemSkipBigramLM>>,
+        String,
+    )> {
+        for name in REQUIRED_MODEL_FILES {
+            let path = Self::try_load(model_dir, name)?;
+            if !Path::new(&path).is_file() {
+                bail!("Missing model file: {}", path);
+            }
+        }
+
+        let system_unigram_lm =
+            MarisaSystemUnigramLM::load(Self::try_load(model_dir, "unigram.model")?.as_str())?;
+        let system_bigram_lm =
+            MarisaSystemBigramLM::load(Self::try_load(model_dir, "bigram.model")?.as_str())?;
+        let skip_bigram_path = Self::try_load(model_dir, "skip_bigram.model")?;
+        let skip_bigram_lm = match MarisaSystemSkipBigramLM::load(&skip_bigram_path) {
+            Ok(lm) => {
+                info!("Loaded skip-bigram model: {}", skip_bigram_path);
+                Some(Rc::new(lm))
+            }
+            Err(_) => {
+                info!(
+                    "Skip-bigram model not found (optional): {}",
+                    skip_bigram_path
+                );
+                None
+            }
+        };
+        let system_dict = Self::try_load(model_dir, "SKK-JISYO.akaza")?;
+        Ok((
+            system_unigram_lm,
+            system_bigram_lm,
+            skip_bigram_lm,
+            system_dict,
+        ))
+    }
+
+    /// 辞書だけで変換する時の、空の unigram モデル。すべての単語が同じコストになる。
+    /// 文節の数が少ないほどコストが低くなるので、なるべく長い単語が選ばれる。
+    fn empty_unigram_lm() -> Result<MarisaSystemUnigramLM> {
+        MarisaSystemUnigramLMBuilder::default()
+            .set_total_words(1)
+            .set_unique_words(1)
+            .build()
+    }
+
+    /// 辞書だけで変換する時の、空の bigram モデル。
+    fn empty_bigram_lm() -> Result<MarisaSystemBigramLM> {
+        // 辞書にない単語のコストと同じにしておく。
+        MarisaSystemBigramLMBuilder::default()
+            .set_default_edge_cost(calc_cost(0, 1, 1))
+            .build()
+    }
+
     fn try_load(model_dir: &str, name: &str) -> Result<String> {
         Ok(model_dir.to_string() + "/" + name)
     }
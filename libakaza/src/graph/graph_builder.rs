@@ -8,6 +8,7 @@ use kelp::{hira2kata, ConvOption};
 use log::trace;
 use regex::Regex;
 
+use crate::graph::candidate::Candidate;
 use crate::graph::lattice_graph::LatticeGraph;
 use crate::graph::segmenter::SegmentationResult;
 use crate::graph::word_node::{WordNode, BOS_TOKEN_KEY, EOS_TOKEN_KEY};
@@ -65,11 +66,28 @@ impl<U: SystemUnigramLM, B: SystemBigramLM, KD: KanaKanjiDict> GraphBuilder<U, B
     }
 
     pub fn construct(&self, yomi: &str, words_ends_at: &SegmentationResult) -> LatticeGraph<U, B> {
+        self.construct_with_context(yomi, words_ends_at, None)
+    }
+
+    /// left_context は、この読みの直前に確定した単語。
+    /// 言語モデルにある単語なら、文頭の代わりにその単語からのバイグラムで先頭の単語を選ぶ。
+    pub fn construct_with_context(
+        &self,
+        yomi: &str,
+        words_ends_at: &SegmentationResult,
+        left_context: Option<&Candidate>,
+    ) -> LatticeGraph<U, B> {
         // このグラフのインデクスは単語の終了位置。
         let mut graph: BTreeMap<i32, Vec<WordNode>> = BTreeMap::new();
 
         let mut bos = WordNode::create_bos();
-        if let Some((word_id, _)) = self.system_unigram_lm.find(BOS_TOKEN_KEY) {
+        let context_word_id = left_context.and_then(|candidate| {
+            self.system_unigram_lm
+                .find(&format!("{}/{}", candidate.surface, candidate.yomi))
+        });
+        if let Some((word_id, _)) =
+            context_word_id.or_else(|| self.system_unigram_lm.find(BOS_TOKEN_KEY))
+        {
             bos.word_id_and_score = Some((word_id, 0.0)); // score=0: ノードコストは0のまま
         }
         graph.insert(0, vec![bos]);
@@ -948,6 +948,51 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_left_context() -> anyhow::Result<()> {
+        // 直前に確定した単語からのバイグラムで、先頭の単語が決まることをテスト
+        let kana_trie = CedarwoodKanaTrie::build(vec!["はし".to_string()]);
+        let segmenter = Segmenter::new(vec![Arc::new(Mutex::new(kana_trie))]);
+        let graph = segmenter.build("はし", None);
+
+        let dict = HashMap::from([("はし".to_string(), vec!["橋".to_string(), "箸".to_string()])]);
+
+        let mut system_unigram_lm_builder = MarisaSystemUnigramLMBuilder::default();
+        system_unigram_lm_builder.add("橋/はし", 1.0);
+        system_unigram_lm_builder.add("箸/はし", 1.0);
+        system_unigram_lm_builder.add("川/かわ", 1.0);
+        system_unigram_lm_builder.add("食事/しょくじ", 1.0);
+        system_unigram_lm_builder.set_total_words(100);
+        system_unigram_lm_builder.set_unique_words(50);
+        let system_unigram_lm = system_unigram_lm_builder.build()?;
+
+        let unigram_map = system_unigram_lm.as_hash_map();
+        let id = |key: &str| unigram_map.get(key).unwrap().0;
+        let mut system_bigram_lm_builder = MarisaSystemBigramLMBuilder::default();
+        system_bigram_lm_builder.set_default_edge_cost(10.0);
+        system_bigram_lm_builder.add(id("川/かわ"), id("橋/はし"), 0.1);
+        system_bigram_lm_builder.add(id("食事/しょくじ"), id("箸/はし"), 0.1);
+        let system_bigram_lm = system_bigram_lm_builder.build()?;
+
+        let graph_builder = GraphBuilder::new(
+            HashmapVecKanaKanjiDict::new(dict),
+            HashmapVecKanaKanjiDict::new(HashMap::new()),
+            Arc::new(Mutex::new(UserData::default())),
+            Rc::new(system_unigram_lm),
+            Rc::new(system_bigram_lm),
+        );
+        let resolver = GraphResolver::default();
+        let top = |context: Candidate| -> anyhow::Result<String> {
+            let lattice = graph_builder.construct_with_context("はし", &graph, Some(&context));
+            Ok(resolver.resolve(&lattice)?[0][0].surface.clone())
+        };
+
+        assert_eq!(top(Candidate::new("かわ", "川", 0.0))?, "橋");
+        assert_eq!(top(Candidate::new("しょくじ", "食事", 0.0))?, "箸");
+
+        Ok(())
+    }
+
     #[test]
     fn test_user_learning_priority() -> anyhow::Result<()> {
         // ユーザー学習が候補順位に影響することをテスト
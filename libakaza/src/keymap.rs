@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::BufReader;
 
@@ -7,6 +8,48 @@ use anyhow::{bail, Context, Result};
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
+/// キーマップで指定できるコマンドの一覧。
+/// ibus-akaza の ibus_akaza_commands_map と一致している必要がある。
+pub const COMMANDS: &[&str] = &[
+    "commit_candidate",
+    "partial_commit",
+    "commit_preedit",
+    "escape",
+    "page_up",
+    "page_down",
+    "set_input_mode_hiragana",
+    "set_input_mode_alnum",
+    "set_input_mode_fullwidth_alnum",
+    "set_input_mode_katakana",
+    "set_input_mode_halfwidth_katakana",
+    "update_candidates",
+    "erase_character_before_cursor",
+    "cursor_up",
+    "cursor_down",
+    "cursor_right",
+    "cursor_left",
+    "extend_clause_right",
+    "extend_clause_left",
+    "cycle_segmentation",
+    "convert_to_full_hiragana",
+    "convert_to_full_katakana",
+    "convert_to_half_katakana",
+    "convert_to_full_romaji",
+    "convert_to_half_romaji",
+    "select_candidate",
+    "set_input_mode",
+];
+
+/// キーマップの modes で指定できる入力モードの名前。
+/// ibus-akaza の input_mode と一致している必要がある。
+pub const INPUT_MODES: &[&str] = &[
+    "Alphanumeric",
+    "Hiragana",
+    "Katakana",
+    "HalfWidthKatakana",
+    "FullWidthAlnum",
+];
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Keymap {
     pub extends: Option<String>,
@@ -14,73 +57,241 @@ pub struct Keymap {
 }
 
 impl Keymap {
-    fn to_map(&self) -> Result<HashMap<KeyPattern, Option<String>>> {
+    fn to_map(&self) -> Result<HashMap<KeyPattern, Option<Vec<KeyCommand>>>> {
         let mut retval = HashMap::new();
 
         for kc in &self.keys {
+            let commands = kc.commands()?;
             for key in &kc.key {
-                let (ctrl, shift, key) = Self::parse_key(key.as_str())?;
-
-                retval.insert(
-                    KeyPattern {
-                        states: kc.states.clone(),
-                        ctrl,
-                        shift,
-                        key,
-                    },
-                    kc.command.clone(),
-                );
+                let mut pattern = Self::parse_pattern(&kc.states, key.as_str())?;
+                pattern.modes = kc.modes.clone();
+                retval.insert(pattern, commands.clone());
             }
         }
 
         Ok(retval)
     }
 
-    fn parse_key(key: &str) -> Result<(bool, bool, String)> {
+    /// キーの名前(修飾キーを除いた部分)として使える文字列かどうか。
+    /// 実際に存在するキーかどうかは IBus でしか分からないので、形式だけを見る。
+    pub fn is_valid_key_name(key: &str) -> bool {
+        key.chars().count() == 1
+            || (!key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+    }
+
+    /// "C-x C-k" のような、空白で区切ったキーの組み合わせを読む。
+    fn parse_pattern(states: &[KeyState], key: &str) -> Result<KeyPattern> {
+        let strokes = key
+            .split_whitespace()
+            .map(Self::parse_stroke)
+            .collect::<Result<Vec<_>>>()?;
+        if strokes.is_empty() {
+            bail!("Empty key in keymap: {:?}", key);
+        }
+        Ok(KeyPattern {
+            states: states.to_vec(),
+            modes: Vec::new(),
+            strokes,
+        })
+    }
+
+    /// "C-S-h" のような修飾キー付きのキーを読む。
+    ///
+    /// 修飾キーは C(Control), S(Shift), M または A(Alt), s(Super), H(Hyper)。
+    fn parse_stroke(key: &str) -> Result<KeyStroke> {
+        let mut stroke = KeyStroke {
+            ctrl: false,
+            shift: false,
+            alt: false,
+            super_: false,
+            hyper: false,
+            key: key.to_string(),
+        };
         if key.contains('-') {
-            let mut ctrl = false;
-            let mut shift = false;
             let keys = key.split('-').collect::<Vec<_>>();
             for m in &keys[0..keys.len() - 1] {
                 match *m {
-                    "C" => {
-                        ctrl = true;
-                    }
-                    "S" => {
-                        shift = true;
-                    }
+                    "C" => stroke.ctrl = true,
+                    "S" => stroke.shift = true,
+                    "M" | "A" => stroke.alt = true,
+                    "s" => stroke.super_ = true,
+                    "H" => stroke.hyper = true,
                     _ => {
                         bail!("Unknown modifier in keymap: {}", key);
                     }
                 }
             }
-
-            Ok((ctrl, shift, keys[keys.len() - 1].to_string()))
-        } else {
-            Ok((false, false, key.to_string()))
+            stroke.key = keys[keys.len() - 1].to_string();
         }
+        Ok(stroke)
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct KeyConfig {
     pub states: Vec<KeyState>,
+    /// このキーを使う入力モード。空なら全ての入力モードで使う。
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modes: Vec<String>,
     pub key: Vec<String>,
-    pub command: Option<String>,
+    pub command: Option<CommandConfig>,
+    /// command が一つの時に渡す引数。
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<serde_yaml::Value>,
+}
+
+impl KeyConfig {
+    /// null なら None。
+    fn commands(&self) -> Result<Option<Vec<KeyCommand>>> {
+        let Some(command) = &self.command else {
+            return Ok(None);
+        };
+        let commands = match command {
+            CommandConfig::Name(name) => vec![KeyCommand::new(name, to_args(&self.args)?)],
+            CommandConfig::Macro(steps) => {
+                if !self.args.is_empty() {
+                    bail!(
+                        "args cannot be used with a list of commands. Write args for each command: {:?}",
+                        self.key
+                    );
+                }
+                steps
+                    .iter()
+                    .map(|step| match step {
+                        MacroStep::Name(name) => Ok(KeyCommand::new(name, Vec::new())),
+                        MacroStep::WithArgs { command, args } => {
+                            Ok(KeyCommand::new(command, to_args(args)?))
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            }
+        };
+        if commands.is_empty() {
+            bail!("Empty command list in keymap: {:?}", self.key);
+        }
+        Ok(Some(commands))
+    }
+}
+
+/// 引数は YAML では数値でも文字列でも書けるが、コマンドには文字列で渡す。
+fn to_args(values: &[serde_yaml::Value]) -> Result<Vec<String>> {
+    values
+        .iter()
+        .map(|value| match value {
+            serde_yaml::Value::String(s) => Ok(s.clone()),
+            serde_yaml::Value::Number(n) => Ok(n.to_string()),
+            serde_yaml::Value::Bool(b) => Ok(b.to_string()),
+            _ => bail!("Unsupported command argument in keymap: {:?}", value),
+        })
+        .collect()
+}
+
+/// キーマップの command。一つのコマンドか、順番に実行するコマンドのリスト(マクロ)。
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum CommandConfig {
+    Name(String),
+    Macro(Vec<MacroStep>),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum MacroStep {
+    Name(String),
+    WithArgs {
+        command: String,
+        #[serde(default)]
+        args: Vec<serde_yaml::Value>,
+    },
+}
+
+/// キーに割り当てる一つのコマンドと、その引数。
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct KeyCommand {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+impl KeyCommand {
+    /// 以前のキーマップの press_number_N は、select_candidate に引数 [N] を渡すコマンドとして読む。
+    pub fn new(name: &str, args: Vec<String>) -> KeyCommand {
+        if let Some(nn) = name.strip_prefix("press_number_") {
+            if args.is_empty() && nn.len() == 1 && nn.chars().all(|c| c.is_ascii_digit()) {
+                return KeyCommand {
+                    name: "select_candidate".to_string(),
+                    args: vec![nn.to_string()],
+                };
+            }
+        }
+        KeyCommand {
+            name: name.to_string(),
+            args,
+        }
+    }
+}
+
+impl fmt::Display for KeyCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.args.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}({})", self.name, self.args.join(", "))
+        }
+    }
 }
 
 // null であとから消すために使う
 #[derive(PartialEq, Debug, Hash, Clone)]
 pub struct KeyPattern {
     pub states: Vec<KeyState>,
+    /// "Katakana" などの入力モードの名前。空なら全ての入力モード。
+    pub modes: Vec<String>,
+    /// 順番に押すキー。"C-x C-k" のような組み合わせでは二つ以上になる。
+    pub strokes: Vec<KeyStroke>,
+}
+
+impl Eq for KeyPattern {}
+
+impl fmt::Display for KeyPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let strokes = self
+            .strokes
+            .iter()
+            .map(|stroke| stroke.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", strokes.join(" "))
+    }
+}
+
+/// 修飾キー付きの一つのキー。
+#[derive(PartialEq, Eq, Debug, Hash, Clone)]
+pub struct KeyStroke {
     pub ctrl: bool,
     pub shift: bool,
+    pub alt: bool,
+    pub super_: bool,
+    pub hyper: bool,
     pub key: String,
 }
 
-impl Eq for KeyPattern {}
+impl fmt::Display for KeyStroke {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (enabled, name) in [
+            (self.ctrl, "C-"),
+            (self.shift, "S-"),
+            (self.alt, "M-"),
+            (self.super_, "s-"),
+            (self.hyper, "H-"),
+        ] {
+            if enabled {
+                write!(f, "{name}")?;
+            }
+        }
+        write!(f, "{}", self.key)
+    }
+}
 
-#[derive(Debug, Hash, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Hash, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
 pub enum KeyState {
     // 何も入力されていない状態。
     PreComposition,
@@ -91,7 +302,7 @@ pub enum KeyState {
 }
 
 impl Keymap {
-    pub fn load(keymap_path: &str) -> Result<HashMap<KeyPattern, String>> {
+    pub fn load(keymap_path: &str) -> Result<HashMap<KeyPattern, Vec<KeyCommand>>> {
         info!("Load {}", keymap_path);
         let got: Keymap = serde_yaml::from_reader(BufReader::new(
             File::open(keymap_path).with_context(|| keymap_path.to_string())?,
@@ -146,30 +357,237 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_extends() -> anyhow::Result<()> {
+        let default = Keymap::load("../keymap/default.yml")?;
+        let atok = Keymap::load("../keymap/atok.yml")?;
+        assert!(atok.len() > default.len());
+        assert!(atok
+            .values()
+            .flatten()
+            .any(|command| command.name == "convert_to_full_hiragana"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_commands_in_keymaps() -> anyhow::Result<()> {
+        for path in ["../keymap/default.yml", "../keymap/atok.yml"] {
+            for (pattern, commands) in Keymap::load(path)? {
+                for command in &commands {
+                    assert!(COMMANDS.contains(&command.name.as_str()), "{command}");
+                }
+                for stroke in &pattern.strokes {
+                    assert!(Keymap::is_valid_key_name(&stroke.key), "{pattern:?}");
+                }
+            }
+        }
+        assert!(!Keymap::is_valid_key_name(""));
+        assert!(!Keymap::is_valid_key_name("Page Up"));
+        Ok(())
+    }
+
     #[test]
     fn test_c_h() -> Result<()> {
-        let (ctrl, shift, key) = Keymap::parse_key("C-h")?;
-        assert!(ctrl);
-        assert!(!shift);
-        assert_eq!(key, "h");
+        assert_eq!(
+            Keymap::parse_stroke("C-h")?,
+            KeyStroke {
+                ctrl: true,
+                shift: false,
+                alt: false,
+                super_: false,
+                hyper: false,
+                key: "h".to_string(),
+            }
+        );
         Ok(())
     }
 
     #[test]
     fn test_c_s_h() -> Result<()> {
-        let (ctrl, shift, key) = Keymap::parse_key("C-S-h")?;
-        assert!(ctrl);
-        assert!(shift);
-        assert_eq!(key, "h");
+        assert_eq!(
+            Keymap::parse_stroke("C-S-h")?,
+            KeyStroke {
+                ctrl: true,
+                shift: true,
+                alt: false,
+                super_: false,
+                hyper: false,
+                key: "h".to_string(),
+            }
+        );
         Ok(())
     }
 
     #[test]
     fn test_shift() -> Result<()> {
-        let (ctrl, shift, key) = Keymap::parse_key("h")?;
-        assert!(!ctrl);
-        assert!(!shift);
-        assert_eq!(key, "h");
+        assert_eq!(
+            Keymap::parse_stroke("h")?,
+            KeyStroke {
+                ctrl: false,
+                shift: false,
+                alt: false,
+                super_: false,
+                hyper: false,
+                key: "h".to_string(),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_alt_super_hyper() -> Result<()> {
+        let stroke = Keymap::parse_stroke("M-space")?;
+        assert!(stroke.alt);
+        assert!(!stroke.ctrl);
+        assert_eq!(stroke.key, "space");
+        assert_eq!(stroke, Keymap::parse_stroke("A-space")?);
+
+        let stroke = Keymap::parse_stroke("C-s-H-k")?;
+        assert!(stroke.ctrl);
+        assert!(stroke.super_);
+        assert!(stroke.hyper);
+        assert!(!stroke.shift);
+        assert!(!stroke.alt);
+        assert_eq!(stroke.key, "k");
+
+        assert!(Keymap::parse_stroke("X-k").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequence() -> Result<()> {
+        let pattern = Keymap::parse_pattern(&[KeyState::PreComposition], "C-x  C-k")?;
+        assert_eq!(
+            pattern.strokes,
+            vec![Keymap::parse_stroke("C-x")?, Keymap::parse_stroke("C-k")?]
+        );
+        assert_eq!(pattern.to_string(), "C-x C-k");
+        assert_eq!(
+            Keymap::parse_pattern(&[], "Henkan k")?.to_string(),
+            "Henkan k"
+        );
+        assert!(Keymap::parse_pattern(&[], " ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_modes() -> Result<()> {
+        let keymap: Keymap = serde_yaml::from_str(
+            r#"
+keys:
+  - states: [Composition]
+    key: [F7]
+    command: convert_to_full_katakana
+  - states: [Composition]
+    modes: [Katakana]
+    key: [F7]
+    command: convert_to_full_hiragana
+"#,
+        )?;
+        let map = keymap.to_map()?;
+        assert_eq!(map.len(), 2);
+        let katakana = map
+            .iter()
+            .find(|(pattern, _)| pattern.modes == vec!["Katakana".to_string()])
+            .unwrap();
+        assert_eq!(
+            katakana.1.as_ref().unwrap()[0].name,
+            "convert_to_full_hiragana"
+        );
+        assert!(keymap.keys[0].modes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_args_and_macro() -> Result<()> {
+        let keymap: Keymap = serde_yaml::from_str(
+            r#"
+keys:
+  - states: [Conversion]
+    key: ["3"]
+    command: select_candidate
+    args: [3]
+  - states: [Conversion]
+    key: [C-Return]
+    command:
+      - convert_to_full_katakana
+      - commit_candidate
+  - states: [PreComposition]
+    key: [C-k]
+    command:
+      - command: set_input_mode
+        args: [Katakana]
+  - states: [PreComposition]
+    key: [C-l]
+    command: null
+"#,
+        )?;
+        let commands = keymap
+            .keys
+            .iter()
+            .map(|kc| kc.commands())
+            .collect::<Result<Vec<_>>>()?;
+        let command = |name: &str, args: &[&str]| KeyCommand {
+            name: name.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        };
+        assert_eq!(
+            commands,
+            vec![
+                Some(vec![command("select_candidate", &["3"])]),
+                Some(vec![
+                    command("convert_to_full_katakana", &[]),
+                    command("commit_candidate", &[])
+                ]),
+                Some(vec![command("set_input_mode", &["Katakana"])]),
+                None,
+            ]
+        );
+        assert_eq!(
+            commands[0].as_ref().unwrap()[0].to_string(),
+            "select_candidate(3)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_press_number_alias() -> Result<()> {
+        let keymap: Keymap = serde_yaml::from_str(
+            r#"
+keys:
+  - states: [Conversion]
+    key: ["3"]
+    command: press_number_3
+  - states: [Conversion]
+    key: ["0"]
+    command:
+      - press_number_0
+      - commit_candidate
+"#,
+        )?;
+        let commands = keymap
+            .keys
+            .iter()
+            .map(|kc| kc.commands())
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            commands,
+            vec![
+                Some(vec![KeyCommand::new(
+                    "select_candidate",
+                    vec!["3".to_string()]
+                )]),
+                Some(vec![
+                    KeyCommand::new("select_candidate", vec!["0".to_string()]),
+                    KeyCommand::new("commit_candidate", Vec::new()),
+                ]),
+            ]
+        );
+        // 番号でないものはそのまま残して、知らないコマンドとして扱う
+        assert_eq!(
+            KeyCommand::new("press_number_10", Vec::new()).name,
+            "press_number_10"
+        );
         Ok(())
     }
 }
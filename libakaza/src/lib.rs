@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 
 pub mod config;
-pub mod consonant;
+mod config_migration;
 pub mod corpus;
 pub mod cost;
 pub mod dict;
@@ -13,7 +13,8 @@ pub mod kana_trie;
 pub mod kansuji;
 pub mod keymap;
 pub mod lm;
-mod resource;
+pub mod resource;
 pub mod romkan;
 pub mod search_result;
+pub mod test_utils;
 pub mod user_side_data;
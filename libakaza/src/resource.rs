@@ -1,20 +1,64 @@
 use std::env;
+use std::path::PathBuf;
+use std::sync::RwLock;
 
 use anyhow::{bail, Context};
 
+/// 追加の data ディレクトリを指定する環境変数。`:` 区切りで複数指定できる。
+pub const DATA_DIRS_ENV: &str = "AKAZA_DATA_DIRS";
+
+/// 設定ファイルの data_dirs で指定された data ディレクトリ。
+static CONFIG_DATA_DIRS: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+/// 設定ファイルで指定された data ディレクトリを登録する。
+/// 設定ファイルを読み込む時に呼ばれる。
+pub fn set_config_data_dirs(dirs: Vec<PathBuf>) {
+    *CONFIG_DATA_DIRS.write().unwrap() = dirs;
+}
+
+/// romkan, keymap, model などを探すディレクトリの一覧。優先度の高い順に並ぶ。
+///
+/// 1. 環境変数 AKAZA_DATA_DIRS
+/// 2. 設定ファイルの data_dirs
+/// 3. `$XDG_DATA_HOME/akaza`
+/// 4. `$XDG_DATA_DIRS/akaza`
+pub fn data_dirs() -> anyhow::Result<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if let Some(env_dirs) = env::var_os(DATA_DIRS_ENV) {
+        dirs.extend(env::split_paths(&env_dirs).filter(|it| !it.as_os_str().is_empty()));
+    }
+    dirs.extend(CONFIG_DATA_DIRS.read().unwrap().iter().cloned());
+
+    let basedirs = xdg::BaseDirectories::with_prefix("akaza")
+        .with_context(|| "Opening xdg directory with 'akaza' prefix")?;
+    dirs.push(basedirs.get_data_home());
+    dirs.extend(basedirs.get_data_dirs());
+
+    let mut unique: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        if !unique.contains(&dir) {
+            unique.push(dir);
+        }
+    }
+    Ok(unique)
+}
+
 pub fn detect_resource_path(base: &str, name: &str) -> anyhow::Result<String> {
     let pathstr: String = if cfg!(test) {
         format!("{}/../{}/{}", env!("CARGO_MANIFEST_DIR"), base, name)
     } else {
-        let target_path = format!("{base}/{name}");
-        let basedirs = xdg::BaseDirectories::with_prefix("akaza")
-            .with_context(|| "Opening xdg directory with 'akaza' prefix")?;
-        let pathbuf = basedirs.find_data_file(&target_path);
-        let Some(pathbuf) = pathbuf else {
-            bail!("Cannot find {:?} in XDG_DATA_HOME or XDG_DATA_DIRS(XDG_DATA_HOME={:?}, XDG_DATA_DIRS={:?}, base={:?}, name={:?})",
-                target_path,
-                basedirs.get_data_home().to_string_lossy().to_string(),
-                basedirs.get_data_dirs().iter().map(|x| x.to_string_lossy().to_string()).collect::<Vec<_>>(),
+        let dirs = data_dirs()?;
+        let Some(pathbuf) = dirs
+            .iter()
+            .map(|dir| dir.join(base).join(name))
+            .find(|path| path.exists())
+        else {
+            bail!(
+                "Cannot find {:?} in {:?} (base={:?}, name={:?})",
+                format!("{base}/{name}"),
+                dirs.iter()
+                    .map(|x| x.to_string_lossy().to_string())
+                    .collect::<Vec<_>>(),
                 base,
                 name
             )
@@ -23,3 +67,61 @@ pub fn detect_resource_path(base: &str, name: &str) -> anyhow::Result<String> {
     };
     Ok(pathstr)
 }
+
+/// ユーザーの data ディレクトリ(`$XDG_DATA_HOME/akaza`)での `{base}/{name}` のパス。
+/// ファイルが存在するかどうかは確認しない。
+pub fn user_resource_path(base: &str, name: &str) -> anyhow::Result<PathBuf> {
+    let basedirs = xdg::BaseDirectories::with_prefix("akaza")
+        .with_context(|| "Opening xdg directory with 'akaza' prefix")?;
+    Ok(basedirs.get_data_home().join(base).join(name))
+}
+
+/// すべての data ディレクトリから `{base}/` 直下のファイルとディレクトリを集める。
+/// 同じ名前のものが複数ある場合は、優先度の高いディレクトリのものを使う。
+pub fn list_resource_files(base: &str) -> anyhow::Result<Vec<PathBuf>> {
+    Ok(list_files_in(&data_dirs()?, base))
+}
+
+fn list_files_in(dirs: &[PathBuf], base: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = dir.join(base).read_dir() else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !files.iter().any(|it| it.file_name() == path.file_name()) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_list_resource_files() -> anyhow::Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let company = tmpdir.path().join("company");
+        let user = tmpdir.path().join("user");
+        fs::create_dir_all(company.join("keymap"))?;
+        fs::create_dir_all(user.join("keymap"))?;
+        fs::write(company.join("keymap/company.yml"), "")?;
+        fs::write(company.join("keymap/default.yml"), "")?;
+        fs::write(user.join("keymap/default.yml"), "")?;
+
+        let files = list_files_in(&[user.clone(), company.clone()], "keymap");
+
+        assert!(files.contains(&company.join("keymap/company.yml")));
+        // 優先度の高いディレクトリのものを使う
+        assert!(files.contains(&user.join("keymap/default.yml")));
+        assert!(!files.contains(&company.join("keymap/default.yml")));
+        Ok(())
+    }
+}
@@ -1,106 +1,500 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::BufReader;
 
+use crate::config::PunctuationStyle;
 use crate::resource::detect_resource_path;
-use anyhow::Context;
-use log::info;
-use regex::{Captures, Regex};
+use anyhow::{bail, Context};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct RomKanConfig {
-    mapping: HashMap<String, Option<String>>,
+    mapping: HashMap<String, Option<RomKanRule>>,
     extends: Option<String>,
+    /// キーコード(evdev)ごとの入力。書かれていれば、キーの文字ではなくキーコードで入力する。
+    #[serde(default)]
+    keycodes: HashMap<u32, Option<KeycodeInput>>,
+    /// 親指シフト入力の親指キー。
+    #[serde(default)]
+    thumb_shift: Option<ThumbShiftConfig>,
 }
 
-fn load_romkan_map(file_path: &str) -> anyhow::Result<HashMap<String, String>> {
+/// ローマ字かなテーブルの一つの規則。
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum RomKanRule {
+    /// `"ka": "か"` のように、かなを出力する。
+    Kana(String),
+    /// `"tt": {kana: "っ", next: "t"}` のように、かなを出力した後、next を入力した状態にする。
+    WithNext {
+        #[serde(default)]
+        kana: String,
+        next: String,
+    },
+}
+
+impl RomKanRule {
+    fn kana(&self) -> &str {
+        match self {
+            RomKanRule::Kana(kana) => kana,
+            RomKanRule::WithNext { kana, .. } => kana,
+        }
+    }
+
+    fn next(&self) -> &str {
+        match self {
+            RomKanRule::Kana(_) => "",
+            RomKanRule::WithNext { next, .. } => next,
+        }
+    }
+}
+
+/// キーコードで入力する時の、一つのキーの入力。
+/// JIS キーボードの「ろ」と「ー」のように、キーの文字では区別できないキーを入力するために使う。
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct KeycodeInput {
+    pub normal: String,
+    /// シフトを押している時の入力。省略すると normal と同じ。
+    #[serde(default)]
+    pub shift: Option<String>,
+    /// 左親指キーと同時に押した時の入力。
+    #[serde(default)]
+    pub left: Option<String>,
+    /// 右親指キーと同時に押した時の入力。
+    #[serde(default)]
+    pub right: Option<String>,
+}
+
+/// 親指シフトの親指キー。
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum Thumb {
+    Left,
+    Right,
+}
+
+/// 親指シフト入力の設定。
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ThumbShiftConfig {
+    /// 左親指キーのキーコード(evdev)
+    pub left: u32,
+    /// 右親指キーのキーコード(evdev)
+    pub right: u32,
+    /// 文字キーと親指キーを同時に押したとみなす時間(ミリ秒)
+    #[serde(default = "default_thumb_shift_timeout")]
+    pub timeout_ms: u64,
+}
+
+fn default_thumb_shift_timeout() -> u64 {
+    100
+}
+
+impl ThumbShiftConfig {
+    /// キーコードが親指キーなら、どちらの親指キーかを返す。
+    pub fn thumb(&self, keycode: u32) -> Option<Thumb> {
+        if keycode == self.left {
+            Some(Thumb::Left)
+        } else if keycode == self.right {
+            Some(Thumb::Right)
+        } else {
+            None
+        }
+    }
+}
+
+/// 継承を解決したテーブル。
+#[derive(Default)]
+struct RomKanTable {
+    mapping: HashMap<String, RomKanRule>,
+    keycodes: HashMap<u32, KeycodeInput>,
+    thumb_shift: Option<ThumbShiftConfig>,
+}
+
+fn load_romkan_table(file_path: &str) -> anyhow::Result<RomKanTable> {
     info!("Loading romkan map: {}", file_path);
     let got: RomKanConfig = serde_yaml::from_reader(BufReader::new(
         File::open(file_path).with_context(|| file_path.to_string())?,
     ))?;
 
-    if let Some(parent) = got.extends {
-        // 継承しているので親を読み込む。
-        // 再帰的な処理になる。
-        let path = detect_resource_path("romkan", &format!("{parent}.yml"))?;
-        let mut parent = load_romkan_map(&path)?;
-
-        for (k, v) in got.mapping {
-            if let Some(v) = v {
-                parent.insert(k, v);
-            } else {
-                parent.remove(&k);
-            }
+    // 継承しているなら親を読み込む。
+    // 再帰的な処理になる。
+    let mut table = match got.extends {
+        Some(parent) => {
+            let path = detect_resource_path("romkan", &format!("{parent}.yml"))?;
+            load_romkan_table(&path)?
         }
+        None => RomKanTable::default(),
+    };
 
-        Ok(parent)
-    } else {
-        // 継承していないのでそのまま。
-        Ok(got
-            .mapping
+    for (k, v) in got.mapping {
+        if let Some(v) = v {
+            table.mapping.insert(k, v);
+        } else {
+            table.mapping.remove(&k);
+        }
+    }
+    for (k, v) in got.keycodes {
+        if let Some(v) = v {
+            table.keycodes.insert(k, v);
+        } else {
+            table.keycodes.remove(&k);
+        }
+    }
+    if got.thumb_shift.is_some() {
+        table.thumb_shift = got.thumb_shift;
+    }
+    Ok(table)
+}
+
+/// 重ねた規則が、テーブルの長い規則に隠れてしまわないかを調べる。
+///
+/// "z" を追加しても "za" などがあると、"z" の後に "a" を入力した時に「ずあ」ではなく「ざ」になってしまう。
+/// このような場合は、長いほうの規則を null で消す必要がある。
+/// "tt" を「っ」+ "t" にする場合の "tta" のように、結果が変わらないものは問題ない。
+fn check_overlay(
+    converter: &RomKanConverter,
+    romkan_map: &HashMap<String, RomKanRule>,
+    overlay: &BTreeMap<String, Option<RomKanRule>>,
+) -> anyhow::Result<()> {
+    let convert = |rule: &RomKanRule, rest: &str| {
+        rule.kana().to_string() + &converter.to_hiragana(&(rule.next().to_string() + rest))
+    };
+
+    let mut errors = Vec::new();
+    for (key, rule) in overlay
+        .iter()
+        .filter_map(|(k, v)| v.as_ref().map(|v| (k, v)))
+    {
+        if key.is_empty() {
+            errors.push("empty key".to_string());
+            continue;
+        }
+        let mut shadowed = romkan_map
             .iter()
-            .filter(|(_, v)| v.is_some())
-            .map(|(k, v)| (k.clone(), v.clone().unwrap()))
-            .collect())
+            .filter(|(longer, _)| longer.len() > key.len() && longer.starts_with(key.as_str()))
+            .filter(|(longer, longer_rule)| {
+                convert(rule, &longer[key.len()..]) != convert(longer_rule, "")
+            })
+            .map(|(longer, _)| format!("{longer:?}"))
+            .collect::<Vec<_>>();
+        if !shadowed.is_empty() {
+            shadowed.sort();
+            errors.push(format!(
+                "{key:?} is a prefix of {}. Set them to null to use {key:?}.",
+                shadowed.join(", ")
+            ));
+        }
     }
+    if !errors.is_empty() {
+        bail!("Conflicting romkan_mapping: {}", errors.join("; "));
+    }
+    Ok(())
+}
+
+/// next で入力しなおす深さの上限。規則が循環していても止まるようにする。
+const MAX_NEXT_DEPTH: usize = 16;
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: HashMap<char, usize>,
+    /// このノードまでの入力で確定する規則
+    rule: Option<RomKanRule>,
 }
 
+/// ローマ字かな変換器。
+/// ローマ字かなテーブルのキーをトライにしておき、一文字ずつたどって変換する。
 #[derive(Debug)]
 pub struct RomKanConverter {
     pub mapping_name: String,
-    romkan_pattern: Regex,
-    romkan_map: HashMap<String, String>,
-    last_char_pattern: Regex,
+    nodes: Vec<TrieNode>,
+    keycodes: HashMap<u32, KeycodeInput>,
+    thumb_shift: Option<ThumbShiftConfig>,
 }
 
 impl RomKanConverter {
     pub fn new(mapping_name: &str) -> anyhow::Result<RomKanConverter> {
-        let romkan_map = load_romkan_map(mapping_name)?;
+        Self::with_overlay(mapping_name, &BTreeMap::new())
+    }
 
-        let mut romas = Vec::from_iter(romkan_map.keys());
-        // 長いキーから一致させるようにする。
-        romas.sort_by_key(|a| std::cmp::Reverse(a.len()));
-        let mut pattern = String::from("(");
-        for x in romas {
-            pattern += &regex::escape(x);
-            pattern += "|";
+    /// mapping_name のテーブルに、設定ファイルの romkan_mapping のような規則を重ねて読み込む。
+    /// null の規則は、テーブルの規則を消す。
+    pub fn with_overlay(
+        mapping_name: &str,
+        overlay: &BTreeMap<String, Option<RomKanRule>>,
+    ) -> anyhow::Result<RomKanConverter> {
+        let RomKanTable {
+            mapping: mut romkan_map,
+            keycodes,
+            thumb_shift,
+        } = load_romkan_table(mapping_name)?;
+        for (k, v) in overlay {
+            match v {
+                Some(v) => romkan_map.insert(k.clone(), v.clone()),
+                None => romkan_map.remove(k),
+            };
         }
-        pattern += ".)";
-
-        let romkan_pattern = Regex::new(&pattern).unwrap();
-        let last_char_pattern = Regex::new(&(pattern + "$")).unwrap();
+        let mut converter = Self::from_map(mapping_name, romkan_map.clone());
+        check_overlay(&converter, &romkan_map, overlay)?;
+        converter.keycodes = keycodes;
+        converter.thumb_shift = thumb_shift;
+        Ok(converter)
+    }
 
-        Ok(RomKanConverter {
+    fn from_map(mapping_name: &str, romkan_map: HashMap<String, RomKanRule>) -> RomKanConverter {
+        let mut converter = RomKanConverter {
             mapping_name: mapping_name.to_string(),
-            romkan_pattern,
-            romkan_map,
-            last_char_pattern,
-        })
+            nodes: vec![TrieNode::default()],
+            keycodes: HashMap::new(),
+            thumb_shift: None,
+        };
+        for (roma, rule) in romkan_map {
+            if roma.is_empty() {
+                warn!("Ignore empty romkan key: {:?}", rule);
+                continue;
+            }
+            let mut idx = 0;
+            for c in roma.chars() {
+                idx = match converter.nodes[idx].children.get(&c) {
+                    Some(child) => *child,
+                    None => {
+                        converter.nodes.push(TrieNode::default());
+                        let child = converter.nodes.len() - 1;
+                        converter.nodes[idx].children.insert(c, child);
+                        child
+                    }
+                };
+            }
+            converter.nodes[idx].rule = Some(rule);
+        }
+        converter
     }
 
     pub fn default_mapping() -> anyhow::Result<RomKanConverter> {
         Self::new(&detect_resource_path("romkan", "default.yml")?)
     }
+
+    /// 句読点として出力されるものを、指定したスタイルの句読点に置き換える。
+    pub fn set_punctuation(&mut self, style: PunctuationStyle) {
+        for node in self.nodes.iter_mut() {
+            let Some(rule) = &mut node.rule else {
+                continue;
+            };
+            let kana = match rule {
+                RomKanRule::Kana(kana) => kana,
+                RomKanRule::WithNext { kana, .. } => kana,
+            };
+            match kana.as_str() {
+                "、" | "，" => *kana = style.touten().to_string(),
+                "。" | "．" => *kana = style.kuten().to_string(),
+                _ => {}
+            }
+        }
+    }
+
+    fn find(&self, key: &str) -> Option<&TrieNode> {
+        let mut idx = 0;
+        for c in key.chars() {
+            idx = *self.nodes[idx].children.get(&c)?;
+        }
+        Some(&self.nodes[idx])
+    }
+
+    /// キーコードで入力するテーブルかどうか。
+    pub fn uses_keycodes(&self) -> bool {
+        !self.keycodes.is_empty()
+    }
+
+    /// キーコードで入力する時に、押されたキーに対応する入力を返す。
+    pub fn keycode_input(&self, keycode: u32, shift: bool) -> Option<&str> {
+        let input = self.keycodes.get(&keycode)?;
+        match &input.shift {
+            Some(shifted) if shift => Some(shifted),
+            _ => Some(&input.normal),
+        }
+    }
+
+    /// 親指シフト入力のテーブルなら、その設定を返す。
+    pub fn thumb_shift(&self) -> Option<&ThumbShiftConfig> {
+        self.thumb_shift.as_ref()
+    }
+
+    /// 親指キーと同時に押した時の入力を返す。指定がなければ、単独で押した時の入力になる。
+    pub fn thumb_shift_input(&self, keycode: u32, thumb: Thumb) -> Option<&str> {
+        let input = self.keycodes.get(&keycode)?;
+        let shifted = match thumb {
+            Thumb::Left => &input.left,
+            Thumb::Right => &input.right,
+        };
+        Some(shifted.as_deref().unwrap_or(&input.normal))
+    }
+
+    /// 一文字入力する。
+    pub fn push(&self, state: &mut RomKanState, c: char) {
+        self.push_with_depth(state, c, 0);
+    }
+
+    fn push_with_depth(&self, state: &mut RomKanState, c: char, depth: usize) {
+        // テーブルにない大文字は、小文字として扱う。
+        let mut key = state.pending.clone();
+        key.push(c);
+        if c.is_ascii_uppercase() && self.find(&key).is_none() {
+            key.pop();
+            key.push(c.to_ascii_lowercase());
+        }
+
+        match self.find(&key) {
+            // これ以上長いキーがないので、確定する。
+            Some(node) if node.children.is_empty() => {
+                state.pending.clear();
+                if let Some(rule) = &node.rule {
+                    self.apply(state, rule, depth);
+                }
+            }
+            // 長いキーがあるかもしれないので、次の入力を待つ。
+            Some(_) => state.pending = key,
+            None if state.pending.is_empty() => state.kana.push(key.chars().last().unwrap()),
+            // 今までの入力を確定してから、入力しなおす。
+            None => {
+                self.flush_once(state, depth);
+                self.push_with_depth(state, key.chars().last().unwrap(), depth);
+            }
+        }
+    }
+
+    fn apply(&self, state: &mut RomKanState, rule: &RomKanRule, depth: usize) {
+        state.kana.push_str(rule.kana());
+        if depth >= MAX_NEXT_DEPTH {
+            warn!("Too deep romkan rule: {:?}", rule);
+            state.kana.push_str(rule.next());
+            return;
+        }
+        for c in rule.next().chars() {
+            self.push_with_depth(state, c, depth + 1);
+        }
+    }
+
+    /// 未確定の入力を一つ確定する。
+    /// 未確定の入力のうち、規則のある一番長い部分を確定し、残りは入力しなおす。
+    fn flush_once(&self, state: &mut RomKanState, depth: usize) {
+        let pending = std::mem::take(&mut state.pending);
+        let matched = pending
+            .char_indices()
+            .map(|(idx, c)| idx + c.len_utf8())
+            .filter_map(|end| {
+                self.find(&pending[..end])
+                    .and_then(|node| node.rule.as_ref())
+                    .map(|rule| (end, rule))
+            })
+            .next_back();
+        let rest = match matched {
+            Some((end, rule)) => {
+                self.apply(state, rule, depth);
+                &pending[end..]
+            }
+            None => {
+                // "zy" のように、規則のない途中までの入力は、先頭の文字をそのまま出力する。
+                let c = pending.chars().next().unwrap();
+                state.kana.push(c);
+                &pending[c.len_utf8()..]
+            }
+        };
+        for c in rest.chars() {
+            self.push_with_depth(state, c, depth);
+        }
+    }
+
+    /// 未確定の入力をすべて確定する。
+    pub fn flush(&self, state: &mut RomKanState) {
+        while !state.pending.is_empty() {
+            self.flush_once(state, 0);
+        }
+    }
+
+    /// state の未確定の入力をすべて確定したかなを返す。state は変えない。
+    pub fn flushed_kana(&self, state: &RomKanState) -> String {
+        if state.pending.is_empty() {
+            return state.kana.clone();
+        }
+        let mut state = state.clone();
+        self.flush(&mut state);
+        state.kana
+    }
+
+    /// src を一文字ずつ入力した時の状態を、入力前の空の状態から順に返す。
+    pub fn states(&self, src: &str) -> Vec<RomKanState> {
+        let mut states = vec![RomKanState::default()];
+        for c in src.chars() {
+            let mut state = states.last().unwrap().clone();
+            self.push(&mut state, c);
+            states.push(state);
+        }
+        states
+    }
+}
+
+/// ローマ字かな変換の途中の状態。
+/// 変換器を持たないので、入力ごとに残しておいて、バックスペースで前の状態に戻せる。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RomKanState {
+    /// 確定したかな
+    kana: String,
+    /// まだ確定していない入力。"n" のように、次の入力によって変換結果が変わるもの。
+    pending: String,
+}
+
+impl RomKanState {
+    pub fn kana(&self) -> &str {
+        &self.kana
+    }
+
+    pub fn pending(&self) -> &str {
+        &self.pending
+    }
+
+    /// 表示する文字列。確定したかなの後ろに、未確定の入力を続ける。
+    pub fn display(&self) -> String {
+        self.kana.clone() + self.pending.as_str()
+    }
+}
+
+/// 一文字ずつ入力した時の状態の列から、最後の一文字分の入力を消した時に残す文字数を返す。
+/// 「っ」と "t" のように、一度に表示が変わった入力はまとめて消す。
+pub fn remove_last_char_len(states: &[RomKanState]) -> usize {
+    let Some(current) = states.last() else {
+        return 0;
+    };
+    let display = current.display();
+    // 消した後の表示が、今の表示の先頭部分になるまで削る。
+    (0..states.len() - 1)
+        .rev()
+        .find(|&len| {
+            let rest_display = states[len].display();
+            rest_display.len() < display.len() && display.starts_with(&rest_display)
+        })
+        .unwrap_or(0)
 }
 
 impl RomKanConverter {
     pub fn to_hiragana(&self, src: &str) -> String {
-        let src = src.to_ascii_lowercase();
-        let src = src.replace("nn", "n'"); // replace nn as n'.
-        let retval = self.romkan_pattern.replace_all(&src, |caps: &Captures| {
-            let rom = caps.get(1).unwrap().as_str();
-            if let Some(e) = self.romkan_map.get(rom) {
-                e.to_string()
-            } else {
-                rom.to_string()
-            }
-        });
-        retval.into_owned()
+        let mut state = RomKanState::default();
+        src.chars().for_each(|c| self.push(&mut state, c));
+        self.flush(&mut state);
+        state.kana
     }
 
+    /// src を変換して、(確定したかな, 未確定の入力) を返す。
+    /// "hogen" なら ("ほげ", "n") になる。
+    pub fn to_hiragana_with_pending(&self, src: &str) -> (String, String) {
+        let mut state = RomKanState::default();
+        src.chars().for_each(|c| self.push(&mut state, c));
+        (state.kana, state.pending)
+    }
+
+    /// 最後の一文字分の入力を消す。
+    /// "aka" なら "ka" が「か」になっているので "a" になり、"sok" なら "so" になる。
     pub fn remove_last_char(&self, src: &str) -> String {
-        self.last_char_pattern.replace(src, "").to_string()
+        let len = remove_last_char_len(&self.states(src));
+        src.chars().take(len).collect()
     }
 }
 
@@ -162,6 +556,18 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_set_punctuation() -> anyhow::Result<()> {
+        let mut converter = RomKanConverter::default_mapping()?;
+        converter.set_punctuation(PunctuationStyle::Western);
+        assert_eq!(converter.to_hiragana("a,i."), "あ，い．");
+        converter.set_punctuation(PunctuationStyle::Mixed);
+        assert_eq!(converter.to_hiragana("a,i."), "あ，い。");
+        converter.set_punctuation(PunctuationStyle::Japanese);
+        assert_eq!(converter.to_hiragana("a,i."), "あ、い。");
+        Ok(())
+    }
+
     #[test]
     fn remove_last_char() -> anyhow::Result<()> {
         let _ = env_logger::builder()
@@ -184,6 +590,23 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_states() -> anyhow::Result<()> {
+        let romkan = RomKanConverter::default_mapping()?;
+        let states = romkan.states("sonn");
+        assert_eq!(states.len(), 5);
+        assert_eq!(states[0], RomKanState::default());
+        assert_eq!((states[3].kana(), states[3].pending()), ("そ", "n"));
+        assert_eq!(states[4].display(), "そん");
+        // 前の状態に一文字入力すると、次の状態になる
+        let mut state = states[2].clone();
+        romkan.push(&mut state, 'n');
+        assert_eq!(state, states[3]);
+        assert_eq!(romkan.flushed_kana(&states[3]), "そん");
+        assert_eq!(remove_last_char_len(&states), 2);
+        Ok(())
+    }
+
     #[test]
     fn test_atok() -> anyhow::Result<()> {
         let _ = env_logger::builder()
@@ -209,4 +632,163 @@ mod tests {
         assert_eq!(converter.to_hiragana("dn"), "だん");
         Ok(())
     }
+
+    #[test]
+    fn test_to_hiragana_with_pending() -> anyhow::Result<()> {
+        let converter = RomKanConverter::default_mapping()?;
+        let cases = [
+            ("hogen", ("ほげ", "n")),
+            ("hogena", ("ほげな", "")),
+            ("hogenn", ("ほげん", "")),
+            ("meny", ("め", "ny")),
+            ("u-nnwwww", ("うーんwww", "w")),
+            ("sozh", ("そ←", "")),
+        ];
+        for (src, (kana, pending)) in cases {
+            assert_eq!(
+                converter.to_hiragana_with_pending(src),
+                (kana.to_string(), pending.to_string()),
+                "{src}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rule_with_next() {
+        let converter = RomKanConverter::from_map(
+            "test",
+            HashMap::from([
+                ("ka".to_string(), RomKanRule::Kana("か".to_string())),
+                ("ta".to_string(), RomKanRule::Kana("た".to_string())),
+                (
+                    "tt".to_string(),
+                    RomKanRule::WithNext {
+                        kana: "っ".to_string(),
+                        next: "t".to_string(),
+                    },
+                ),
+                // 循環していても止まる
+                (
+                    "x".to_string(),
+                    RomKanRule::WithNext {
+                        kana: "".to_string(),
+                        next: "x".to_string(),
+                    },
+                ),
+            ]),
+        );
+        assert_eq!(converter.to_hiragana("katta"), "かった");
+        assert_eq!(converter.to_hiragana("kattta"), "かっった");
+        assert_eq!(
+            converter.to_hiragana_with_pending("katt"),
+            ("かっ".to_string(), "t".to_string())
+        );
+        // 「っ」と "t" をまとめて消す
+        assert_eq!(converter.remove_last_char("katt"), "ka");
+        assert!(converter.to_hiragana("x").ends_with('x'));
+    }
+
+    #[test]
+    fn test_kana() -> anyhow::Result<()> {
+        let converter = RomKanConverter::new("../romkan/kana.yml")?;
+        // 濁点がつくかもしれないので、未確定のまま表示する
+        assert_eq!(
+            converter.to_hiragana_with_pending("t"),
+            ("".to_string(), "か".to_string())
+        );
+        assert_eq!(converter.to_hiragana("t@"), "が");
+        assert_eq!(converter.to_hiragana("tt@"), "かが");
+        assert_eq!(converter.to_hiragana("f["), "ぱ");
+        assert_eq!(converter.to_hiragana("t"), "か");
+        assert_eq!(converter.to_hiragana("Z"), "っ");
+        assert_eq!(converter.remove_last_char("tt@"), "t");
+        Ok(())
+    }
+
+    #[test]
+    fn test_tut() -> anyhow::Result<()> {
+        let converter = RomKanConverter::new("../romkan/tut.yml")?;
+        // nn を「ん」とみなさない
+        assert_eq!(converter.to_hiragana("nna"), "罫");
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_overlay() -> anyhow::Result<()> {
+        let overlay: BTreeMap<String, Option<RomKanRule>> = serde_yaml::from_str(
+            r#"
+wi: ゐ
+z/: ／
+tt: {kana: っ, next: t}
+zya: null
+"#,
+        )?;
+        let converter = RomKanConverter::with_overlay("../romkan/default.yml", &overlay)?;
+        assert_eq!(converter.to_hiragana("wiz/"), "ゐ／");
+        assert_eq!(converter.to_hiragana("zya"), "zや");
+        assert_eq!(converter.to_hiragana("kattr"), "かっtr");
+
+        // "z" を追加しても、"za" などがあると "z" にならない
+        let overlay = BTreeMap::from([("z".to_string(), Some(RomKanRule::Kana("ず".to_string())))]);
+        let err = RomKanConverter::with_overlay("../romkan/default.yml", &overlay).unwrap_err();
+        assert!(err.to_string().contains(r#""z" is a prefix of"#), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_longest_rule() {
+        let converter = RomKanConverter::from_map(
+            "test",
+            HashMap::from([
+                ("t".to_string(), RomKanRule::Kana("T".to_string())),
+                ("tsa".to_string(), RomKanRule::Kana("つぁ".to_string())),
+                ("so".to_string(), RomKanRule::Kana("そ".to_string())),
+            ]),
+        );
+        // "ts" には規則がないので、"t" を確定してから "so" を変換する
+        assert_eq!(converter.to_hiragana("tso"), "Tそ");
+    }
+
+    #[test]
+    fn test_jis_kana() -> anyhow::Result<()> {
+        let converter = RomKanConverter::new("../romkan/jis-kana.yml")?;
+        assert!(converter.uses_keycodes());
+        // 「ろ」と「ー」はキーコードで区別する
+        assert_eq!(converter.keycode_input(89, false), Some("ろ"));
+        assert_eq!(converter.keycode_input(124, false), Some("ー"));
+        // シフトで小さいかな
+        assert_eq!(converter.keycode_input(4, true), Some("ぁ"));
+        assert_eq!(converter.keycode_input(4, false), Some("あ"));
+        // シフトの指定がないキーは、シフトを押していても同じ
+        assert_eq!(converter.keycode_input(2, true), Some("ぬ"));
+
+        let input = [20, 26, 33, 27, 44]
+            .iter()
+            .map(|keycode| converter.keycode_input(*keycode, false).unwrap())
+            .collect::<String>();
+        assert_eq!(converter.to_hiragana(&input), "がぱつ");
+        assert_eq!(
+            converter.to_hiragana_with_pending("か"),
+            ("".to_string(), "か".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_nicola() -> anyhow::Result<()> {
+        let converter = RomKanConverter::new("../romkan/nicola.yml")?;
+        let thumb_shift = converter.thumb_shift().unwrap();
+        assert_eq!(thumb_shift.thumb(94), Some(Thumb::Left));
+        assert_eq!(thumb_shift.thumb(92), Some(Thumb::Right));
+        assert_eq!(thumb_shift.thumb(30), None);
+
+        // a キー
+        assert_eq!(converter.keycode_input(30, false), Some("う"));
+        assert_eq!(converter.thumb_shift_input(30, Thumb::Left), Some("を"));
+        assert_eq!(converter.thumb_shift_input(30, Thumb::Right), Some("ゔ"));
+        // q キーは右親指の指定がないので、単独と同じ
+        assert_eq!(converter.thumb_shift_input(16, Thumb::Right), Some("。"));
+        Ok(())
+    }
 }
@@ -0,0 +1,35 @@
+//! テストで使う補助関数。環境変数を書き換えるテストで共有する。
+
+use std::env;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+/// 環境変数を一時的に書き換え、drop した時に元に戻す。
+pub struct EnvVarGuard {
+    key: &'static str,
+    prev: Option<String>,
+}
+
+impl EnvVarGuard {
+    pub fn set(key: &'static str, value: &Path) -> Self {
+        let prev = env::var(key).ok();
+        env::set_var(key, value);
+        Self { key, prev }
+    }
+}
+
+impl Drop for EnvVarGuard {
+    fn drop(&mut self) {
+        if let Some(value) = &self.prev {
+            env::set_var(self.key, value);
+        } else {
+            env::remove_var(self.key);
+        }
+    }
+}
+
+/// 環境変数はプロセス全体で共有されるので、書き換えるテストはこのロックを取ってから実行する。
+pub fn env_lock() -> &'static Mutex<()> {
+    static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+    LOCK.get_or_init(|| Mutex::new(()))
+}
@@ -3,4 +3,5 @@ mod skip_bigram_user_stats;
 mod unigram_user_stats;
 // 調整めんどくさいのでいったんオフ。
 pub mod user_data;
+mod user_journal;
 mod user_stats_utils;
@@ -2,6 +2,9 @@ use rustc_hash::FxHashMap;
 
 use crate::cost::calc_cost;
 use crate::graph::candidate::Candidate;
+use crate::user_side_data::user_stats_utils::{
+    count_words, select_entries_to_evict, sort_by_recency,
+};
 
 #[derive(Default)]
 pub(crate) struct SkipBigramUserStats {
@@ -11,19 +14,28 @@ pub(crate) struct SkipBigramUserStats {
     total_words: u32,
     /// skip-bigram の出現頻度。"surface1/kana1\tsurface2/kana2" がキー。
     pub(crate) word_count: FxHashMap<String, u32>,
+    /// 最後に使われた時の clock の値。古いエントリーから削除するのに使う。
+    last_used: FxHashMap<String, u64>,
+    /// 学習するたびに増える通し番号。
+    clock: u64,
 }
 
 impl SkipBigramUserStats {
-    pub(crate) fn new(
-        unique_words: u32,
-        total_words: u32,
-        word_count: FxHashMap<String, u32>,
-    ) -> SkipBigramUserStats {
-        SkipBigramUserStats {
-            unique_words,
-            total_words,
-            word_count,
+    /// ファイルから読み込んだエントリーから構築する。
+    /// 後ろにあるエントリーほど最近使われたものとして扱う。
+    pub(crate) fn from_entries(entries: Vec<(String, u32)>) -> SkipBigramUserStats {
+        let mut stats = SkipBigramUserStats::default();
+        for (key, count) in entries {
+            stats.touch(&key);
+            stats.word_count.insert(key, count);
         }
+        (stats.unique_words, stats.total_words) = count_words(&stats.word_count);
+        stats
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        self.last_used.insert(key.to_string(), self.clock);
     }
 
     /// skip-bigram のエッジコストを計算する。
@@ -51,6 +63,7 @@ impl SkipBigramUserStats {
             };
 
             let key = candidate1.key() + "\t" + candidate2.key().as_str();
+            self.touch(&key);
             if let Some(cnt) = self.word_count.get(&key) {
                 self.word_count.insert(key, cnt + 1);
             } else {
@@ -60,4 +73,42 @@ impl SkipBigramUserStats {
             self.total_words += 1;
         }
     }
+
+    /// 条件に合うエントリーだけを残し、ユニーク単語数と総単語出現数を数え直す。
+    /// 削除したエントリー数を返す。
+    pub(crate) fn retain<F: FnMut(&String, &mut u32) -> bool>(&mut self, f: F) -> usize {
+        let before = self.word_count.len();
+        self.word_count.retain(f);
+        self.last_used
+            .retain(|key, _| self.word_count.contains_key(key));
+        (self.unique_words, self.total_words) = count_words(&self.word_count);
+        before - self.word_count.len()
+    }
+
+    /// 別の統計データの出現回数を足し合わせ、ユニーク単語数と総単語出現数を数え直す。
+    /// 取り込んだエントリーは、相手側で使われた順に最近使われたものとして扱う。
+    pub(crate) fn merge(&mut self, other: &SkipBigramUserStats) {
+        for (key, count) in other.entries_by_recency() {
+            self.touch(key);
+            let entry = self.word_count.entry(key.clone()).or_insert(0);
+            *entry = entry.saturating_add(count);
+        }
+        (self.unique_words, self.total_words) = count_words(&self.word_count);
+    }
+
+    /// 古いものから順に並べたエントリー。ファイルにはこの順で書き出す。
+    pub(crate) fn entries_by_recency(&self) -> Vec<(&String, u32)> {
+        sort_by_recency(&self.word_count, &self.last_used)
+    }
+
+    /// エントリー数が max_entries を超えていたら、価値の低いものから削除する。
+    /// 削除したエントリー数を返す。
+    pub(crate) fn shrink_to(&mut self, max_entries: usize) -> usize {
+        let evicted =
+            select_entries_to_evict(&self.word_count, &self.last_used, self.clock, max_entries);
+        if evicted.is_empty() {
+            return 0;
+        }
+        self.retain(|key, _| !evicted.contains(key))
+    }
 }
@@ -1,4 +1,6 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
@@ -6,7 +8,9 @@ use anyhow::Result;
 use encoding_rs::UTF_8;
 use log::{info, warn};
 use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
 
+use crate::config::UserDataConfig;
 use crate::dict::skk::read::read_skkdict;
 use crate::dict::skk::write::write_skk_dict;
 use crate::graph::candidate::Candidate;
@@ -15,10 +19,42 @@ use crate::kana_trie::cedarwood_kana_trie::CedarwoodKanaTrie;
 use crate::user_side_data::bigram_user_stats::BiGramUserStats;
 use crate::user_side_data::skip_bigram_user_stats::SkipBigramUserStats;
 use crate::user_side_data::unigram_user_stats::UniGramUserStats;
+use crate::user_side_data::user_journal::{
+    append_journal_file, clear_journal_file, read_journal_file,
+};
 use crate::user_side_data::user_stats_utils::{read_user_stats_file, write_user_stats_file};
 
+const UNIGRAM_FILE: &str = "unigram.v1.txt";
+const BIGRAM_FILE: &str = "bigram.v1.txt";
+const SKIP_BIGRAM_FILE: &str = "skip_bigram.v1.txt";
+const DICT_FILE: &str = "SKK-JISYO.user";
+const JOURNAL_FILE: &str = "journal.v1.txt";
+
+/// ジャーナルにこの回数分の学習が溜まったら、スナップショットファイルに書き出す。
+const JOURNAL_COMPACTION_THRESHOLD: usize = 500;
+
+/// ユーザー固有データをまるごと書き出すための形式。
+/// `akaza-data user-data export/import` で使う。
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct UserDataSnapshot {
+    /// "漢字/かな" ごとの出現回数
+    #[serde(default)]
+    pub unigram: BTreeMap<String, u32>,
+    /// "漢字1/かな1\t漢字2/かな2" ごとの出現回数
+    #[serde(default)]
+    pub bigram: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub skip_bigram: BTreeMap<String, u32>,
+    /// ユーザー辞書。読み => 表記のリスト
+    #[serde(default)]
+    pub dict: BTreeMap<String, Vec<String>>,
+}
+
 /**
  * ユーザー固有データ
+ *
+ * 学習結果は `record_entries` のたびにジャーナルへ追記し、ある程度溜まったところで
+ * unigram/bigram などのスナップショットファイルへ書き出す(コンパクション)。
  */
 #[derive(Default)]
 pub struct UserData {
@@ -36,45 +72,46 @@ pub struct UserData {
     bigram_path: Option<String>,
     skip_bigram_path: Option<String>,
     dict_path: Option<String>,
+    journal_path: Option<String>,
 
     pub dict: FxHashMap<String, Vec<String>>,
 
+    /// スナップショットファイルに書き出していない学習結果があるか。
     pub(crate) need_save: bool,
+    /// ジャーナルに溜まっている学習の回数。
+    journal_records: usize,
+    /// ジャーナルへの追記に失敗した学習結果があるか。
+    /// その場合は、次回の保存でスナップショットを書き出す。
+    journal_failed: bool,
+    /// 学習が行われたことを保存スレッドに通知する。
+    save_notifier: Option<Sender<()>>,
+    /// 統計データのエントリー数の上限。
+    limits: UserDataConfig,
 }
 
 impl UserData {
-    pub fn load_from_default_path() -> Result<Self> {
+    /// ユーザーデータの保存先ディレクトリ。 `~/.local/share/akaza/` になる。
+    pub fn default_data_dir() -> Result<PathBuf> {
         let basedir = xdg::BaseDirectories::with_prefix("akaza")?;
-        let unigram_path = basedir
-            .place_data_file(Path::new("unigram.v1.txt"))?
-            .to_str()
-            .unwrap()
-            .to_string();
-        let bigram_path = basedir
-            .place_data_file(Path::new("bigram.v1.txt"))?
-            .to_str()
-            .unwrap()
-            .to_string();
-        let skip_bigram_path = basedir
-            .place_data_file(Path::new("skip_bigram.v1.txt"))?
-            .to_str()
-            .unwrap()
-            .to_string();
-        let dict_path = basedir
-            .place_data_file(Path::new("SKK-JISYO.user"))?
-            .to_str()
-            .unwrap()
-            .to_string();
-        info!(
-            "Load user data from default path: unigram={}, bigram={}, skip_bigram={}",
-            unigram_path, bigram_path, skip_bigram_path
-        );
-        Ok(UserData::load(
-            &unigram_path,
-            &bigram_path,
-            &skip_bigram_path,
-            &dict_path,
-        ))
+        Ok(basedir.create_data_directory("")?)
+    }
+
+    pub fn load_from_default_path() -> Result<Self> {
+        let data_dir = Self::default_data_dir()?;
+        info!("Load user data from default path: {}", data_dir.display());
+        Ok(UserData::load_from_dir(&data_dir))
+    }
+
+    /// 指定したディレクトリに置かれているユーザーデータを読み込む。
+    pub fn load_from_dir(data_dir: &Path) -> Self {
+        let path = |name: &str| data_dir.join(name).to_string_lossy().to_string();
+        UserData::load(
+            &path(UNIGRAM_FILE),
+            &path(BIGRAM_FILE),
+            &path(SKIP_BIGRAM_FILE),
+            &path(DICT_FILE),
+            &path(JOURNAL_FILE),
+        )
     }
 
     pub fn load(
@@ -82,65 +119,42 @@ impl UserData {
         bigram_path: &String,
         skip_bigram_path: &String,
         dict_path: &String,
+        journal_path: &String,
     ) -> Self {
         // ユーザーデータが読み込めないことは fatal エラーではない。
         // 初回起動時にはデータがないので。
         // データがなければ初期所状態から始める
         let unigram_user_stats = match read_user_stats_file(unigram_path) {
-            Ok(dat) => {
-                let unique_count = dat.len() as u32;
-                let total_count: u32 = dat.iter().map(|f| f.1).sum();
-                let mut word_count: FxHashMap<String, u32> = FxHashMap::default();
-                for (word, count) in dat {
-                    word_count.insert(word, count);
-                }
-                UniGramUserStats::new(unique_count, total_count, word_count)
-            }
+            Ok(dat) => UniGramUserStats::from_entries(dat),
             Err(err) => {
                 warn!(
                     "Cannot load user unigram data from {}: {}",
                     unigram_path, err
                 );
 
-                UniGramUserStats::new(0, 0, FxHashMap::default())
+                UniGramUserStats::default()
             }
         };
 
         // build bigram
         let bigram_user_stats = match read_user_stats_file(bigram_path) {
-            Ok(dat) => {
-                let unique_count = dat.len() as u32;
-                let total_count: u32 = dat.iter().map(|f| f.1).sum();
-                let mut words_count: FxHashMap<String, u32> = FxHashMap::default();
-                for (words, count) in dat {
-                    words_count.insert(words, count);
-                }
-                BiGramUserStats::new(unique_count, total_count, words_count)
-            }
+            Ok(dat) => BiGramUserStats::from_entries(dat),
             Err(err) => {
                 warn!("Cannot load user bigram data from {}: {}", bigram_path, err);
                 // ユーザーデータは初回起動時などにはないので、データがないものとして処理を続行する
-                BiGramUserStats::new(0, 0, FxHashMap::default())
+                BiGramUserStats::default()
             }
         };
 
         // build skip-bigram
         let skip_bigram_user_stats = match read_user_stats_file(skip_bigram_path) {
-            Ok(dat) => {
-                let unique_count = dat.len() as u32;
-                let total_count: u32 = dat.iter().map(|f| f.1).sum();
-                let mut words_count: FxHashMap<String, u32> = FxHashMap::default();
-                for (words, count) in dat {
-                    words_count.insert(words, count);
-                }
-                SkipBigramUserStats::new(unique_count, total_count, words_count)
-            }
+            Ok(dat) => SkipBigramUserStats::from_entries(dat),
             Err(err) => {
                 warn!(
                     "Cannot load user skip-bigram data from {}: {}",
                     skip_bigram_path, err
                 );
-                SkipBigramUserStats::new(0, 0, FxHashMap::default())
+                SkipBigramUserStats::default()
             }
         };
 
@@ -152,6 +166,43 @@ impl UserData {
             }
         };
 
+        let mut user_data = UserData {
+            unigram_user_stats,
+            bigram_user_stats,
+            skip_bigram_user_stats,
+            dict,
+            kana_trie: Default::default(),
+            unigram_path: Some(unigram_path.clone()),
+            bigram_path: Some(bigram_path.clone()),
+            skip_bigram_path: Some(skip_bigram_path.clone()),
+            dict_path: Some(dict_path.clone()),
+            journal_path: Some(journal_path.clone()),
+            need_save: false,
+            journal_records: 0,
+            journal_failed: false,
+            save_notifier: None,
+            limits: UserDataConfig::default(),
+        };
+
+        // 前回のコンパクション以降の学習結果をジャーナルから復元する。
+        match read_journal_file(journal_path) {
+            Ok(records) => {
+                info!(
+                    "Replaying {} records from user journal: {}",
+                    records.len(),
+                    journal_path
+                );
+                for candidates in &records {
+                    user_data.record_stats(candidates);
+                }
+                user_data.journal_records = records.len();
+                user_data.need_save = !records.is_empty();
+            }
+            Err(err) => {
+                warn!("Cannot load user journal from {}: {}", journal_path, err);
+            }
+        }
+
         // let kana_trie = match CedarwoodKanaTrie::load(kana_trie_path) {
         //     Ok(trie) => trie,
         //     Err(err) => {
@@ -160,17 +211,26 @@ impl UserData {
         //     }
         // };
 
+        user_data.rebuild_kana_trie();
+        user_data
+    }
+
+    /// 統計データとユーザー辞書の読みから、かなトライを作り直す。
+    fn rebuild_kana_trie(&mut self) {
         // cedarwood トライを構築する。
         // キャッシュせずに動的に構築する方向性。
         let t1 = SystemTime::now();
-        let mut yomis = unigram_user_stats
+        let mut yomis = self
+            .unigram_user_stats
             .word_count
             .keys()
             .filter_map(|it| it.split_once('/'))
             .map(|(_, yomi)| yomi.to_string())
             .collect::<Vec<_>>();
         // ユーザー辞書の内容も追加
-        dict.keys().for_each(|yomi| yomis.push(yomi.to_string()));
+        self.dict
+            .keys()
+            .for_each(|yomi| yomis.push(yomi.to_string()));
         let yomi_len = yomis.len();
         let kana_trie = CedarwoodKanaTrie::build(yomis);
         let t2 = SystemTime::now();
@@ -180,23 +240,81 @@ impl UserData {
             yomi_len
         );
 
-        UserData {
-            unigram_user_stats,
-            bigram_user_stats,
-            skip_bigram_user_stats,
-            dict,
-            kana_trie: Arc::new(Mutex::new(kana_trie)),
-            unigram_path: Some(unigram_path.clone()),
-            bigram_path: Some(bigram_path.clone()),
-            skip_bigram_path: Some(skip_bigram_path.clone()),
-            dict_path: Some(dict_path.clone()),
-            need_save: false,
-        }
+        // セグメンターとトライを共有しているので、中身を入れ替える。
+        *self.kana_trie.lock().unwrap() = kana_trie;
     }
 
     /// 入力確定した漢字のリストをユーザー統計データとして記録する。
     /// "Surface/Kana" のフォーマットで渡すこと。
     pub fn record_entries(&mut self, candidates: &[Candidate]) {
+        self.record_stats(candidates);
+
+        // かなトライを更新する
+        let mut kana_trie = self.kana_trie.lock().unwrap();
+        candidates
+            .iter()
+            .map(|it| it.yomi.to_string())
+            .for_each(|it| {
+                if !kana_trie.contains(it.as_str()) {
+                    kana_trie.update(it.as_str())
+                }
+            });
+        drop(kana_trie);
+
+        if let Some(journal_path) = &self.journal_path {
+            match append_journal_file(journal_path, candidates) {
+                Ok(()) => self.journal_records += 1,
+                Err(err) => {
+                    warn!("Cannot append to user journal {}: {}", journal_path, err);
+                    self.journal_failed = true;
+                }
+            }
+        }
+
+        self.need_save = true;
+
+        if let Some(notifier) = &self.save_notifier {
+            // 保存スレッドが終了している場合は送れないが、ジャーナルには書けているので問題ない。
+            let _ = notifier.send(());
+        }
+    }
+
+    /// 統計データのエントリー数の上限を設定する。上限を超えた分は、次のコンパクションで削除される。
+    pub fn set_limits(&mut self, limits: UserDataConfig) {
+        self.limits = limits;
+    }
+
+    /// 上限を超えている統計データを削除する。削除したエントリー数を返す。
+    fn enforce_limits(&mut self) -> usize {
+        let unigram = self
+            .unigram_user_stats
+            .shrink_to(self.limits.max_unigram_entries);
+        let bigram = self
+            .bigram_user_stats
+            .shrink_to(self.limits.max_bigram_entries);
+        let skip_bigram = self
+            .skip_bigram_user_stats
+            .shrink_to(self.limits.max_skip_bigram_entries);
+        if unigram + bigram + skip_bigram > 0 {
+            info!(
+                "Pruned user stats exceeding the limits: unigram={}, bigram={}, skip_bigram={}",
+                unigram, bigram, skip_bigram
+            );
+        }
+        if unigram > 0 {
+            self.rebuild_kana_trie();
+        }
+        unigram + bigram + skip_bigram
+    }
+
+    /// 学習のたびに通知を受け取る。
+    pub fn set_save_notifier(&mut self, notifier: Sender<()>) {
+        self.save_notifier = Some(notifier);
+    }
+
+    /// 統計データとユーザー辞書に学習結果を反映する。
+    /// ジャーナルの再生時にも使う。
+    fn record_stats(&mut self, candidates: &[Candidate]) {
         self.unigram_user_stats.record_entries(candidates);
         self.bigram_user_stats.record_entries(candidates);
         self.skip_bigram_user_stats.record_entries(candidates);
@@ -211,23 +329,26 @@ impl UserData {
                     .or_default()
                     .push(candidate.surface.to_string())
             });
+    }
 
-        // かなトライを更新する
-        let mut kana_trie = self.kana_trie.lock().unwrap();
-        candidates
-            .iter()
-            .map(|it| it.yomi.to_string())
-            .for_each(|it| {
-                if !kana_trie.contains(it.as_str()) {
-                    kana_trie.update(it.as_str())
-                }
-            });
+    /// 定期的に呼ばれる保存処理。
+    /// 学習結果はジャーナルに追記済みなので、ジャーナルがある程度溜まった時だけコンパクションする。
+    pub fn write_user_files(&mut self) -> Result<()> {
+        if self.need_save
+            && (self.journal_path.is_none()
+                || self.journal_failed
+                || self.journal_records >= JOURNAL_COMPACTION_THRESHOLD)
+        {
+            self.compact()?;
+        }
 
-        self.need_save = true;
+        Ok(())
     }
 
-    pub fn write_user_files(&mut self) -> Result<()> {
+    /// スナップショットファイルを書き出して、ジャーナルを空にする。
+    pub fn compact(&mut self) -> Result<()> {
         if self.need_save {
+            self.enforce_limits();
             info!(
                 "Saving user stats file: unigram={:?},{}, bigram={:?},{}, skip_bigram={:?},{}",
                 self.unigram_path,
@@ -238,24 +359,141 @@ impl UserData {
                 self.skip_bigram_user_stats.word_count.len(),
             );
             if let Some(unigram_path) = &self.unigram_path {
-                write_user_stats_file(unigram_path, &self.unigram_user_stats.word_count)?;
+                write_user_stats_file(unigram_path, &self.unigram_user_stats.entries_by_recency())?;
             }
             if let Some(bigram_path) = &self.bigram_path {
-                write_user_stats_file(bigram_path, &self.bigram_user_stats.word_count)?;
+                write_user_stats_file(bigram_path, &self.bigram_user_stats.entries_by_recency())?;
             }
             if let Some(skip_bigram_path) = &self.skip_bigram_path {
-                write_user_stats_file(skip_bigram_path, &self.skip_bigram_user_stats.word_count)?;
+                write_user_stats_file(
+                    skip_bigram_path,
+                    &self.skip_bigram_user_stats.entries_by_recency(),
+                )?;
             }
             if let Some(dict_path) = &self.dict_path {
                 write_skk_dict(dict_path, vec![self.dict.clone().into_iter().collect()])?;
             }
+            // スナップショットを書き終えてからジャーナルを消す。
+            // 順序が逆だと、その間に落ちた時に学習結果が失われる。
+            if let Some(journal_path) = &self.journal_path {
+                clear_journal_file(journal_path)?;
+            }
 
             self.need_save = false;
+            self.journal_records = 0;
+            self.journal_failed = false;
         }
 
         Ok(())
     }
 
+    /// 現在の学習内容を書き出す。
+    pub fn snapshot(&self) -> UserDataSnapshot {
+        UserDataSnapshot {
+            unigram: self
+                .unigram_user_stats
+                .word_count
+                .clone()
+                .into_iter()
+                .collect(),
+            bigram: self
+                .bigram_user_stats
+                .word_count
+                .clone()
+                .into_iter()
+                .collect(),
+            skip_bigram: self
+                .skip_bigram_user_stats
+                .word_count
+                .clone()
+                .into_iter()
+                .collect(),
+            dict: self.dict.clone().into_iter().collect(),
+        }
+    }
+
+    /// 学習内容を snapshot の内容で置き換える。
+    /// ファイルに反映するには、このあと `compact` を呼ぶこと。
+    pub fn restore(&mut self, snapshot: UserDataSnapshot) {
+        self.unigram_user_stats =
+            UniGramUserStats::from_entries(snapshot.unigram.into_iter().collect());
+        self.bigram_user_stats =
+            BiGramUserStats::from_entries(snapshot.bigram.into_iter().collect());
+        self.skip_bigram_user_stats =
+            SkipBigramUserStats::from_entries(snapshot.skip_bigram.into_iter().collect());
+
+        self.dict = snapshot.dict.into_iter().collect();
+        self.rebuild_kana_trie();
+        self.need_save = true;
+    }
+
+    /// 別のマシンなどの学習結果を取り込む。出現回数は足し合わせ、ユーザー辞書は和集合をとる。
+    /// ファイルに反映するには、このあと `compact` を呼ぶこと。
+    pub fn merge(&mut self, other: &UserData) {
+        self.unigram_user_stats.merge(&other.unigram_user_stats);
+        self.bigram_user_stats.merge(&other.bigram_user_stats);
+        self.skip_bigram_user_stats
+            .merge(&other.skip_bigram_user_stats);
+
+        for (yomi, surfaces) in &other.dict {
+            let entry = self.dict.entry(yomi.clone()).or_default();
+            for surface in surfaces {
+                if !entry.contains(surface) {
+                    entry.push(surface.clone());
+                }
+            }
+        }
+
+        self.rebuild_kana_trie();
+        self.need_save = true;
+    }
+
+    /// 単語の学習結果を忘れる。その単語を含む bigram/skip-bigram と、ユーザー辞書の登録も消す。
+    /// 削除したエントリー数を返す。
+    pub fn remove_word(&mut self, surface: &str, yomi: &str) -> usize {
+        let key = format!("{surface}/{yomi}");
+        let contains_key = |words: &String| words.split('\t').any(|word| word == key);
+
+        let mut removed = self.unigram_user_stats.retain(|word, _| *word != key);
+        removed += self
+            .bigram_user_stats
+            .retain(|words, _| !contains_key(words));
+        removed += self
+            .skip_bigram_user_stats
+            .retain(|words, _| !contains_key(words));
+
+        if let Some(surfaces) = self.dict.get_mut(yomi) {
+            let before = surfaces.len();
+            surfaces.retain(|it| it != surface);
+            removed += before - surfaces.len();
+            if surfaces.is_empty() {
+                self.dict.remove(yomi);
+            }
+        }
+
+        if removed > 0 {
+            self.rebuild_kana_trie();
+            self.need_save = true;
+        }
+        removed
+    }
+
+    /// 出現回数が min_count 未満のエントリーを統計データから削除する。
+    /// 削除したエントリー数を返す。
+    pub fn prune(&mut self, min_count: u32) -> usize {
+        let removed = self.unigram_user_stats.retain(|_, cnt| *cnt >= min_count)
+            + self.bigram_user_stats.retain(|_, cnt| *cnt >= min_count)
+            + self
+                .skip_bigram_user_stats
+                .retain(|_, cnt| *cnt >= min_count);
+
+        if removed > 0 {
+            self.rebuild_kana_trie();
+            self.need_save = true;
+        }
+        removed
+    }
+
     pub fn get_unigram_cost(&self, node: &WordNode) -> Option<f32> {
         self.unigram_user_stats.get_cost(&node.key())
     }
@@ -273,6 +511,8 @@ impl UserData {
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
     use log::LevelFilter;
 
     use super::*;
@@ -324,4 +564,193 @@ mod tests {
         info!("{}, {}", cost2, cost3);
         assert!(cost2 > cost3);
     }
+
+    fn watashi() -> WordNode {
+        WordNode::new(0, "私", "わたし", None, false)
+    }
+
+    #[test]
+    fn test_journal_replay() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+
+        {
+            let mut user_data = UserData::load_from_dir(tmpdir.path());
+            user_data.record_entries(&[Candidate::new("わたし", "私", 0_f32)]);
+            // スナップショットを書かずに終了しても、学習結果は失われない。
+            user_data.write_user_files()?;
+        }
+        assert!(!tmpdir.path().join("unigram.v1.txt").exists());
+
+        let user_data = UserData::load_from_dir(tmpdir.path());
+        assert!(user_data.get_unigram_cost(&watashi()).is_some());
+        assert!(user_data.kana_trie.lock().unwrap().contains("わたし"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+
+        let mut user_data = UserData::load_from_dir(tmpdir.path());
+        user_data.record_entries(&[Candidate::new("わたし", "私", 0_f32)]);
+        user_data.record_entries(&[Candidate::new("わたし", "私", 0_f32)]);
+        let cost = user_data.get_unigram_cost(&watashi());
+        user_data.compact()?;
+        assert!(!tmpdir.path().join("journal.v1.txt").exists());
+
+        // コンパクション後に読み直しても二重に数えられない。
+        let user_data = UserData::load_from_dir(tmpdir.path());
+        assert_eq!(user_data.get_unigram_cost(&watashi()), cost);
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_word() {
+        let mut user_data = UserData::default();
+        user_data.record_entries(&[
+            Candidate::new("わたし", "私", 0_f32),
+            Candidate::new("が", "が", 0_f32),
+            Candidate::new("いく", "行く", 0_f32),
+        ]);
+
+        // unigram 1件, bigram 1件, skip-bigram 1件
+        assert_eq!(user_data.remove_word("私", "わたし"), 3);
+        assert_eq!(user_data.get_unigram_cost(&watashi()), None);
+        let snapshot = user_data.snapshot();
+        assert_eq!(
+            snapshot.bigram.keys().collect::<Vec<_>>(),
+            vec!["が/が\t行く/いく"]
+        );
+        assert!(snapshot.skip_bigram.is_empty());
+        assert!(!user_data.kana_trie.lock().unwrap().contains("わたし"));
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut user_data = UserData::default();
+        user_data.record_entries(&[Candidate::new("わたし", "私", 0_f32)]);
+        user_data.record_entries(&[Candidate::new("わたし", "私", 0_f32)]);
+        user_data.record_entries(&[Candidate::new("かれ", "彼", 0_f32)]);
+
+        assert_eq!(user_data.prune(2), 1);
+        assert_eq!(
+            user_data.snapshot().unigram,
+            BTreeMap::from([("私/わたし".to_string(), 2)])
+        );
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+
+        let mut user_data = UserData::default();
+        user_data.record_entries(&[
+            Candidate::new("わたし", "私", 0_f32),
+            Candidate::new("が", "が", 0_f32),
+        ]);
+        let snapshot = user_data.snapshot();
+
+        let mut restored = UserData::load_from_dir(tmpdir.path());
+        restored.restore(snapshot);
+        restored.compact()?;
+
+        let restored = UserData::load_from_dir(tmpdir.path());
+        assert_eq!(restored.snapshot(), user_data.snapshot());
+        assert_eq!(
+            restored.get_unigram_cost(&watashi()),
+            user_data.get_unigram_cost(&watashi())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut desktop = UserData::default();
+        desktop.record_entries(&[
+            Candidate::new("わたし", "私", 0_f32),
+            Candidate::new("が", "が", 0_f32),
+        ]);
+        let mut laptop = UserData::default();
+        laptop.record_entries(&[Candidate::new("わたし", "私", 0_f32)]);
+        let mut compound = Candidate::new("ないかくそうりだいじん", "内閣総理大臣", 0_f32);
+        compound.compound_word = true;
+        laptop.record_entries(&[compound]);
+
+        desktop.merge(&laptop);
+
+        let snapshot = desktop.snapshot();
+        assert_eq!(
+            snapshot.unigram,
+            BTreeMap::from([
+                ("私/わたし".to_string(), 2),
+                ("が/が".to_string(), 1),
+                ("内閣総理大臣/ないかくそうりだいじん".to_string(), 1),
+            ])
+        );
+        assert_eq!(
+            snapshot.dict,
+            BTreeMap::from([(
+                "ないかくそうりだいじん".to_string(),
+                vec!["内閣総理大臣".to_string()]
+            )])
+        );
+        assert!(desktop
+            .kana_trie
+            .lock()
+            .unwrap()
+            .contains("ないかくそうりだいじん"));
+
+        // 同じ内容を一から学習した場合とコストが一致する
+        let mut expected = UserData::default();
+        expected.restore(snapshot);
+        assert_eq!(
+            desktop.get_unigram_cost(&watashi()),
+            expected.get_unigram_cost(&watashi())
+        );
+    }
+
+    #[test]
+    fn test_merge_saturates() {
+        let saturated = || {
+            let mut snapshot = UserData::default().snapshot();
+            snapshot.unigram.insert("私/わたし".to_string(), u32::MAX);
+            let mut user_data = UserData::default();
+            user_data.restore(snapshot);
+            user_data
+        };
+        let mut desktop = saturated();
+        let laptop = saturated();
+
+        desktop.merge(&laptop);
+
+        assert_eq!(desktop.snapshot().unigram["私/わたし"], u32::MAX);
+    }
+
+    #[test]
+    fn test_limits() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+
+        let mut user_data = UserData::load_from_dir(tmpdir.path());
+        user_data.set_limits(UserDataConfig {
+            max_unigram_entries: 2,
+            ..Default::default()
+        });
+        user_data.record_entries(&[Candidate::new("わたし", "私", 0_f32)]);
+        user_data.record_entries(&[Candidate::new("かれ", "彼", 0_f32)]);
+        user_data.record_entries(&[Candidate::new("かのじょ", "彼女", 0_f32)]);
+        user_data.compact()?;
+
+        // 一番古いものが消える
+        let user_data = UserData::load_from_dir(tmpdir.path());
+        assert_eq!(
+            user_data.snapshot().unigram.keys().collect::<Vec<_>>(),
+            vec!["彼/かれ", "彼女/かのじょ"]
+        );
+        // 使われた順にファイルに書かれている
+        assert_eq!(
+            fs::read_to_string(tmpdir.path().join(UNIGRAM_FILE))?,
+            "彼/かれ 1\n彼女/かのじょ 1\n"
+        );
+        Ok(())
+    }
 }
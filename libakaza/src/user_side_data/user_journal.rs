@@ -0,0 +1,171 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+
+use anyhow::{Context, Result};
+use log::warn;
+
+use crate::graph::candidate::Candidate;
+
+/// ユーザー学習のジャーナルファイルを読み込む。
+///
+/// 1行が `record_entries` 1回分に相当する。各エントリーは "読み\t表記\t複合語フラグ" の3つ組で、
+/// それがタブ区切りで並ぶ。書き込み途中でクラッシュした場合などの壊れた行は読み飛ばす。
+/// ファイルが存在しない場合は空として扱う。
+pub(crate) fn read_journal_file(path: &str) -> Result<Vec<Vec<Candidate>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("Cannot open journal: {path}")),
+    };
+
+    let mut result: Vec<Vec<Candidate>> = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.context("Cannot read user journal file")?;
+        if line.is_empty() {
+            continue;
+        }
+        match parse_journal_line(&line) {
+            Some(candidates) => result.push(candidates),
+            None => warn!("Skipped broken line in user journal {}: {:?}", path, line),
+        }
+    }
+
+    Ok(result)
+}
+
+fn parse_journal_line(line: &str) -> Option<Vec<Candidate>> {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    if fields.len() % 3 != 0 {
+        return None;
+    }
+
+    let mut candidates = Vec::with_capacity(fields.len() / 3);
+    for entry in fields.chunks(3) {
+        let (yomi, surface, compound_word) = (entry[0], entry[1], entry[2]);
+        if yomi.is_empty() || surface.is_empty() {
+            return None;
+        }
+        let mut candidate = Candidate::new(yomi, surface, 0_f32);
+        candidate.compound_word = match compound_word {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        };
+        candidates.push(candidate);
+    }
+    Some(candidates)
+}
+
+/// `record_entries` 1回分をジャーナルの末尾に追記する。
+pub(crate) fn append_journal_file(path: &str, candidates: &[Candidate]) -> Result<()> {
+    let mut line = String::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        if i > 0 {
+            line.push('\t');
+        }
+        line.push_str(&candidate.yomi);
+        line.push('\t');
+        line.push_str(&candidate.surface);
+        line.push('\t');
+        line.push(if candidate.compound_word { '1' } else { '0' });
+    }
+    line.push('\n');
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Cannot open journal: {path}"))?;
+    // 前回書き込み途中で落ちた行が残っていたら、それとつながらないように改行してから書く。
+    if file.metadata()?.len() > 0 {
+        let mut last = [0_u8; 1];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            line.insert(0, '\n');
+        }
+    }
+    // 1行をまとめて書くことで、途中まで書かれた行ができる可能性を減らす。
+    file.write_all(line.as_bytes())?;
+    // 電源断でも直近の学習結果が消えないように、ディスクに書き出しておく。
+    file.sync_data()?;
+
+    Ok(())
+}
+
+/// スナップショットに反映し終えたジャーナルを消す。
+pub(crate) fn clear_journal_file(path: &str) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err).with_context(|| format!("Cannot remove journal: {path}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_append_and_read() -> Result<()> {
+        let tmpdir = tempdir()?;
+        let path = tmpdir.path().join("journal.v1.txt");
+        let path = path.to_str().unwrap();
+
+        let mut compound = Candidate::new("ないかくそうりだいじん", "内閣総理大臣", 0_f32);
+        compound.compound_word = true;
+        append_journal_file(path, &[Candidate::new("わたし", "私", 0_f32)])?;
+        append_journal_file(path, &[compound.clone(), Candidate::new("が", "が", 0_f32)])?;
+
+        // 書き込み途中で落ちた行は読み飛ばされる
+        OpenOptions::new()
+            .append(true)
+            .open(path)?
+            .write_all("かれ\t彼".as_bytes())?;
+
+        let got = read_journal_file(path)?;
+        assert_eq!(
+            got,
+            vec![
+                vec![Candidate::new("わたし", "私", 0_f32)],
+                vec![compound, Candidate::new("が", "が", 0_f32)],
+            ]
+        );
+
+        clear_journal_file(path)?;
+        assert!(read_journal_file(path)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_after_torn_line() -> Result<()> {
+        let tmpdir = tempdir()?;
+        let path = tmpdir.path().join("journal.v1.txt");
+        let path = path.to_str().unwrap();
+
+        append_journal_file(path, &[Candidate::new("わたし", "私", 0_f32)])?;
+        // 改行を書く前に落ちた行の後に追記しても、次の行は壊れない
+        OpenOptions::new()
+            .append(true)
+            .open(path)?
+            .write_all("かれ\t彼".as_bytes())?;
+        append_journal_file(path, &[Candidate::new("あなた", "貴方", 0_f32)])?;
+
+        assert_eq!(
+            read_journal_file(path)?,
+            vec![
+                vec![Candidate::new("わたし", "私", 0_f32)],
+                vec![Candidate::new("あなた", "貴方", 0_f32)],
+            ]
+        );
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::{Context, Result};
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use std::fs;
 use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
@@ -27,7 +27,61 @@ pub(crate) fn read_user_stats_file(path: &String) -> Result<Vec<(String, u32)>>
     Ok(result)
 }
 
-pub(crate) fn write_user_stats_file(path: &str, word_count: &FxHashMap<String, u32>) -> Result<()> {
+/// (ユニーク単語数, 総単語出現数) を数える。
+pub(crate) fn count_words(word_count: &FxHashMap<String, u32>) -> (u32, u32) {
+    // 取り込んだデータの回数が大きくても溢れないようにする。
+    let total = word_count
+        .values()
+        .fold(0_u32, |total, count| total.saturating_add(*count));
+    (word_count.len() as u32, total)
+}
+
+/// last_used の古い順に並べる。
+pub(crate) fn sort_by_recency<'a>(
+    word_count: &'a FxHashMap<String, u32>,
+    last_used: &FxHashMap<String, u64>,
+) -> Vec<(&'a String, u32)> {
+    let mut entries = word_count
+        .iter()
+        .map(|(key, count)| (key, *count))
+        .collect::<Vec<_>>();
+    entries.sort_by_cached_key(|(key, _)| (last_used.get(*key).copied().unwrap_or(0), *key));
+    entries
+}
+
+/// エントリー数を max_entries に収めるために削除するキーを選ぶ。max_entries が 0 なら無制限。
+///
+/// 出現回数を、最後に使われてからの経過(学習回数)で減衰させた値が低いものから選ぶ。
+/// max_entries 回学習される間に使われなかったエントリーは、価値が半分になる。
+/// 出現回数だけで選ぶと、覚えたばかりの単語がすぐに消えてしまうので。
+pub(crate) fn select_entries_to_evict(
+    word_count: &FxHashMap<String, u32>,
+    last_used: &FxHashMap<String, u64>,
+    clock: u64,
+    max_entries: usize,
+) -> FxHashSet<String> {
+    if max_entries == 0 || word_count.len() <= max_entries {
+        return FxHashSet::default();
+    }
+
+    let score = |key: &String, count: u32| {
+        let age = clock - last_used.get(key).copied().unwrap_or(0);
+        count as f64 * 0.5_f64.powf(age as f64 / max_entries as f64)
+    };
+    let mut entries = sort_by_recency(word_count, last_used);
+    // 同じ価値なら古いものから消す(sort_by は安定ソート)。
+    entries.sort_by(|(key1, count1), (key2, count2)| {
+        score(key1, *count1).total_cmp(&score(key2, *count2))
+    });
+    entries
+        .into_iter()
+        .take(word_count.len() - max_entries)
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// entries の順にファイルに書き出す。
+pub(crate) fn write_user_stats_file(path: &str, entries: &[(&String, u32)]) -> Result<()> {
     let mut tmpfile = OpenOptions::new()
         .write(true)
         .create(true)
@@ -35,7 +89,7 @@ pub(crate) fn write_user_stats_file(path: &str, word_count: &FxHashMap<String, u
         .mode(0o600)
         .open(path.to_string() + ".tmp")?;
 
-    for (key, cnt) in word_count {
+    for (key, cnt) in entries {
         tmpfile.write_all(key.as_bytes())?;
         tmpfile.write_all(" ".as_bytes())?;
         tmpfile.write_all(cnt.to_string().as_bytes())?;
@@ -56,10 +110,48 @@ mod tests {
     fn test_write() {
         let tmpfile = NamedTempFile::new().unwrap();
         let path = tmpfile.path().to_str().unwrap().to_string();
-        let wc: FxHashMap<String, u32> = [("渡し".to_string(), 3_u32)].into_iter().collect();
-        write_user_stats_file(&path, &wc).unwrap();
+        let key = "渡し".to_string();
+        write_user_stats_file(&path, &[(&key, 3_u32)]).unwrap();
         let mut buf = String::new();
         File::open(path).unwrap().read_to_string(&mut buf).unwrap();
         assert_eq!(buf, "渡し 3\n");
     }
+
+    #[test]
+    fn test_select_entries_to_evict() {
+        let word_count: FxHashMap<String, u32> = [
+            ("よく使う".to_string(), 4),
+            ("たまに使う".to_string(), 1),
+            ("さっき使った".to_string(), 1),
+        ]
+        .into_iter()
+        .collect();
+        let last_used: FxHashMap<String, u64> = [
+            ("よく使う".to_string(), 1),
+            ("たまに使う".to_string(), 2),
+            ("さっき使った".to_string(), 3),
+        ]
+        .into_iter()
+        .collect();
+
+        assert!(select_entries_to_evict(&word_count, &last_used, 3, 0).is_empty());
+        assert!(select_entries_to_evict(&word_count, &last_used, 3, 3).is_empty());
+        assert_eq!(
+            select_entries_to_evict(&word_count, &last_used, 3, 2),
+            ["たまに使う".to_string()].into_iter().collect()
+        );
+
+        // 長い間使われていなければ、出現回数が多くても消される
+        let last_used: FxHashMap<String, u64> = [
+            ("よく使う".to_string(), 1),
+            ("たまに使う".to_string(), 10),
+            ("さっき使った".to_string(), 11),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            select_entries_to_evict(&word_count, &last_used, 11, 2),
+            ["よく使う".to_string()].into_iter().collect()
+        );
+    }
 }
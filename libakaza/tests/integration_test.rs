@@ -2,6 +2,9 @@ use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
+use libakaza::config::{DictConfig, DictEncoding, DictType, DictUsage, EngineConfig};
+use libakaza::engine::base::HenkanEngine;
+use libakaza::engine::bigram_word_viterbi_engine::BigramWordViterbiEngineBuilder;
 use libakaza::graph::graph_builder::GraphBuilder;
 use libakaza::graph::graph_resolver::GraphResolver;
 use libakaza::graph::segmenter::Segmenter;
@@ -303,3 +306,33 @@ fn test_user_dict_and_system_dict_integration() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// モデルがない場合は、辞書だけで変換する
+#[test]
+fn test_dictionary_only_mode_without_model() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let dict_path = tmpdir.path().join("SKK-JISYO.test");
+    std::fs::write(&dict_path, "がっこう /学校/\n")?;
+
+    let engine = BigramWordViterbiEngineBuilder::new(EngineConfig {
+        dicts: vec![DictConfig {
+            path: dict_path.to_string_lossy().to_string(),
+            encoding: DictEncoding::Utf8,
+            dict_type: DictType::SKK,
+            usage: DictUsage::Normal,
+        }],
+        dict_cache: false,
+        model: tmpdir.path().join("missing").to_string_lossy().to_string(),
+        reranking_weights: Default::default(),
+    })
+    .build()?;
+
+    assert!(engine
+        .model_error()
+        .is_some_and(|err| err.contains("unigram.model")));
+
+    let result = engine.convert("がっこう", None)?;
+    assert_eq!(result[0][0].surface, "学校");
+
+    Ok(())
+}